@@ -0,0 +1,37 @@
+// CAPTCHA widget: insert with <script src="/widget"></script> inside a form
+(function () {
+  var script = document.currentScript;
+  var box = document.createElement("div");
+  box.className = "captcha-widget";
+  box.innerHTML =
+    '<img alt="CAPTCHA">' +
+    '<button type="button" title="New image" aria-label="New image">&#x21bb;</button>' +
+    '<input name="captcha_answer" autocomplete="off" autocapitalize="off" spellcheck="false" required>' +
+    '<input type="hidden" name="captcha_token">';
+  script.parentNode.insertBefore(box, script);
+
+  var image = box.querySelector("img");
+  var answer = box.querySelector("input[name=captcha_answer]");
+  var token = box.querySelector("input[name=captcha_token]");
+
+  function refresh() {
+    fetch(IMAGE_URL, { cache: "no-store", credentials: "same-origin" })
+      .then(function (response) {
+        if (!response.ok) {
+          throw new Error("CAPTCHA image request failed: " + response.status);
+        }
+        token.value = response.headers.get("x-captcha-token") || "";
+        return response.blob();
+      })
+      .then(function (blob) {
+        if (image.src) {
+          URL.revokeObjectURL(image.src);
+        }
+        image.src = URL.createObjectURL(blob);
+        answer.value = "";
+      });
+  }
+
+  box.querySelector("button").addEventListener("click", refresh);
+  refresh();
+})();
@@ -0,0 +1,258 @@
+//! Ready-made [actix-web](https://docs.rs/actix-web) integration
+//!
+//! ```no_run
+//! use actix_web::{web, App, HttpServer};
+//! use captcha_generator::actix::{captcha_route, widget_route, CaptchaState, VerifiedCaptcha};
+//! use captcha_generator::verify::TokenSigner;
+//!
+//! async fn signup(_: VerifiedCaptcha) -> &'static str {
+//!     "welcome, human"
+//! }
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let state = CaptchaState::new(TokenSigner::new(b"server secret key".to_vec()));
+//! HttpServer::new(move || {
+//!     App::new()
+//!         .app_data(web::Data::new(state.clone()))
+//!         .route("/captcha.png", captcha_route(state.clone()))
+//!         .route("/widget", widget_route("/captcha.png"))
+//!         .route("/signup", web::post().to(signup))
+//! })
+//! .bind(("127.0.0.1", 8080))?
+//! .run()
+//! .await
+//! # }
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE, COOKIE};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, Route};
+use serde::de::DeserializeOwned;
+
+use crate::problem::{ApiError, Problem};
+use crate::web::{
+    problem_json, token_cookie, widget_script, RequestFields, ANSWER_HEADER, TOKEN_HEADER,
+    WIDGET_CONTENT_TYPE,
+};
+pub use crate::web::{CaptchaRejection, CaptchaState, IssueError, VerifiedCaptcha};
+
+/// A `GET` route serving a fresh CAPTCHA PNG
+///
+/// The answer token is returned in the `X-Captcha-Token` header and the
+/// `captcha_token` cookie. Rendering runs on actix's blocking thread pool, so
+/// it does not hold up other requests on the same worker.
+pub fn captcha_route(state: CaptchaState) -> Route {
+    web::get().to(move || {
+        let state = state.clone();
+        async move {
+            let issue_state = state.clone();
+            match web::block(move || issue_state.issue()).await {
+                Ok(issued) => serve_captcha(&state, issued),
+                Err(e) => HttpResponse::from_error(e),
+            }
+        }
+    })
+}
+
+/// A `GET` route serving the embeddable widget script
+///
+/// `<script src="/widget"></script>` inside a form inserts an image loaded
+/// from `image_path`, a refresh button, the answer field and a hidden token
+/// field, so submitting the form sends everything [`VerifiedCaptcha`] needs.
+/// The route at `image_path` must return the token of each image in the
+/// `X-Captcha-Token` header, as [`captcha_route`] does.
+pub fn widget_route(image_path: &str) -> Route {
+    let script = widget_script(image_path);
+    web::get().to(move || {
+        let script = script.clone();
+        async move {
+            HttpResponse::Ok()
+                .insert_header((CONTENT_TYPE, WIDGET_CONTENT_TYPE))
+                .insert_header((CACHE_CONTROL, "no-cache"))
+                .body(script)
+        }
+    })
+}
+
+fn serve_captcha(
+    state: &CaptchaState,
+    issued: Result<(Vec<u8>, String), IssueError>,
+) -> HttpResponse {
+    match issued {
+        Ok((png, token)) => HttpResponse::Ok()
+            .insert_header((CONTENT_TYPE, "image/png"))
+            .insert_header((CACHE_CONTROL, "no-store"))
+            .insert_header(("set-cookie", token_cookie(&token, state.secure_cookie)))
+            .insert_header((TOKEN_HEADER, token))
+            .body(png),
+        Err(e) => problem_response(&e),
+    }
+}
+
+/// Reads `CaptchaState` from `web::Data<CaptchaState>` or plain app data
+impl<T> FromRequest for VerifiedCaptcha<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let body = Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            let state = req
+                .app_data::<web::Data<CaptchaState>>()
+                .map(|data| data.get_ref().clone())
+                .or_else(|| req.app_data::<CaptchaState>().cloned())
+                .ok_or_else(|| {
+                    actix_web::error::ErrorInternalServerError("CaptchaState is not registered")
+                })?;
+            let body = body.await?;
+
+            let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+            RequestFields {
+                token_header: header(TOKEN_HEADER),
+                answer_header: header(ANSWER_HEADER),
+                cookie_header: header(COOKIE.as_str()),
+                content_type: header(CONTENT_TYPE.as_str()),
+                body: &body,
+            }
+            .verify(&state)
+            .map_err(Into::into)
+        })
+    }
+}
+
+impl ResponseError for CaptchaRejection {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status()).unwrap_or(StatusCode::BAD_REQUEST)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+/// An `application/problem+json` response for `error`
+fn problem_response<E: ApiError>(error: &E) -> HttpResponse {
+    let status = StatusCode::from_u16(error.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    HttpResponse::build(status)
+        .insert_header((CONTENT_TYPE, Problem::CONTENT_TYPE))
+        .body(problem_json(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::TokenSigner;
+    use actix_web::{test, App};
+
+    async fn protected(_: VerifiedCaptcha) -> &'static str {
+        "ok"
+    }
+
+    #[actix_web::test]
+    async fn test_issue_and_verify() {
+        let state = CaptchaState::new(TokenSigner::new(b"key".to_vec()));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/captcha", captcha_route(state.clone()))
+                .route("/protected", web::post().to(protected)),
+        )
+        .await;
+
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/captcha").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "image/png");
+        assert!(response.headers().contains_key(TOKEN_HEADER));
+        let cookie = response
+            .headers()
+            .get("set-cookie")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(cookie.ends_with("; Secure"), "{}", cookie);
+
+        let token = state.signer.mint("AB23");
+        let request = |answer: &str| {
+            test::TestRequest::post()
+                .uri("/protected")
+                .insert_header((TOKEN_HEADER, token.clone()))
+                .insert_header((ANSWER_HEADER, answer.to_string()))
+                .to_request()
+        };
+        let response = test::call_service(&app, request("ZZZZ")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["code"], "code_mismatch");
+        let response = test::call_service(&app, request("AB23")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The token cannot be replayed, and a third wrong answer to a new one is refused
+        let response = test::call_service(&app, request("AB23")).await;
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["code"], "code_used");
+        let token = state.signer.mint("AB23");
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::BAD_REQUEST,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            let request = test::TestRequest::post()
+                .uri("/protected")
+                .insert_header((TOKEN_HEADER, token.clone()))
+                .insert_header((ANSWER_HEADER, "ZZZZ"))
+                .to_request();
+            assert_eq!(test::call_service(&app, request).await.status(), status);
+        }
+    }
+
+    #[cfg(feature = "store")]
+    #[actix_web::test]
+    async fn test_tenant_quota() {
+        use crate::store::{CaptchaStore, Quota};
+        use std::sync::Arc;
+
+        let store = CaptchaStore::new().with_default_quota(Quota {
+            max_outstanding: Some(1),
+            max_rate: None,
+        });
+        let state = CaptchaState::new(TokenSigner::new(b"key".to_vec()))
+            .with_store(Arc::new(store))
+            .with_tenant("acme");
+        let app = test::init_service(App::new().route("/captcha", captcha_route(state))).await;
+        let request = || test::TestRequest::get().uri("/captcha").to_request();
+
+        let response = test::call_service(&app, request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["code"], "quota_exceeded");
+    }
+
+    #[actix_web::test]
+    async fn test_widget() {
+        let app =
+            test::init_service(App::new().route("/widget", widget_route("/captcha.png"))).await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/widget").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            WIDGET_CONTENT_TYPE
+        );
+        let body = test::read_body(response).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains(r#"fetch("/captcha.png","#));
+    }
+}
@@ -0,0 +1,259 @@
+//! Ready-made [axum](https://docs.rs/axum) integration
+//!
+//! ```no_run
+//! use axum::{routing::post, Router};
+//! use captcha_generator::axum::{captcha_route, widget_route, CaptchaState, VerifiedCaptcha};
+//! use captcha_generator::verify::TokenSigner;
+//!
+//! async fn signup(VerifiedCaptcha(_): VerifiedCaptcha) -> &'static str {
+//!     "welcome, human"
+//! }
+//!
+//! let state = CaptchaState::new(TokenSigner::new(b"server secret key".to_vec()));
+//! let app: Router = Router::new()
+//!     .route("/captcha.png", captcha_route(state.clone()))
+//!     .route("/widget", widget_route("/captcha.png"))
+//!     .route("/signup", post(signup))
+//!     .with_state(state);
+//! ```
+
+use ::axum::body::to_bytes;
+use ::axum::extract::{FromRef, FromRequest, Request};
+use ::axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, COOKIE, SET_COOKIE};
+use ::axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::routing::{get, MethodRouter};
+use serde::de::DeserializeOwned;
+
+use crate::problem::{ApiError, Problem};
+use crate::web::{
+    problem_json, token_cookie, widget_script, RequestFields, ANSWER_HEADER, TOKEN_HEADER,
+    WIDGET_CONTENT_TYPE,
+};
+pub use crate::web::{CaptchaRejection, CaptchaState, IssueError, VerifiedCaptcha};
+
+/// Largest request body the extractor reads
+const MAX_BODY: usize = 64 * 1024;
+
+/// A `GET` route serving a fresh CAPTCHA PNG
+///
+/// The answer token is returned in the `X-Captcha-Token` header and the
+/// `captcha_token` cookie. Rendering runs on Tokio's blocking thread pool, so
+/// it does not hold up other requests on the same worker.
+pub fn captcha_route<S>(state: CaptchaState) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    get(move || {
+        let state = state.clone();
+        async move {
+            let issue_state = state.clone();
+            let issued = tokio::task::spawn_blocking(move || issue_state.issue())
+                .await
+                .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+            serve_captcha(&state, issued)
+        }
+    })
+}
+
+/// A `GET` route serving the embeddable widget script
+///
+/// `<script src="/widget"></script>` inside a form inserts an image loaded
+/// from `image_path`, a refresh button, the answer field and a hidden token
+/// field, so submitting the form sends everything [`VerifiedCaptcha`] needs.
+/// The route at `image_path` must return the token of each image in the
+/// `X-Captcha-Token` header, as [`captcha_route`] does.
+pub fn widget_route<S>(image_path: &str) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let script = widget_script(image_path);
+    get(move || {
+        let script = script.clone();
+        async move {
+            (
+                [
+                    (CONTENT_TYPE, WIDGET_CONTENT_TYPE),
+                    (CACHE_CONTROL, "no-cache"),
+                ],
+                script,
+            )
+        }
+    })
+}
+
+fn serve_captcha(state: &CaptchaState, issued: Result<(Vec<u8>, String), IssueError>) -> Response {
+    let (png, token) = match issued {
+        Ok(issued) => issued,
+        Err(e) => return problem_response(&e),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if let (Ok(header), Ok(cookie)) = (
+        HeaderValue::from_str(&token),
+        HeaderValue::from_str(&token_cookie(&token, state.secure_cookie)),
+    ) {
+        headers.insert(HeaderName::from_static(TOKEN_HEADER), header);
+        headers.insert(SET_COOKIE, cookie);
+    }
+    (headers, png).into_response()
+}
+
+impl<S, T> FromRequest<S> for VerifiedCaptcha<T>
+where
+    CaptchaState: FromRef<S>,
+    S: Send + Sync,
+    T: DeserializeOwned + Send,
+{
+    type Rejection = CaptchaRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let state = CaptchaState::from_ref(state);
+        let (parts, body) = req.into_parts();
+        let body = to_bytes(body, MAX_BODY)
+            .await
+            .map_err(|e| CaptchaRejection::InvalidBody(e.to_string()))?;
+
+        let header = |name| parts.headers.get(name).and_then(|v| v.to_str().ok());
+        RequestFields {
+            token_header: header(TOKEN_HEADER),
+            answer_header: header(ANSWER_HEADER),
+            cookie_header: header(COOKIE.as_str()),
+            content_type: header(CONTENT_TYPE.as_str()),
+            body: &body,
+        }
+        .verify(&state)
+    }
+}
+
+impl IntoResponse for CaptchaRejection {
+    fn into_response(self) -> Response {
+        problem_response(&self)
+    }
+}
+
+/// An `application/problem+json` response for `error`
+fn problem_response<E: ApiError>(error: &E) -> Response {
+    let status = StatusCode::from_u16(error.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (
+        status,
+        [(CONTENT_TYPE, Problem::CONTENT_TYPE)],
+        problem_json(error),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::{TokenLedger, TokenSigner};
+    use ::axum::body::Body;
+    use ::axum::routing::post;
+    use ::axum::Router;
+    use tower::ServiceExt;
+
+    async fn protected(VerifiedCaptcha(_): VerifiedCaptcha) -> &'static str {
+        "ok"
+    }
+
+    #[tokio::test]
+    async fn test_issue_and_verify() {
+        let state = CaptchaState::new(TokenSigner::new(b"key".to_vec()));
+        let app = Router::new()
+            .route("/captcha", captcha_route(state.clone()))
+            .route("/protected", post(protected))
+            .with_state(state.clone());
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/captcha").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+        assert!(response.headers().contains_key(TOKEN_HEADER));
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.ends_with("; Secure"), "{}", cookie);
+
+        let token = state.signer.mint("AB23");
+        let request = |answer: &str| {
+            Request::post("/protected")
+                .header(TOKEN_HEADER, &token)
+                .header(ANSWER_HEADER, answer)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let code = |response: Response| async {
+            let body = to_bytes(response.into_body(), MAX_BODY).await.unwrap();
+            let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            problem["code"].clone()
+        };
+        let response = app.clone().oneshot(request("ZZZZ")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[CONTENT_TYPE], Problem::CONTENT_TYPE);
+        assert_eq!(code(response).await, "code_mismatch");
+        let response = app.clone().oneshot(request("AB23")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The token cannot be replayed
+        let response = app.clone().oneshot(request("AB23")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(code(response).await, "code_used");
+    }
+
+    #[tokio::test]
+    async fn test_too_many_attempts() {
+        let state = CaptchaState::new(TokenSigner::new(b"key".to_vec()))
+            .with_ledger(TokenLedger::new().with_max_attempts(1));
+        let app = Router::new()
+            .route("/protected", post(protected))
+            .with_state(state.clone());
+        let request = Request::post("/protected")
+            .header(TOKEN_HEADER, state.signer.mint("AB23"))
+            .header(ANSWER_HEADER, "ZZZZ")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[cfg(feature = "store")]
+    #[tokio::test]
+    async fn test_tenant_quota() {
+        use crate::store::{CaptchaStore, Quota};
+        use std::sync::Arc;
+
+        let store = CaptchaStore::new().with_default_quota(Quota {
+            max_outstanding: Some(1),
+            max_rate: None,
+        });
+        let state = CaptchaState::new(TokenSigner::new(b"key".to_vec()))
+            .with_store(Arc::new(store))
+            .with_tenant("acme");
+        let app: Router = Router::new().route("/captcha", captcha_route(state));
+        let request = || Request::get("/captcha").body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[CONTENT_TYPE], Problem::CONTENT_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_widget() {
+        let app: Router = Router::new().route("/widget", widget_route("/captcha.png"));
+        let response = app
+            .oneshot(Request::get("/widget").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], WIDGET_CONTENT_TYPE);
+        let body = to_bytes(response.into_body(), MAX_BODY).await.unwrap();
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains(r#"fetch("/captcha.png","#));
+    }
+}
@@ -0,0 +1,204 @@
+//! A content-addressed cache in front of rendering
+//!
+//! A seeded configuration always renders the same code and pixels, so refresh
+//! endpoints and retries that ask for the same challenge again can be served
+//! from memory instead of rasterizing it a second time:
+//!
+//! ```
+//! use captcha_generator::cache::RenderCache;
+//! use captcha_generator::CaptchaConfig;
+//!
+//! let cache = RenderCache::new(256);
+//! let config = CaptchaConfig {
+//!     seed: Some(7),
+//!     ..Default::default()
+//! };
+//! let first = cache.get_or_render(&config).unwrap();
+//! let again = cache.get_or_render(&config).unwrap();
+//! assert!(std::sync::Arc::ptr_eq(&first, &again));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::fingerprint::{write_config, Identity};
+use crate::{Captcha, CaptchaConfig, CaptchaError, Effect};
+
+type Key = [u8; 32];
+
+struct Entry {
+    captcha: Arc<Captcha>,
+    last_used: u64,
+    /// Keeps custom effects alive so their hashed addresses are not reused
+    _pinned: Vec<Effect>,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<Key, Entry>,
+    clock: u64,
+}
+
+/// Rendered CAPTCHAs keyed by a hash of the seed and every configuration value
+///
+/// Only seeded configurations are cached; the code is determined by the seed,
+/// so it is covered by the key. When more than `capacity` renders are held,
+/// the least recently used one is dropped.
+pub struct RenderCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+}
+
+impl RenderCache {
+    /// Create a cache holding at most `capacity` rendered CAPTCHAs
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            capacity,
+        }
+    }
+
+    /// Return the cached render of `config`, rendering and caching it on a miss
+    ///
+    /// Configurations without a `seed` are rendered fresh every time.
+    pub fn get_or_render(&self, config: &CaptchaConfig) -> Result<Arc<Captcha>, CaptchaError> {
+        if config.seed.is_none() || self.capacity == 0 {
+            return Ok(Arc::new(Captcha::try_with_config(config.clone())?));
+        }
+
+        let key = config_key(config);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let now = entries.clock;
+            if let Some(entry) = entries.map.get_mut(&key) {
+                entry.last_used = now;
+                return Ok(entry.captcha.clone());
+            }
+        }
+
+        // Rendered without holding the lock; concurrent misses for one key may both render
+        let captcha = Arc::new(Captcha::try_with_config(config.clone())?);
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.map.len() >= self.capacity && !entries.map.contains_key(&key) {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                entries.map.remove(&oldest);
+            }
+        }
+        let now = entries.clock;
+        entries.map.insert(
+            key,
+            Entry {
+                captcha: captcha.clone(),
+                last_used: now,
+                _pinned: config.effects.clone(),
+            },
+        );
+        Ok(captcha)
+    }
+
+    /// Number of cached renders
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+
+    /// Whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every cached render
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().map.clear();
+    }
+}
+
+impl fmt::Debug for RenderCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderCache")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// Hash every value that influences the render
+///
+/// Font data is identified by content, so configurations loading the same font
+/// separately share renders. Custom effects are identified by address.
+fn config_key(config: &CaptchaConfig) -> Key {
+    let mut hasher = Sha256::new();
+    write_config(&mut hasher, config, Identity::Local);
+    hasher.update(config.seed.unwrap_or_default().to_be_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FontSource;
+
+    fn seeded(seed: u64) -> CaptchaConfig {
+        CaptchaConfig {
+            seed: Some(seed),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let cache = RenderCache::new(4);
+        let a = cache.get_or_render(&seeded(1)).unwrap();
+        assert!(Arc::ptr_eq(&a, &cache.get_or_render(&seeded(1)).unwrap()));
+        assert_eq!(a.code, Captcha::with_config(seeded(1)).code);
+
+        let wider = CaptchaConfig {
+            width: 300,
+            ..seeded(1)
+        };
+        assert!(!Arc::ptr_eq(&a, &cache.get_or_render(&wider).unwrap()));
+        assert_eq!(cache.len(), 2);
+
+        cache.get_or_render(&CaptchaConfig::default()).unwrap();
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = RenderCache::new(2);
+        let first = cache.get_or_render(&seeded(1)).unwrap();
+        cache.get_or_render(&seeded(2)).unwrap();
+        cache.get_or_render(&seeded(1)).unwrap();
+        cache.get_or_render(&seeded(3)).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(
+            &first,
+            &cache.get_or_render(&seeded(1)).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_fonts_are_part_of_the_key() {
+        let font = |data: &[u8]| CaptchaConfig {
+            fonts: vec![FontSource::from_bytes(data)],
+            ..seeded(1)
+        };
+        let data = crate::font::FONT_DATA;
+        // Separately loaded copies of the same font share a key
+        assert_eq!(config_key(&font(data)), config_key(&font(data)));
+        assert_ne!(config_key(&font(data)), config_key(&seeded(1)));
+        assert_ne!(
+            config_key(&font(data)),
+            config_key(&font(&data[..data.len() - 1]))
+        );
+    }
+}
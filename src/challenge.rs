@@ -0,0 +1,376 @@
+//! Self-contained challenges for queue-based architectures
+//!
+//! A [`Challenge`] holds everything needed to check an answer later: a keyed
+//! hash of the code, the seed and a hash of the configuration it was rendered
+//! from, and its expiry. It serializes to a compact, versioned binary form
+//! (see [`wire`](crate::wire)), so one service can generate CAPTCHAs and
+//! another verify them over a message bus, sharing only the [`TokenSigner`] key.
+//! The seed is encrypted under that key, so whoever carries a challenge cannot
+//! render it again to read the code:
+//!
+//! ```
+//! use captcha_generator::challenge::Challenge;
+//! use captcha_generator::verify::TokenSigner;
+//! use captcha_generator::CaptchaConfig;
+//!
+//! let signer = TokenSigner::new(b"shared secret".to_vec());
+//! let (challenge, captcha) = Challenge::issue(&signer, CaptchaConfig::default()).unwrap();
+//! let message = challenge.to_bytes();
+//!
+//! // ...in the verifying service:
+//! let challenge = Challenge::from_bytes(&message).unwrap();
+//! assert!(challenge.verify(&signer, &captcha.code).is_ok());
+//! ```
+//!
+//! [`Challenge::verify`] is stateless, so a correct answer can be replayed
+//! until the challenge expires. [`Challenge::verify_once`] checks the nonce
+//! against a [`TokenLedger`] to accept each challenge only once.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::fingerprint::{write_config, Identity};
+use crate::verify::{TokenLedger, TokenSigner, VerifyError};
+use crate::wire::{self, Fields, Kind};
+use crate::{Captcha, CaptchaConfig, CaptchaError};
+
+/// Length of the challenge fields after the header
+const FIELDS_LEN: usize = 16 + 32 + 8 + 32 + 8;
+
+/// An issued CAPTCHA, without its code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Challenge {
+    /// Random per challenge; [`Challenge::verify_once`] uses it to reject replays
+    pub nonce: [u8; 16],
+    /// The signer's keyed hash of the expiry, nonce and normalized code
+    pub answer_hash: [u8; 32],
+    /// The seed the CAPTCHA was rendered with, encrypted; see [`Challenge::seed`]
+    pub sealed_seed: [u8; 8],
+    /// SHA-256 of the configuration without its seed, see [`config_hash`]
+    pub config_hash: [u8; 32],
+    /// Unix time in seconds at which the challenge expires
+    pub expires_at: u64,
+}
+
+impl Challenge {
+    /// Render a CAPTCHA from `config` with a fresh random seed and describe it
+    ///
+    /// The challenge expires after the signer's TTL by its clock, and answers are compared
+    /// with its case sensitivity.
+    pub fn issue(
+        signer: &TokenSigner,
+        mut config: CaptchaConfig,
+    ) -> Result<(Self, Captcha), CaptchaError> {
+        let seed = OsRng.next_u64();
+        config.seed = Some(seed);
+        let captcha = Captcha::try_with_config(config.clone())?;
+        let mut nonce = [0u8; 16];
+        OsRng.fill_bytes(&mut nonce);
+        let expires_at = signer.unix_now().saturating_add(signer.ttl().as_secs());
+        let answer_hash = signer.tag(&expires_at.to_be_bytes(), &nonce, &captcha.code);
+        let challenge = Self {
+            nonce,
+            answer_hash,
+            sealed_seed: xor(seed.to_be_bytes(), signer.seed_pad(&nonce)),
+            config_hash: config_hash(&config),
+            expires_at,
+        };
+        Ok((challenge, captcha))
+    }
+
+    /// Check `input` against the challenge's code
+    ///
+    /// `signer` must use the key the challenge was issued with, and its clock
+    /// decides whether the challenge has expired. The
+    /// comparison runs in constant time.
+    pub fn verify(&self, signer: &TokenSigner, input: &str) -> Result<(), VerifyError> {
+        if signer.unix_now() >= self.expires_at {
+            return Err(VerifyError::Expired);
+        }
+        self.check(signer, input)
+    }
+
+    /// Like [`Challenge::verify`], but accept each challenge only once
+    ///
+    /// As with [`TokenSigner::verify_token_once`], `ledger` remembers the
+    /// nonce until the challenge expires: afterwards a correctly answered
+    /// challenge is [`VerifyError::Used`], and one that had the ledger's
+    /// maximum of wrong answers is [`VerifyError::TooManyAttempts`].
+    pub fn verify_once(
+        &self,
+        signer: &TokenSigner,
+        input: &str,
+        ledger: &TokenLedger,
+    ) -> Result<(), VerifyError> {
+        let now = signer.unix_now();
+        if now >= self.expires_at {
+            return Err(VerifyError::Expired);
+        }
+        // Forged challenges can claim any expiry, but are never kept longer than real ones
+        let expires_at = self
+            .expires_at
+            .min(now.saturating_add(signer.ttl().as_secs()));
+        ledger.check(self.nonce, expires_at, now, || self.check(signer, input))
+    }
+
+    fn check(&self, signer: &TokenSigner, input: &str) -> Result<(), VerifyError> {
+        if signer.check(
+            &self.expires_at.to_be_bytes(),
+            &self.nonce,
+            input,
+            &self.answer_hash,
+        ) {
+            Ok(())
+        } else {
+            Err(VerifyError::Mismatch)
+        }
+    }
+
+    /// The seed the CAPTCHA was rendered with, decrypted with `signer`'s key
+    ///
+    /// Together with the configuration it renders the same CAPTCHA again.
+    /// With any other key the result is an unrelated seed.
+    pub fn seed(&self, signer: &TokenSigner) -> u64 {
+        u64::from_be_bytes(xor(self.sealed_seed, signer.seed_pad(&self.nonce)))
+    }
+
+    /// Whether the challenge was rendered from `config`, ignoring its seed
+    pub fn matches_config(&self, config: &CaptchaConfig) -> bool {
+        self.config_hash == config_hash(config)
+    }
+
+    /// The challenge as a version 1 [`wire`] challenge, 99 bytes long
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(wire::HEADER_LEN + FIELDS_LEN);
+        bytes.extend_from_slice(&wire::header(Kind::Challenge));
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.answer_hash);
+        bytes.extend_from_slice(&self.sealed_seed);
+        bytes.extend_from_slice(&self.config_hash);
+        bytes.extend_from_slice(&self.expires_at.to_be_bytes());
+        bytes
+    }
+
+    /// Decode a challenge written by [`Challenge::to_bytes`] of this or any later version
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VerifyError> {
+        let mut fields = Fields(wire::body(bytes, Kind::Challenge, FIELDS_LEN)?);
+        Ok(Self {
+            nonce: fields.array(),
+            answer_hash: fields.array(),
+            sealed_seed: fields.array(),
+            config_hash: fields.array(),
+            expires_at: u64::from_be_bytes(fields.array()),
+        })
+    }
+
+    /// [`Challenge::to_bytes`] as unpadded URL-safe base64, for text-only queues
+    pub fn to_base64(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.to_bytes())
+    }
+
+    /// Decode a challenge written by [`Challenge::to_base64`]
+    pub fn from_base64(encoded: &str) -> Result<Self, VerifyError> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded.trim())
+            .map_err(|_| VerifyError::Malformed)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+fn xor(a: [u8; 8], b: [u8; 8]) -> [u8; 8] {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+/// SHA-256 over a stable encoding of every configuration value except the seed
+///
+/// Fonts held in memory are hashed by content and font files by file name, so
+/// services that install the same font in different directories agree on the
+/// hash. Custom effects only contribute their position.
+pub fn config_hash(config: &CaptchaConfig) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    write_config(&mut hasher, config, Identity::Portable);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::FontSource;
+
+    fn signer() -> TokenSigner {
+        TokenSigner::new(b"test key".to_vec())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (challenge, captcha) = Challenge::issue(&signer(), CaptchaConfig::default()).unwrap();
+        let bytes = challenge.to_bytes();
+        assert_eq!(bytes.len(), 99);
+
+        let decoded = Challenge::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, challenge);
+        assert_eq!(
+            decoded.verify(&signer(), &captcha.code.to_lowercase()),
+            Ok(())
+        );
+        assert_eq!(
+            decoded.verify(&signer(), "wrong"),
+            Err(VerifyError::Mismatch)
+        );
+        assert_eq!(
+            decoded.verify(&TokenSigner::new(b"other key".to_vec()), &captcha.code),
+            Err(VerifyError::Mismatch)
+        );
+        assert_eq!(
+            Challenge::from_bytes(&bytes[..40]),
+            Err(VerifyError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_verify_once() {
+        let signer = signer();
+        let ledger = TokenLedger::new().with_max_attempts(2);
+        let (challenge, captcha) = Challenge::issue(&signer, CaptchaConfig::default()).unwrap();
+        assert_eq!(
+            challenge.verify_once(&signer, "wrong", &ledger),
+            Err(VerifyError::Mismatch)
+        );
+        assert_eq!(
+            challenge.verify_once(&signer, &captcha.code, &ledger),
+            Ok(())
+        );
+        assert_eq!(
+            challenge.verify_once(&signer, &captcha.code, &ledger),
+            Err(VerifyError::Used)
+        );
+
+        let (challenge, captcha) = Challenge::issue(&signer, CaptchaConfig::default()).unwrap();
+        for expected in [VerifyError::Mismatch, VerifyError::TooManyAttempts] {
+            assert_eq!(
+                challenge.verify_once(&signer, "wrong", &ledger),
+                Err(expected)
+            );
+        }
+        assert_eq!(
+            challenge.verify_once(&signer, &captcha.code, &ledger),
+            Err(VerifyError::TooManyAttempts)
+        );
+    }
+
+    #[test]
+    fn test_wire_formats() {
+        let (challenge, _) = Challenge::issue(&signer(), CaptchaConfig::default()).unwrap();
+        let bytes = challenge.to_bytes();
+        assert_eq!(
+            bytes[..3],
+            [wire::MAGIC, Kind::Challenge as u8, wire::VERSION]
+        );
+        assert_eq!(bytes[51..59], challenge.sealed_seed);
+
+        // Fields appended by a later version are ignored
+        let mut newer = bytes.clone();
+        newer[2] = wire::VERSION + 1;
+        newer.extend_from_slice(&[0; 12]);
+        assert_eq!(Challenge::from_bytes(&newer), Ok(challenge.clone()));
+
+        assert_eq!(
+            Challenge::from_bytes(&bytes[wire::HEADER_LEN..]),
+            Err(VerifyError::Malformed)
+        );
+
+        assert_eq!(
+            Challenge::from_base64(&challenge.to_base64()),
+            Ok(challenge)
+        );
+        assert_eq!(Challenge::from_base64("!!"), Err(VerifyError::Malformed));
+    }
+
+    #[test]
+    fn test_seed_and_config_reproduce_the_captcha() {
+        let config = CaptchaConfig {
+            width: 200,
+            ..Default::default()
+        };
+        let (challenge, captcha) = Challenge::issue(&signer(), config.clone()).unwrap();
+        assert!(challenge.matches_config(&config));
+        assert!(!challenge.matches_config(&CaptchaConfig::default()));
+
+        let render = |seed| {
+            Captcha::with_config(CaptchaConfig {
+                seed: Some(seed),
+                ..config.clone()
+            })
+        };
+        assert_eq!(render(challenge.seed(&signer())).code, captcha.code);
+    }
+
+    #[test]
+    fn test_seed_is_encrypted() {
+        let (challenge, _) = Challenge::issue(&signer(), CaptchaConfig::default()).unwrap();
+        let seed = challenge.seed(&signer());
+        let bytes = challenge.to_bytes();
+        assert!(!bytes.windows(8).any(|w| w == seed.to_be_bytes()));
+        assert!(!bytes.windows(8).any(|w| w == seed.to_le_bytes()));
+        assert_ne!(
+            challenge.seed(&TokenSigner::new(b"other key".to_vec())),
+            seed
+        );
+    }
+
+    #[test]
+    fn test_expired() {
+        let signer = signer().with_ttl(Duration::ZERO);
+        let (challenge, captcha) = Challenge::issue(&signer, CaptchaConfig::default()).unwrap();
+        assert_eq!(
+            challenge.verify(&signer, &captcha.code),
+            Err(VerifyError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_config_hash_uses_font_content() {
+        let font = || CaptchaConfig {
+            fonts: vec![FontSource::from_bytes(crate::font::FONT_DATA)],
+            ..Default::default()
+        };
+        assert_eq!(config_hash(&font()), config_hash(&font()));
+        assert_ne!(config_hash(&font()), config_hash(&CaptchaConfig::default()));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_config_hash_ignores_font_directories() {
+        let file = |path: &str| CaptchaConfig {
+            fonts: vec![FontSource::from_file(path)],
+            ..Default::default()
+        };
+        assert_eq!(
+            config_hash(&file("/usr/share/fonts/Inter.ttf")),
+            config_hash(&file("/opt/app/fonts/Inter.ttf"))
+        );
+        assert_ne!(
+            config_hash(&file("/opt/app/fonts/Inter.ttf")),
+            config_hash(&file("/opt/app/fonts/Roboto.ttf"))
+        );
+    }
+
+    #[test]
+    fn test_config_hash_is_stable() {
+        // Services of different releases must agree; change this only with the encoding version
+        let hash: String = config_hash(&CaptchaConfig::default())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(
+            hash,
+            "8fee63688a2bc95fe4577338ba5eeddb5ce0eb980832739a4dd6f8c5dfe1fdb3"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Character set presets built from a table of confusable characters
+//!
+//! Each preset starts from a base alphabet and drops both characters of every
+//! confusable pair that applies to its audience, as long as both characters of
+//! the pair are in the alphabet. A digits-only preset therefore keeps `0` and
+//! `1`, since there is no `O` or `I` to mistake them for.
+
+/// Who a confusable pair is a problem for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strictness {
+    /// Confused by most readers, excluded from every preset
+    General,
+    /// Confused by readers with low vision, excluded from accessibility presets
+    LowVision,
+}
+
+/// Pairs of characters that are easily mistaken for each other
+pub const CONFUSABLE_PAIRS: &[(char, char, Strictness)] = &[
+    ('0', 'O', Strictness::General),
+    ('1', 'I', Strictness::General),
+    ('1', 'l', Strictness::General),
+    ('I', 'l', Strictness::General),
+    ('0', 'o', Strictness::General),
+    ('O', 'o', Strictness::General),
+    ('C', 'c', Strictness::General),
+    ('S', 's', Strictness::General),
+    ('V', 'v', Strictness::General),
+    ('W', 'w', Strictness::General),
+    ('X', 'x', Strictness::General),
+    ('Z', 'z', Strictness::General),
+    ('8', 'B', Strictness::LowVision),
+    ('6', 'G', Strictness::LowVision),
+    ('5', 'S', Strictness::LowVision),
+    ('2', 'Z', Strictness::LowVision),
+    ('U', 'V', Strictness::LowVision),
+    ('Q', 'O', Strictness::LowVision),
+    ('D', '0', Strictness::LowVision),
+    ('M', 'N', Strictness::LowVision),
+    ('9', 'g', Strictness::LowVision),
+];
+
+const DIGITS: &str = "0123456789";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+
+/// Named character set presets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CharsetPreset {
+    /// Digits and uppercase letters without general confusables
+    #[default]
+    Default,
+    /// Digits and uppercase letters, also avoiding pairs such as B/8 and G/6
+    Elderly,
+    /// Digits only, for numeric keypads
+    Numeric,
+    /// Uppercase letters only
+    Letters,
+    /// Digits and both letter cases without general confusables
+    MixedCase,
+}
+
+impl CharsetPreset {
+    /// Every preset
+    pub const ALL: &'static [CharsetPreset] = &[
+        CharsetPreset::Default,
+        CharsetPreset::Elderly,
+        CharsetPreset::Numeric,
+        CharsetPreset::Letters,
+        CharsetPreset::MixedCase,
+    ];
+
+    /// Look up a preset by its name, e.g. `"elderly"`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// The preset's name
+    pub fn name(&self) -> &'static str {
+        match self {
+            CharsetPreset::Default => "default",
+            CharsetPreset::Elderly => "elderly",
+            CharsetPreset::Numeric => "numeric",
+            CharsetPreset::Letters => "letters",
+            CharsetPreset::MixedCase => "mixed-case",
+        }
+    }
+
+    /// The characters codes are drawn from
+    pub fn chars(&self) -> String {
+        let (base, strictness) = match self {
+            CharsetPreset::Default => (format!("{}{}", DIGITS, UPPERCASE), Strictness::General),
+            CharsetPreset::Elderly => (format!("{}{}", DIGITS, UPPERCASE), Strictness::LowVision),
+            CharsetPreset::Numeric => (DIGITS.to_string(), Strictness::General),
+            CharsetPreset::Letters => (UPPERCASE.to_string(), Strictness::General),
+            CharsetPreset::MixedCase => (
+                format!("{}{}{}", DIGITS, UPPERCASE, LOWERCASE),
+                Strictness::General,
+            ),
+        };
+        without_confusables(&base, strictness)
+    }
+}
+
+/// Remove both characters of every pair up to `strictness` whose characters are both in `base`
+pub fn without_confusables(base: &str, strictness: Strictness) -> String {
+    let excluded: Vec<char> = CONFUSABLE_PAIRS
+        .iter()
+        .filter(|(a, b, s)| *s <= strictness && base.contains(*a) && base.contains(*b))
+        .flat_map(|(a, b, _)| [*a, *b])
+        .collect();
+    base.chars().filter(|c| !excluded.contains(c)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_readable_charset() {
+        assert_eq!(
+            CharsetPreset::Default.chars(),
+            "23456789ABCDEFGHJKLMNPQRSTUVWXYZ"
+        );
+    }
+
+    #[test]
+    fn test_elderly_drops_low_vision_pairs() {
+        let chars = CharsetPreset::Elderly.chars();
+        for c in ['B', '8', 'G', '6', '0', 'O', '1', 'I'] {
+            assert!(!chars.contains(c), "{} should be excluded", c);
+        }
+        assert!(chars.contains('A'));
+    }
+
+    #[test]
+    fn test_numeric_keeps_all_digits() {
+        assert_eq!(CharsetPreset::Numeric.chars(), "0123456789");
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            CharsetPreset::from_name("Elderly"),
+            Some(CharsetPreset::Elderly)
+        );
+        assert_eq!(
+            CharsetPreset::from_name("mixed-case"),
+            Some(CharsetPreset::MixedCase)
+        );
+        assert_eq!(CharsetPreset::from_name("klingon"), None);
+        for preset in CharsetPreset::ALL {
+            assert_eq!(CharsetPreset::from_name(preset.name()), Some(*preset));
+        }
+    }
+}
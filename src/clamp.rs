@@ -0,0 +1,319 @@
+use std::f32::consts::PI;
+use std::fmt;
+
+use crate::{Captcha, CaptchaConfig, CaptchaError, CharsetPreset, Effect, FontSource, Spacing};
+
+/// Largest accepted width and height in pixels
+const MAX_DIMENSION: u32 = 1024;
+/// Largest accepted image area in pixels, e.g. 1024×256 or 512×512
+const MAX_PIXELS: u32 = 1024 * 256;
+/// Largest accepted font size in pixels
+const MAX_FONT_SIZE: f32 = 128.0;
+/// Longest accepted code
+const MAX_CODE_LENGTH: usize = 16;
+/// Most interference lines drawn
+const MAX_LINES: usize = 16;
+/// Most noise dots drawn
+const MAX_NOISE_DOTS: usize = 2000;
+/// Largest distortion amplitude in pixels for the wave and ripple
+const MAX_AMPLITUDE: f32 = 32.0;
+/// Most effects applied
+const MAX_EFFECTS: usize = 8;
+
+/// A configuration value that was out of range and has been replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adjustment {
+    /// The configuration field, e.g. `"width"` or `"effects[1]"`
+    pub field: String,
+    /// The original value, formatted with `Debug`
+    pub from: String,
+    /// The value used instead, formatted with `Debug`
+    pub to: String,
+}
+
+impl fmt::Display for Adjustment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.from, self.to)
+    }
+}
+
+/// Collects an [`Adjustment`] for every value it changes
+#[derive(Default)]
+struct Clamper {
+    adjustments: Vec<Adjustment>,
+}
+
+impl Clamper {
+    /// Replace `value` with `clamp(value)`, recording the change if there is one
+    fn set<T, F>(&mut self, field: &str, value: &mut T, clamp: F)
+    where
+        T: PartialEq + fmt::Debug,
+        F: FnOnce(&T) -> T,
+    {
+        let to = clamp(value);
+        if *value != to {
+            self.adjustments.push(Adjustment {
+                field: field.to_string(),
+                from: format!("{:?}", value),
+                to: format!("{:?}", to),
+            });
+            *value = to;
+        }
+    }
+}
+
+/// `value` limited to `min..=max`, or `fallback` if it is NaN or infinite
+fn finite(value: f32, min: f32, max: f32, fallback: f32) -> f32 {
+    if value.is_finite() {
+        value.clamp(min, max)
+    } else {
+        fallback
+    }
+}
+
+impl CaptchaConfig {
+    /// Replace every out-of-range value with the nearest sane one
+    ///
+    /// Meant for configurations supplied by tenants or users, which must never
+    /// make generation fail or panic. Sizes, counts and distortions are limited
+    /// to what real CAPTCHAs use, at most 1024 pixels a side and 1024×256 in
+    /// area, 16 characters, a 128 pixel font no taller than the image and 8
+    /// effects, so even the costliest render takes around a tenth of a second.
+    /// Empty or reversed ranges are widened, NaN or infinite values fall back
+    /// to the defaults, and fonts read from files or installed fonts are
+    /// removed, since they could name any file the process can read. Returns
+    /// what was changed, in field order.
+    pub fn clamp(&mut self) -> Vec<Adjustment> {
+        let defaults = CaptchaConfig::default();
+        let mut c = Clamper::default();
+
+        c.set("width", &mut self.width, |w| (*w).clamp(16, MAX_DIMENSION));
+        let max_height = (MAX_PIXELS / self.width).min(MAX_DIMENSION);
+        c.set("height", &mut self.height, |h| (*h).clamp(16, max_height));
+        c.set("code_length", &mut self.code_length, |len| {
+            (*len).clamp(1, MAX_CODE_LENGTH)
+        });
+        c.set("charset", &mut self.charset, |charset| {
+            let charset: String = charset.chars().filter(|c| !c.is_control()).collect();
+            if charset.trim().is_empty() {
+                CharsetPreset::Default.chars()
+            } else {
+                charset
+            }
+        });
+
+        let max_font = (self.height as f32).min(MAX_FONT_SIZE);
+        c.set("font_size", &mut self.font_size, |size| {
+            finite(*size, 4.0, max_font, defaults.font_size.min(max_font))
+        });
+        let font_size = self.font_size;
+        c.set("spacing", &mut self.spacing, |spacing| match *spacing {
+            Spacing::Advance(extra) => Spacing::Advance(finite(extra, -font_size, font_size, 0.0)),
+            Spacing::Optical(gap) => Spacing::Optical(finite(gap, -font_size, font_size, 0.0)),
+        });
+        let max_margin = self.width.min(self.height) as f32 / 2.0;
+        c.set("alignment.margin", &mut self.alignment.margin, |margin| {
+            finite(*margin, 0.0, max_margin, 0.0)
+        });
+
+        c.set(
+            "interference_lines",
+            &mut self.interference_lines,
+            |&(min, max)| {
+                let min = min.min(MAX_LINES);
+                (min, max.clamp(min + 1, MAX_LINES + 1))
+            },
+        );
+        c.set("noise_dots", &mut self.noise_dots, |dots| {
+            (*dots).min(MAX_NOISE_DOTS)
+        });
+        c.set("wave_amplitude", &mut self.wave_amplitude, |&(min, max)| {
+            let min = finite(min, 0.0, MAX_AMPLITUDE, defaults.wave_amplitude.0);
+            let max = finite(max, 0.0, MAX_AMPLITUDE, defaults.wave_amplitude.1);
+            // Sampling needs a non-empty range
+            (min, max.max(min + 0.01))
+        });
+        c.set("rotation", &mut self.rotation, |rotation| {
+            finite(*rotation, 0.0, PI, defaults.rotation)
+        });
+        let (max_x, max_y) = (self.width as f32 / 4.0, self.height as f32 / 4.0);
+        c.set("jitter", &mut self.jitter, |&(x, y)| {
+            (finite(x, 0.0, max_x, 0.0), finite(y, 0.0, max_y, 0.0))
+        });
+
+        if self.effects.len() > MAX_EFFECTS {
+            c.adjustments.push(Adjustment {
+                field: "effects".to_string(),
+                from: format!("{} effects", self.effects.len()),
+                to: format!("{} effects", MAX_EFFECTS),
+            });
+            self.effects.truncate(MAX_EFFECTS);
+        }
+        for (i, effect) in self.effects.iter_mut().enumerate() {
+            let clamped = match effect {
+                Effect::Shear(max) => Effect::Shear(finite(*max, 0.0, 1.0, 0.0)),
+                Effect::Overlap(pixels) => {
+                    Effect::Overlap(finite(*pixels, -font_size, font_size, 0.0))
+                }
+                Effect::Outline(thickness) => Effect::Outline(finite(*thickness, 1.0, 8.0, 1.0)),
+                Effect::Ripple {
+                    amplitude,
+                    wavelength,
+                } => Effect::Ripple {
+                    amplitude: finite(*amplitude, 0.0, MAX_AMPLITUDE, 0.0),
+                    wavelength: finite(*wavelength, 1.0, MAX_DIMENSION as f32, 1.0),
+                },
+                Effect::Custom(_) => continue,
+            };
+            // Effects are not `PartialEq`, so compare their printed parameters
+            if format!("{:?}", effect) != format!("{:?}", clamped) {
+                c.adjustments.push(Adjustment {
+                    field: format!("effects[{}]", i),
+                    from: format!("{:?}", effect),
+                    to: format!("{:?}", clamped),
+                });
+                *effect = clamped;
+            }
+        }
+
+        let fonts: Vec<_> = self
+            .fonts
+            .iter()
+            .filter(|font| matches!(font, FontSource::Embedded | FontSource::Bytes(_)))
+            .cloned()
+            .collect();
+        if fonts.len() != self.fonts.len() {
+            c.adjustments.push(Adjustment {
+                field: "fonts".to_string(),
+                from: format!("{:?}", self.fonts),
+                to: format!("{:?}", fonts),
+            });
+            self.fonts = fonts;
+        }
+
+        if let Some(quantization) = &mut self.quantization {
+            c.set("quantization.colors", &mut quantization.colors, |colors| {
+                (*colors).clamp(1, 256)
+            });
+        }
+
+        c.adjustments
+    }
+}
+
+impl Captcha {
+    /// Generate a CAPTCHA from a clamped copy of `config`, reporting what was clamped
+    ///
+    /// Fonts that cannot be loaded are replaced by the embedded font (reported
+    /// as a `"fonts"` adjustment), so only encoding can still fail.
+    pub fn with_config_clamped(
+        mut config: CaptchaConfig,
+    ) -> Result<(Self, Vec<Adjustment>), CaptchaError> {
+        let mut adjustments = config.clamp();
+        if crate::font::load_fonts(&config.fonts).is_err() {
+            adjustments.push(Adjustment {
+                field: "fonts".to_string(),
+                from: format!("{:?}", config.fonts),
+                to: "[]".to_string(),
+            });
+            config.fonts.clear();
+        }
+        Ok((Self::try_with_config(config)?, adjustments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_is_untouched() {
+        assert!(CaptchaConfig::default().clamp().is_empty());
+        for difficulty in [
+            crate::Difficulty::Easy,
+            crate::Difficulty::Hard,
+            crate::Difficulty::Extreme,
+        ] {
+            assert!(CaptchaConfig::with_difficulty(difficulty)
+                .clamp()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn test_hostile_config_still_renders() {
+        let config = CaptchaConfig {
+            width: 0,
+            height: 100_000,
+            code_length: 0,
+            charset: String::new(),
+            font_size: f32::NAN,
+            interference_lines: (9, 3),
+            noise_dots: usize::MAX,
+            wave_amplitude: (2.0, 2.0),
+            rotation: f32::INFINITY,
+            jitter: (-5.0, 1e9),
+            effects: vec![Effect::Outline(-3.0), Effect::Shear(0.1)],
+            fonts: vec![FontSource::from_bytes(vec![0u8; 4])],
+            ..Default::default()
+        };
+        let (captcha, adjustments) = Captcha::with_config_clamped(config).unwrap();
+        assert_eq!(captcha.image.dimensions(), (16, MAX_DIMENSION));
+        assert_eq!(captcha.code.len(), 1);
+
+        let fields: Vec<_> = adjustments.iter().map(|a| a.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "width",
+                "height",
+                "code_length",
+                "charset",
+                "font_size",
+                "interference_lines",
+                "noise_dots",
+                "wave_amplitude",
+                "rotation",
+                "jitter",
+                "effects[0]",
+                "fonts",
+            ]
+        );
+        assert_eq!(adjustments[0].to_string(), "width: 0 -> 16");
+    }
+
+    #[test]
+    fn test_large_config_is_capped() {
+        let mut config = CaptchaConfig {
+            width: 8192,
+            height: 8192,
+            code_length: 64,
+            font_size: 16_000.0,
+            noise_dots: 16_000_000,
+            effects: vec![Effect::Outline(2.0); 100],
+            ..Default::default()
+        };
+        config.clamp();
+        assert_eq!((config.width, config.height), (MAX_DIMENSION, 256));
+        assert_eq!(config.code_length, MAX_CODE_LENGTH);
+        assert_eq!(config.font_size, MAX_FONT_SIZE);
+        assert_eq!(config.noise_dots, MAX_NOISE_DOTS);
+        assert_eq!(config.effects.len(), MAX_EFFECTS);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_font_files_are_removed() {
+        let mut config = CaptchaConfig {
+            fonts: vec![
+                FontSource::from_file("/dev/zero"),
+                FontSource::from_bytes(crate::font::FONT_DATA),
+            ],
+            ..Default::default()
+        };
+        let adjustments = config.clamp();
+        assert_eq!(adjustments.len(), 1);
+        assert_eq!(adjustments[0].field, "fonts");
+        assert!(matches!(config.fonts[..], [FontSource::Bytes(_)]));
+    }
+}
@@ -0,0 +1,121 @@
+//! The time source for expiry
+//!
+//! Token TTLs, challenge expiry and the store's TTLs and rate windows all read
+//! the time from a [`Clock`]. [`SystemClock`] is the default; tests can share
+//! a [`ManualClock`] with the code under test and move it forward instead of
+//! sleeping:
+//!
+//! ```
+//! # #[cfg(feature = "tokens")] {
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use captcha_generator::clock::ManualClock;
+//! use captcha_generator::verify::{TokenSigner, VerifyError};
+//!
+//! let clock = Arc::new(ManualClock::new(Duration::from_secs(1_700_000_000)));
+//! let signer = TokenSigner::new(b"key".to_vec()).with_clock(clock.clone());
+//! let token = signer.mint("AB23CD");
+//!
+//! clock.advance(Duration::from_secs(301));
+//! assert_eq!(signer.verify_token(&token, "AB23CD"), Err(VerifyError::Expired));
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A source of the current time
+pub trait Clock: Send + Sync {
+    /// Time elapsed since the Unix epoch
+    fn now(&self) -> Duration;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+/// The system's wall clock
+///
+/// On `wasm32-unknown-unknown` with the `wasm` feature it reads JavaScript's
+/// `Date.now()`, as the standard library has no clock there.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    fn now(&self) -> Duration {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    fn now(&self) -> Duration {
+        Duration::from_secs_f64(js::date_now().max(0.0) / 1000.0)
+    }
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod js {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = Date, js_name = now)]
+        pub fn date_now() -> f64;
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    /// Create a clock showing `now` since the Unix epoch
+    pub fn new(now: Duration) -> Self {
+        Self {
+            nanos: AtomicU64::new(now.as_nanos() as u64),
+        }
+    }
+
+    /// Move the clock forward by `by`
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// Set the clock to `now` since the Unix epoch
+    pub fn set(&self, now: Duration) {
+        self.nanos.store(now.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(10)));
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(shared.now(), Duration::from_millis(11_500));
+        clock.set(Duration::ZERO);
+        assert_eq!(shared.now(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_system_clock_is_after_2020() {
+        assert!(SystemClock.now() > Duration::from_secs(1_577_836_800));
+    }
+}
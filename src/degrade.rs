@@ -0,0 +1,191 @@
+//! Switching to cheaper CAPTCHAs under load
+//!
+//! When a load-shedding signal fires, a [`Degrader`] hands out CAPTCHAs from a
+//! cheaper configuration instead of letting requests queue behind expensive
+//! renders, and counts how many were degraded:
+//!
+//! ```
+//! use captcha_generator::degrade::Degrader;
+//! use captcha_generator::CaptchaConfig;
+//!
+//! let degrader = Degrader::new(CaptchaConfig::hard());
+//! degrader.set_degraded(true); // e.g. when the request queue grows too long
+//! let captcha = degrader.generate().unwrap();
+//! degrader.set_degraded(false);
+//! assert_eq!(degrader.stats().degraded, 1);
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{Captcha, CaptchaConfig, CaptchaError, Effect};
+
+/// Steps of `CaptchaConfig::step_down` that keep the canvas size
+const CHEAP_STEPS: usize = 3;
+
+impl CaptchaConfig {
+    /// A cheaper version of the configuration with the same size and look
+    ///
+    /// Noise dots are cut to a quarter and ripple effects and extra
+    /// interference lines dropped; fonts, colors, canvas and code are kept.
+    pub fn cheap_profile(&self) -> CaptchaConfig {
+        let mut config = self.clone();
+        for step in 0..CHEAP_STEPS {
+            config.step_down(self, step);
+        }
+        config
+    }
+
+    /// Apply cost reduction `step` relative to `original`, or return `false` when there are no more
+    ///
+    /// Noise dots are halved, ripple effects and extra interference lines
+    /// dropped, noise dots halved again, and then the canvas and font shrunk
+    /// by 15% per step, to about half the size.
+    pub(crate) fn step_down(&mut self, original: &CaptchaConfig, step: usize) -> bool {
+        /// Factor the canvas shrinks by in each of the last steps
+        const SHRINK: f32 = 0.85;
+        /// Canvas reductions, down to about half the size
+        const MAX_SHRINKS: usize = 4;
+
+        match step {
+            0 => self.noise_dots = original.noise_dots / 2,
+            1 => {
+                self.effects
+                    .retain(|effect| !matches!(effect, Effect::Ripple { .. }));
+                let min = self.interference_lines.0;
+                self.interference_lines = (min, min + 1);
+            }
+            2 => self.noise_dots = original.noise_dots / 4,
+            step if step < CHEAP_STEPS + MAX_SHRINKS => {
+                let factor = SHRINK.powi((step + 1 - CHEAP_STEPS) as i32);
+                self.width = ((original.width as f32 * factor) as u32).max(16);
+                self.height = ((original.height as f32 * factor) as u32).max(16);
+                self.font_size = original.font_size * factor;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// How many CAPTCHAs a [`Degrader`] generated in each mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DegradationStats {
+    /// Generated from the full configuration
+    pub normal: u64,
+    /// Generated from the cheap configuration
+    pub degraded: u64,
+    /// Times degraded mode was switched on
+    pub activations: u64,
+}
+
+/// Generates from a full or a cheap configuration, switchable at any time
+#[derive(Debug)]
+pub struct Degrader {
+    normal: CaptchaConfig,
+    cheap: CaptchaConfig,
+    degraded: AtomicBool,
+    normal_count: AtomicU64,
+    degraded_count: AtomicU64,
+    activations: AtomicU64,
+}
+
+impl Degrader {
+    /// Generate from `config`, or from its [`cheap_profile`](CaptchaConfig::cheap_profile) while degraded
+    pub fn new(config: CaptchaConfig) -> Self {
+        Self {
+            cheap: config.cheap_profile(),
+            normal: config,
+            degraded: AtomicBool::new(false),
+            normal_count: AtomicU64::new(0),
+            degraded_count: AtomicU64::new(0),
+            activations: AtomicU64::new(0),
+        }
+    }
+
+    /// Use `cheap` while degraded instead of the derived profile
+    pub fn with_cheap_config(mut self, cheap: CaptchaConfig) -> Self {
+        self.cheap = cheap;
+        self
+    }
+
+    /// Switch degraded mode on or off
+    pub fn set_degraded(&self, degraded: bool) {
+        let was = self.degraded.swap(degraded, Ordering::Relaxed);
+        if degraded && !was {
+            self.activations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether CAPTCHAs are currently generated from the cheap configuration
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// The configuration the next CAPTCHA is generated from
+    pub fn config(&self) -> &CaptchaConfig {
+        if self.is_degraded() {
+            &self.cheap
+        } else {
+            &self.normal
+        }
+    }
+
+    /// Generate a CAPTCHA in the current mode
+    pub fn generate(&self) -> Result<Captcha, CaptchaError> {
+        let degraded = self.is_degraded();
+        let (config, count) = if degraded {
+            (&self.cheap, &self.degraded_count)
+        } else {
+            (&self.normal, &self.normal_count)
+        };
+        let captcha = Captcha::try_with_config(config.clone())?;
+        count.fetch_add(1, Ordering::Relaxed);
+        Ok(captcha)
+    }
+
+    /// Counts of generated CAPTCHAs by mode
+    pub fn stats(&self) -> DegradationStats {
+        DegradationStats {
+            normal: self.normal_count.load(Ordering::Relaxed),
+            degraded: self.degraded_count.load(Ordering::Relaxed),
+            activations: self.activations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Difficulty;
+
+    #[test]
+    fn test_cheap_profile() {
+        let config = CaptchaConfig::with_difficulty(Difficulty::Extreme);
+        let cheap = config.cheap_profile();
+        assert_eq!(cheap.noise_dots, config.noise_dots / 4);
+        assert_eq!(cheap.interference_lines, (6, 7));
+        assert_eq!(cheap.effects.len(), config.effects.len() - 1);
+        assert_eq!((cheap.width, cheap.height), (config.width, config.height));
+    }
+
+    #[test]
+    fn test_switching_modes() {
+        let degrader = Degrader::new(CaptchaConfig::default());
+        degrader.generate().unwrap();
+        degrader.set_degraded(true);
+        degrader.set_degraded(true);
+        assert_eq!(degrader.config().noise_dots, 25);
+        degrader.generate().unwrap();
+        degrader.generate().unwrap();
+        degrader.set_degraded(false);
+        degrader.set_degraded(true);
+        assert_eq!(
+            degrader.stats(),
+            DegradationStats {
+                normal: 1,
+                degraded: 2,
+                activations: 2,
+            }
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{CaptchaConfig, Effect};
+
+/// How hard a CAPTCHA is to read, scaling every distortion parameter together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Difficulty {
+    /// Light distortion for low-risk forms and accessibility
+    Easy,
+    /// The default parameters
+    #[default]
+    Medium,
+    /// More lines, noise and rotation
+    Hard,
+    /// As much distortion as stays readable for humans
+    Extreme,
+}
+
+impl Difficulty {
+    /// Overwrite the distortion parameters and effects of `config`, keeping size, fonts, colors and the rest
+    pub fn apply_to(&self, config: &mut CaptchaConfig) {
+        let (lines, dots, wave, rotation, jitter) = match self {
+            Difficulty::Easy => ((1, 2), 40, (0.5, 1.0), 0.12, (1.0, 2.0)),
+            Difficulty::Medium => ((2, 4), 100, (1.5, 2.5), 0.26, (2.0, 5.0)),
+            Difficulty::Hard => ((4, 6), 200, (2.5, 3.5), 0.4, (3.0, 7.0)),
+            Difficulty::Extreme => ((6, 9), 320, (3.5, 4.5), 0.55, (4.0, 9.0)),
+        };
+        config.interference_lines = lines;
+        config.noise_dots = dots;
+        config.wave_amplitude = wave;
+        config.rotation = rotation;
+        config.jitter = jitter;
+        config.effects = match self {
+            Difficulty::Easy | Difficulty::Medium => Vec::new(),
+            Difficulty::Hard => vec![Effect::Shear(0.2)],
+            Difficulty::Extreme => vec![
+                Effect::Shear(0.3),
+                Effect::Overlap(5.0),
+                Effect::Ripple {
+                    amplitude: 1.5,
+                    wavelength: 36.0,
+                },
+            ],
+        };
+    }
+}
+
+impl CaptchaConfig {
+    /// The default configuration with the distortion of `difficulty`
+    pub fn with_difficulty(difficulty: Difficulty) -> Self {
+        let mut config = Self::default();
+        difficulty.apply_to(&mut config);
+        config
+    }
+
+    /// Light distortion
+    pub fn easy() -> Self {
+        Self::with_difficulty(Difficulty::Easy)
+    }
+
+    /// The default distortion
+    pub fn medium() -> Self {
+        Self::with_difficulty(Difficulty::Medium)
+    }
+
+    /// Heavy distortion
+    pub fn hard() -> Self {
+        Self::with_difficulty(Difficulty::Hard)
+    }
+
+    /// The heaviest distortion
+    pub fn extreme() -> Self {
+        Self::with_difficulty(Difficulty::Extreme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_medium_matches_default() {
+        let medium = CaptchaConfig::medium();
+        let default = CaptchaConfig::default();
+        assert_eq!(medium.interference_lines, default.interference_lines);
+        assert_eq!(medium.noise_dots, default.noise_dots);
+        assert_eq!(medium.wave_amplitude, default.wave_amplitude);
+        assert_eq!(medium.rotation, default.rotation);
+        assert_eq!(medium.jitter, default.jitter);
+        assert!(medium.effects.is_empty());
+    }
+
+    #[test]
+    fn test_presets_scale_together() {
+        let presets = [
+            CaptchaConfig::easy(),
+            CaptchaConfig::medium(),
+            CaptchaConfig::hard(),
+            CaptchaConfig::extreme(),
+        ];
+        for pair in presets.windows(2) {
+            assert!(pair[0].noise_dots < pair[1].noise_dots);
+            assert!(pair[0].interference_lines.1 < pair[1].interference_lines.1);
+            assert!(pair[0].wave_amplitude.1 < pair[1].wave_amplitude.1);
+            assert!(pair[0].rotation < pair[1].rotation);
+            assert!(pair[0].jitter.1 < pair[1].jitter.1);
+        }
+        for config in presets {
+            crate::Captcha::with_config(config);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::sync::Arc;
+
+use image::RgbaImage;
+
+/// An extra distortion applied on top of the base rendering
+///
+/// Effects run in the order they appear in `CaptchaConfig::effects`.
+/// Character effects change how each glyph is drawn; image effects run on the
+/// finished raster after the wave distortion and are skipped by
+/// [`Captcha::to_svg_string`](crate::Captcha::to_svg_string).
+#[derive(Debug, Clone)]
+pub enum Effect {
+    /// Slant every character by a random horizontal shear factor in `-max..max`
+    Shear(f32),
+    /// Pull neighbouring characters this many pixels closer so they touch or overlap
+    Overlap(f32),
+    /// Draw a ring of this thickness in pixels around each character's strokes, leaving them hollow
+    Outline(f32),
+    /// Displace pixels along both axes with crossing sine waves
+    Ripple {
+        /// Largest displacement in pixels
+        amplitude: f32,
+        /// Length of one wave period in pixels
+        wavelength: f32,
+    },
+    /// A caller-provided image effect
+    Custom(CustomEffect),
+}
+
+type CustomFn = dyn Fn(&mut RgbaImage, u64) + Send + Sync;
+
+/// An image effect implemented by the caller
+///
+/// The function receives the rendered image and a seed drawn from the
+/// CAPTCHA's RNG, so seeded configurations stay reproducible.
+#[derive(Clone)]
+pub struct CustomEffect(Arc<CustomFn>);
+
+impl CustomEffect {
+    /// Wrap a function as an effect
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&mut RgbaImage, u64) + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    pub(crate) fn apply(&self, image: &mut RgbaImage, seed: u64) {
+        (self.0)(image, seed)
+    }
+
+    /// Address of the wrapped function, shared by every clone
+    #[cfg(feature = "cache")]
+    pub(crate) fn addr(&self) -> usize {
+        Arc::as_ptr(&self.0) as *const () as usize
+    }
+}
+
+impl fmt::Debug for CustomEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CustomEffect")
+    }
+}
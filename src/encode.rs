@@ -0,0 +1,388 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder as ImageJpegEncoder;
+use image::codecs::webp::WebPEncoder as ImageWebPEncoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, RgbImage, RgbaImage};
+
+use crate::quantize::{quantize, Quantization};
+use crate::CaptchaError;
+
+/// Turns a rendered CAPTCHA image into bytes
+///
+/// Implement this to use an external codec or optimizer, e.g. mozjpeg or a
+/// PNG optimizer run over the output of [`PngEncoder`]:
+///
+/// ```
+/// use captcha_generator::image::RgbImage;
+/// use captcha_generator::{Captcha, CaptchaError, Encoder, PngEncoder};
+///
+/// struct OptimizedPng;
+///
+/// impl Encoder for OptimizedPng {
+///     fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, CaptchaError> {
+///         let png = PngEncoder.encode(image)?;
+///         // run an optimizer over `png` here
+///         Ok(png)
+///     }
+///
+///     fn content_type(&self) -> &str {
+///         "image/png"
+///     }
+/// }
+///
+/// let bytes = Captcha::new().encode_with(&OptimizedPng).unwrap();
+/// ```
+pub trait Encoder {
+    /// Encode the image
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, CaptchaError>;
+
+    /// Encode an image with an alpha channel
+    ///
+    /// Formats without transparency flatten the image onto white first.
+    fn encode_rgba(&self, image: &RgbaImage) -> Result<Vec<u8>, CaptchaError> {
+        self.encode(&flatten(image))
+    }
+
+    /// MIME type of the encoded bytes, e.g. `image/png`
+    fn content_type(&self) -> &str;
+}
+
+/// The built-in PNG encoder
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PngEncoder;
+
+impl Encoder for PngEncoder {
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, CaptchaError> {
+        let mut bytes = Vec::new();
+        image.write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )?;
+        Ok(bytes)
+    }
+
+    fn encode_rgba(&self, image: &RgbaImage) -> Result<Vec<u8>, CaptchaError> {
+        let mut bytes = Vec::new();
+        image.write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )?;
+        Ok(bytes)
+    }
+
+    fn content_type(&self) -> &str {
+        "image/png"
+    }
+}
+
+/// The built-in JPEG encoder
+#[derive(Debug, Clone, Copy)]
+pub struct JpegEncoder {
+    /// Quality from 1 (smallest) to 100 (best)
+    pub quality: u8,
+}
+
+impl Default for JpegEncoder {
+    fn default() -> Self {
+        Self { quality: 85 }
+    }
+}
+
+impl Encoder for JpegEncoder {
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, CaptchaError> {
+        let mut bytes = Vec::new();
+        let quality = self.quality.clamp(1, 100);
+        image.write_with_encoder(ImageJpegEncoder::new_with_quality(&mut bytes, quality))?;
+        Ok(bytes)
+    }
+
+    fn content_type(&self) -> &str {
+        "image/jpeg"
+    }
+}
+
+/// The built-in WebP encoder (lossless)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebPEncoder;
+
+impl Encoder for WebPEncoder {
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, CaptchaError> {
+        let mut bytes = Vec::new();
+        image.write_with_encoder(ImageWebPEncoder::new_lossless(&mut bytes))?;
+        Ok(bytes)
+    }
+
+    fn encode_rgba(&self, image: &RgbaImage) -> Result<Vec<u8>, CaptchaError> {
+        let mut bytes = Vec::new();
+        image.write_with_encoder(ImageWebPEncoder::new_lossless(&mut bytes))?;
+        Ok(bytes)
+    }
+
+    fn content_type(&self) -> &str {
+        "image/webp"
+    }
+}
+
+/// Built-in raster output formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageOutputFormat {
+    /// Truecolor PNG
+    Png,
+    /// JPEG with a quality from 1 (smallest) to 100 (best)
+    Jpeg {
+        /// Encoding quality
+        quality: u8,
+    },
+    /// Lossless WebP
+    WebP,
+}
+
+impl ImageOutputFormat {
+    /// The encoder for this format
+    pub fn encoder(&self) -> Box<dyn Encoder> {
+        match *self {
+            ImageOutputFormat::Png => Box::new(PngEncoder),
+            ImageOutputFormat::Jpeg { quality } => Box::new(JpegEncoder { quality }),
+            ImageOutputFormat::WebP => Box::new(WebPEncoder),
+        }
+    }
+}
+
+/// Palette-based (PNG-8) encoder
+///
+/// The image is reduced to a median-cut palette first, which usually makes the
+/// payload much smaller than a truecolor PNG.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Png8Encoder {
+    /// Palette size and dithering
+    pub quantization: Quantization,
+}
+
+impl Encoder for Png8Encoder {
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, CaptchaError> {
+        let quantized = quantize(image, self.quantization);
+        let palette: Vec<u8> = quantized.palette.concat();
+
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, quantized.width, quantized.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(palette);
+        let mut writer = encoder.write_header().map_err(png_error)?;
+        writer
+            .write_image_data(&quantized.indices)
+            .map_err(png_error)?;
+        writer.finish().map_err(png_error)?;
+        Ok(bytes)
+    }
+
+    fn content_type(&self) -> &str {
+        "image/png"
+    }
+}
+
+pub(crate) fn png_error(e: png::EncodingError) -> CaptchaError {
+    CaptchaError::Image(ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        e,
+    )))
+}
+
+/// Encoded image bytes together with their MIME type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedImage {
+    /// The encoded bytes
+    pub bytes: Vec<u8>,
+    /// MIME type of `bytes`, e.g. `image/png`
+    pub content_type: String,
+}
+
+impl EncodedImage {
+    /// Size of the encoded payload in bytes
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// The bytes as standard base64
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(&self.bytes)
+    }
+
+    /// A `data:` URI that can be used directly as an `<img src>`
+    pub fn to_data_uri(&self) -> String {
+        format!("data:{};base64,{}", self.content_type, self.to_base64())
+    }
+}
+
+/// Composite `image` onto a white background
+pub(crate) fn flatten(image: &RgbaImage) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let alpha = a as u32;
+        image::Rgb([r, g, b].map(|c| ((c as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8))
+    })
+}
+
+/// Encode the image as PNG, falling back to smaller encodings until it fits in `max_bytes`
+///
+/// Tries plain PNG, from `rgba` when given so it keeps its transparency, then
+/// PNG-8 with increasingly small palettes, then JPEG at decreasing quality.
+/// The fallbacks encode the opaque `image`.
+pub(crate) fn encode_within_budget(
+    image: &RgbImage,
+    rgba: Option<&RgbaImage>,
+    max_bytes: Option<usize>,
+) -> Result<EncodedImage, CaptchaError> {
+    let encoders: Vec<Box<dyn Encoder>> = match max_bytes {
+        None => vec![Box::new(PngEncoder)],
+        Some(_) => {
+            let mut encoders: Vec<Box<dyn Encoder>> = vec![Box::new(PngEncoder)];
+            for colors in [256, 64, 16, 8] {
+                encoders.push(Box::new(Png8Encoder {
+                    quantization: Quantization {
+                        colors,
+                        ..Default::default()
+                    },
+                }));
+            }
+            for quality in [85, 70, 55, 40, 25, 10] {
+                encoders.push(Box::new(JpegEncoder { quality }));
+            }
+            encoders
+        }
+    };
+
+    let budget = max_bytes.unwrap_or(usize::MAX);
+    let mut smallest = usize::MAX;
+    for (i, encoder) in encoders.iter().enumerate() {
+        let bytes = match rgba {
+            Some(rgba) if i == 0 => encoder.encode_rgba(rgba)?,
+            _ => encoder.encode(image)?,
+        };
+        if bytes.len() <= budget {
+            return Ok(EncodedImage {
+                bytes,
+                content_type: encoder.content_type().to_string(),
+            });
+        }
+        smallest = smallest.min(bytes.len());
+    }
+
+    Err(CaptchaError::BudgetExceeded {
+        max_bytes: budget,
+        smallest,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgba_encoding() {
+        let image = RgbaImage::from_pixel(4, 3, image::Rgba([10, 20, 30, 0]));
+        let bytes = PngEncoder.encode_rgba(&image).unwrap();
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert!(decoded.color().has_alpha());
+
+        let flat = flatten(&image);
+        assert_eq!(flat.get_pixel(0, 0).0, [255, 255, 255]);
+        let bytes = JpegEncoder::default().encode_rgba(&image).unwrap();
+        assert!(!image::load_from_memory(&bytes).unwrap().color().has_alpha());
+    }
+
+    #[test]
+    fn test_png_encoder() {
+        let image = RgbImage::new(4, 3);
+        let bytes = PngEncoder.encode(&image).unwrap();
+        assert_eq!(&bytes[1..4], b"PNG");
+        assert_eq!(PngEncoder.content_type(), "image/png");
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let image = RgbImage::from_pixel(16, 8, image::Rgb([200, 30, 30]));
+        for (format, mime, magic) in [
+            (ImageOutputFormat::Png, "image/png", &b"\x89PNG"[..]),
+            (
+                ImageOutputFormat::Jpeg { quality: 60 },
+                "image/jpeg",
+                &b"\xff\xd8"[..],
+            ),
+            (ImageOutputFormat::WebP, "image/webp", &b"RIFF"[..]),
+        ] {
+            let encoder = format.encoder();
+            let bytes = encoder.encode(&image).unwrap();
+            assert_eq!(encoder.content_type(), mime);
+            assert!(bytes.starts_with(magic));
+            let decoded = image::load_from_memory(&bytes).unwrap();
+            assert_eq!(decoded.width(), 16);
+        }
+    }
+
+    #[test]
+    fn test_data_uri() {
+        let encoded = EncodedImage {
+            bytes: b"abc".to_vec(),
+            content_type: "image/png".to_string(),
+        };
+        assert_eq!(encoded.to_base64(), "YWJj");
+        assert_eq!(encoded.to_data_uri(), "data:image/png;base64,YWJj");
+    }
+
+    #[test]
+    fn test_png8_encoder() {
+        let image = crate::Captcha::new().image;
+        let png8 = Png8Encoder::default().encode(&image).unwrap();
+        let decoded = image::load_from_memory(&png8).unwrap();
+        assert_eq!(decoded.width(), image.width());
+        assert!(png8.len() < PngEncoder.encode(&image).unwrap().len());
+    }
+
+    #[test]
+    fn test_budget_falls_back_to_smaller_encodings() {
+        let image = crate::Captcha::new().image;
+        let png = PngEncoder.encode(&image).unwrap();
+
+        let unlimited = encode_within_budget(&image, None, None).unwrap();
+        assert_eq!(unlimited.bytes, png);
+
+        let budget = png.len() / 2;
+        let encoded = encode_within_budget(&image, None, Some(budget)).unwrap();
+        assert!(encoded.size() <= budget);
+
+        let err = encode_within_budget(&image, None, Some(10)).unwrap_err();
+        assert!(matches!(
+            err,
+            CaptchaError::BudgetExceeded { max_bytes: 10, .. }
+        ));
+    }
+
+    #[test]
+    fn test_budget_keeps_transparency_in_plain_png() {
+        let captcha = crate::Captcha::with_config(crate::CaptchaConfig {
+            theme: crate::ColorTheme {
+                background: crate::Background::Transparent,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let rgba = captcha.rgba_image().unwrap();
+
+        let png = encode_within_budget(&captcha.image, Some(rgba), None).unwrap();
+        assert!(image::load_from_memory(&png.bytes)
+            .unwrap()
+            .color()
+            .has_alpha());
+
+        // The smaller fallbacks flatten the background
+        let budget = png.size() - 1;
+        let smaller = encode_within_budget(&captcha.image, Some(rgba), Some(budget)).unwrap();
+        assert!(smaller.size() <= budget);
+        assert!(!image::load_from_memory(&smaller.bytes)
+            .unwrap()
+            .color()
+            .has_alpha());
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+use crate::i18n::{Locale, MessageCatalog, MessageKey};
+
+/// Errors that can occur while generating or encoding a CAPTCHA
+#[derive(Debug)]
+pub enum CaptchaError {
+    /// A font file could not be read
+    Io(std::io::Error),
+    /// Font data could not be parsed as a TrueType/OpenType font
+    InvalidFont,
+    /// The image could not be encoded or saved
+    Image(image::ImageError),
+    /// No encoding of the image fits in the configured `max_bytes`
+    BudgetExceeded {
+        /// The configured budget
+        max_bytes: usize,
+        /// Size of the smallest encoding that was tried
+        smallest: usize,
+    },
+    /// A configured font has no glyph for some characters of the charset
+    MissingGlyphs {
+        /// Index of the font in the configuration (0 for the embedded font)
+        font: usize,
+        /// The characters without a glyph
+        chars: Vec<char>,
+    },
+    /// Even a code of the narrowest characters is wider than the image
+    TextTooWide {
+        /// Width of that code in pixels
+        needed: u32,
+        /// The image width
+        width: u32,
+    },
+    /// The charset has no characters to draw a code from
+    EmptyCharset,
+}
+
+impl CaptchaError {
+    /// The catalog key for this error's user-facing message
+    pub fn message_key(&self) -> MessageKey {
+        match self {
+            CaptchaError::Io(_) => MessageKey::FontUnreadable,
+            CaptchaError::InvalidFont => MessageKey::InvalidFont,
+            CaptchaError::Image(_) => MessageKey::ImageFailed,
+            CaptchaError::BudgetExceeded { .. } => MessageKey::PayloadTooLarge,
+            CaptchaError::MissingGlyphs { .. }
+            | CaptchaError::TextTooWide { .. }
+            | CaptchaError::EmptyCharset => MessageKey::InvalidConfig,
+        }
+    }
+
+    /// The user-facing message for this error, without internal details
+    pub fn localized<'c>(&self, catalog: &'c dyn MessageCatalog) -> &'c str {
+        catalog.message(self.message_key())
+    }
+}
+
+impl fmt::Display for CaptchaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = self.localized(&Locale::En);
+        match self {
+            CaptchaError::Io(e) => write!(f, "{}: {}", message, e),
+            CaptchaError::InvalidFont => write!(f, "{}", message),
+            CaptchaError::Image(e) => write!(f, "{}: {}", message, e),
+            CaptchaError::BudgetExceeded {
+                max_bytes,
+                smallest,
+            } => write!(
+                f,
+                "{}: smallest encoding is {} bytes, budget is {} bytes",
+                message, smallest, max_bytes
+            ),
+            CaptchaError::MissingGlyphs { font, chars } => write!(
+                f,
+                "{}: font {} has no glyph for {:?}",
+                message,
+                font,
+                chars.iter().collect::<String>()
+            ),
+            CaptchaError::TextTooWide { needed, width } => write!(
+                f,
+                "{}: the code needs at least {} pixels, the image is {} wide",
+                message, needed, width
+            ),
+            CaptchaError::EmptyCharset => write!(f, "{}: the charset is empty", message),
+        }
+    }
+}
+
+impl std::error::Error for CaptchaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CaptchaError::Io(e) => Some(e),
+            CaptchaError::InvalidFont => None,
+            CaptchaError::Image(e) => Some(e),
+            CaptchaError::BudgetExceeded { .. }
+            | CaptchaError::MissingGlyphs { .. }
+            | CaptchaError::TextTooWide { .. }
+            | CaptchaError::EmptyCharset => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CaptchaError {
+    fn from(e: std::io::Error) -> Self {
+        CaptchaError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for CaptchaError {
+    fn from(e: image::ImageError) -> Self {
+        CaptchaError::Image(e)
+    }
+}
@@ -0,0 +1,233 @@
+//! A stable encoding of configurations for hashing
+//!
+//! Every value is written field by field in a fixed order: integers as
+//! big-endian `u64`s or `u32`s, floats by their bits, enums as a tag byte
+//! chosen here, and lists and strings behind their length. Unlike `Debug`
+//! output, the encoding does not change with derives or the toolchain, so
+//! separately deployed services agree on the hash of a configuration.
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    Background, CaptchaConfig, ColorRange, Dithering, Effect, FontSource, HorizontalAlign, Spacing,
+    VerticalAnchor,
+};
+
+/// Prefix of every encoding; a change to the encoding needs a new version
+const DOMAIN: &[u8] = b"captcha-generator config v1";
+
+/// How font files and custom effects, which have no portable value, are identified
+///
+/// Fonts held in memory are always identified by content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Identity {
+    /// Font files by full path and custom effects by address, for keys that
+    /// never leave the process
+    #[cfg(feature = "cache")]
+    Local,
+    /// Font files by file name and custom effects only by position, so other
+    /// processes holding the same fonts agree
+    #[cfg(feature = "challenge")]
+    Portable,
+}
+
+/// Feed every value of `config` except the seed into `hasher`
+pub(crate) fn write_config(hasher: &mut Sha256, config: &CaptchaConfig, identity: Identity) {
+    // Destructured so that a new configuration field cannot be forgotten here
+    let CaptchaConfig {
+        width,
+        height,
+        code_length,
+        charset,
+        font_size,
+        spacing,
+        alignment,
+        theme,
+        interference_lines,
+        noise_dots,
+        wave_amplitude,
+        rotation,
+        jitter,
+        effects,
+        fonts,
+        random_font_per_char,
+        seed: _,
+        max_bytes,
+        quantization,
+    } = config;
+
+    let mut w = Writer(hasher);
+    w.0.update(DOMAIN);
+    w.u32(*width);
+    w.u32(*height);
+    w.usize(*code_length);
+    w.bytes(charset.as_bytes());
+    w.f32(*font_size);
+    match *spacing {
+        Spacing::Advance(extra) => {
+            w.u8(0);
+            w.f32(extra);
+        }
+        Spacing::Optical(gap) => {
+            w.u8(1);
+            w.f32(gap);
+        }
+    }
+    w.u8(match alignment.horizontal {
+        HorizontalAlign::Left => 0,
+        HorizontalAlign::Center => 1,
+        HorizontalAlign::Right => 2,
+    });
+    w.u8(match alignment.vertical {
+        VerticalAnchor::Top => 0,
+        VerticalAnchor::Middle => 1,
+        VerticalAnchor::Bottom => 2,
+    });
+    w.f32(alignment.margin);
+
+    match theme.background {
+        Background::Noise => w.u8(0),
+        Background::Transparent => w.u8(1),
+        Background::Solid(color) => {
+            w.u8(2);
+            w.0.update(color.0);
+        }
+        Background::Gradient { top, bottom } => {
+            w.u8(3);
+            w.0.update(top.0);
+            w.0.update(bottom.0);
+        }
+    }
+    w.colors(&theme.text);
+    w.colors(&theme.interference);
+    w.colors(&theme.noise);
+
+    w.usize(interference_lines.0);
+    w.usize(interference_lines.1);
+    w.usize(*noise_dots);
+    w.f32(wave_amplitude.0);
+    w.f32(wave_amplitude.1);
+    w.f32(*rotation);
+    w.f32(jitter.0);
+    w.f32(jitter.1);
+
+    w.usize(effects.len());
+    for effect in effects {
+        match effect {
+            Effect::Shear(max) => {
+                w.u8(0);
+                w.f32(*max);
+            }
+            Effect::Overlap(pixels) => {
+                w.u8(1);
+                w.f32(*pixels);
+            }
+            Effect::Outline(thickness) => {
+                w.u8(2);
+                w.f32(*thickness);
+            }
+            Effect::Ripple {
+                amplitude,
+                wavelength,
+            } => {
+                w.u8(3);
+                w.f32(*amplitude);
+                w.f32(*wavelength);
+            }
+            #[cfg_attr(not(feature = "cache"), allow(unused_variables))]
+            Effect::Custom(custom) => {
+                w.u8(4);
+                match identity {
+                    #[cfg(feature = "cache")]
+                    Identity::Local => w.usize(custom.addr()),
+                    #[cfg(feature = "challenge")]
+                    // Closures have no portable identity
+                    Identity::Portable => {}
+                }
+            }
+        }
+    }
+
+    w.usize(fonts.len());
+    for font in fonts {
+        match font {
+            FontSource::Embedded => w.u8(0),
+            FontSource::Bytes(data) => {
+                w.u8(1);
+                w.0.update(Sha256::digest(data));
+            }
+            #[cfg(feature = "fs")]
+            FontSource::File(path) => {
+                w.u8(2);
+                match identity {
+                    #[cfg(feature = "cache")]
+                    Identity::Local => w.bytes(path.as_os_str().as_encoded_bytes()),
+                    #[cfg(feature = "challenge")]
+                    Identity::Portable => w.bytes(
+                        path.file_name()
+                            .unwrap_or(path.as_os_str())
+                            .as_encoded_bytes(),
+                    ),
+                }
+            }
+            #[cfg(feature = "system-fonts")]
+            FontSource::System(name) => {
+                w.u8(3);
+                w.bytes(name.as_bytes());
+            }
+        }
+    }
+    w.u8(*random_font_per_char as u8);
+
+    match max_bytes {
+        None => w.u8(0),
+        Some(max_bytes) => {
+            w.u8(1);
+            w.usize(*max_bytes);
+        }
+    }
+    match quantization {
+        None => w.u8(0),
+        Some(quantization) => {
+            w.u8(1);
+            w.usize(quantization.colors);
+            w.u8(match quantization.dithering {
+                Dithering::None => 0,
+                Dithering::FloydSteinberg => 1,
+            });
+        }
+    }
+}
+
+struct Writer<'a>(&'a mut Sha256);
+
+impl Writer<'_> {
+    fn u8(&mut self, value: u8) {
+        self.0.update([value]);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.update(value.to_be_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.0.update((value as u64).to_be_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.usize(value.len());
+        self.0.update(value);
+    }
+
+    fn colors(&mut self, ranges: &[ColorRange]) {
+        self.usize(ranges.len());
+        for range in ranges {
+            self.0.update(range.min.0);
+            self.0.update(range.max.0);
+        }
+    }
+}
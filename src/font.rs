@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use rusttype::Font;
+
+use crate::CaptchaError;
+
+/// Embedded DejaVu Sans font
+pub(crate) const FONT_DATA: &[u8] = include_bytes!("../assets/dejavusans.ttf");
+
+/// Largest font file read; larger files, or devices like `/dev/zero`, are rejected
+#[cfg(feature = "fs")]
+const MAX_FONT_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Where a font used for CAPTCHA text comes from
+#[derive(Clone, Default)]
+pub enum FontSource {
+    /// The embedded DejaVu Sans font
+    #[default]
+    Embedded,
+    /// TrueType/OpenType font data held in memory
+    Bytes(Arc<[u8]>),
+    /// A TrueType/OpenType font file on disk
+    #[cfg(feature = "fs")]
+    File(PathBuf),
+    /// An installed font looked up by family name, e.g. `"Segoe UI"`
+    #[cfg(feature = "system-fonts")]
+    System(String),
+}
+
+impl FontSource {
+    /// Create a font source from in-memory font data
+    pub fn from_bytes(data: impl Into<Vec<u8>>) -> Self {
+        FontSource::Bytes(data.into().into())
+    }
+
+    /// Create a font source from a font file path
+    #[cfg(feature = "fs")]
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        FontSource::File(path.into())
+    }
+
+    /// Create a font source for the installed font family `name`
+    ///
+    /// The regular face of the family is used. Fonts are found in the
+    /// platform's font directories, e.g. `C:\Windows\Fonts` on Windows.
+    #[cfg(feature = "system-fonts")]
+    pub fn system(name: impl Into<String>) -> Self {
+        FontSource::System(name.into())
+    }
+
+    /// Load and parse the font
+    pub fn load(&self) -> Result<Font<'static>, CaptchaError> {
+        match self {
+            FontSource::Embedded => Font::try_from_bytes(FONT_DATA),
+            FontSource::Bytes(data) => Font::try_from_vec(data.to_vec()),
+            #[cfg(feature = "fs")]
+            FontSource::File(path) => Font::try_from_vec(read_font_file(path)?),
+            #[cfg(feature = "system-fonts")]
+            FontSource::System(name) => {
+                let (data, index) = system_font_data(name)?;
+                Font::try_from_vec_and_index(data, index)
+            }
+        }
+        .ok_or(CaptchaError::InvalidFont)
+    }
+}
+
+/// Read a font file of at most [`MAX_FONT_FILE_BYTES`]
+#[cfg(feature = "fs")]
+fn read_font_file(path: &Path) -> Result<Vec<u8>, CaptchaError> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    std::fs::File::open(path)?
+        .take(MAX_FONT_FILE_BYTES + 1)
+        .read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_FONT_FILE_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("font file is larger than {} bytes", MAX_FONT_FILE_BYTES),
+        )
+        .into());
+    }
+    Ok(data)
+}
+
+/// The data and face index of the regular face of an installed font family
+#[cfg(feature = "system-fonts")]
+fn system_font_data(name: &str) -> Result<(Vec<u8>, u32), CaptchaError> {
+    use fontdb::{Database, Family, Query};
+
+    // Scanning the font directories is slow, so it happens once per process
+    static DATABASE: OnceLock<Database> = OnceLock::new();
+    let database = DATABASE.get_or_init(|| {
+        let mut database = Database::new();
+        database.load_system_fonts();
+        database
+    });
+    let not_found = || {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no installed font named {:?}", name),
+        )
+    };
+    let id = database
+        .query(&Query {
+            families: &[Family::Name(name)],
+            ..Query::default()
+        })
+        .ok_or_else(not_found)?;
+    database
+        .with_face_data(id, |data, index| (data.to_vec(), index))
+        .ok_or_else(|| not_found().into())
+}
+
+impl fmt::Debug for FontSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontSource::Embedded => write!(f, "Embedded"),
+            FontSource::Bytes(data) => write!(f, "Bytes({} bytes)", data.len()),
+            #[cfg(feature = "fs")]
+            FontSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            #[cfg(feature = "system-fonts")]
+            FontSource::System(name) => f.debug_tuple("System").field(name).finish(),
+        }
+    }
+}
+
+/// Bounds on the fonts kept parsed in memory between CAPTCHAs
+///
+/// Loaded fonts beyond either bound are dropped least recently used first and
+/// parsed again on their next use. The embedded font is always kept and not
+/// counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FontCacheLimits {
+    /// Most fonts kept
+    pub max_fonts: usize,
+    /// Most bytes of font data kept; a larger font is loaded but not cached
+    ///
+    /// Fonts given as bytes count twice: the cache keeps the caller's buffer
+    /// alive as well as the copy the parsed font owns.
+    pub max_bytes: usize,
+}
+
+impl Default for FontCacheLimits {
+    fn default() -> Self {
+        Self {
+            max_fonts: 256,
+            max_bytes: 128 * 1024 * 1024,
+        }
+    }
+}
+
+/// What the font cache currently holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FontCacheStats {
+    /// Number of cached fonts
+    pub fonts: usize,
+    /// Bytes of font data they take
+    pub bytes: usize,
+    /// Fonts dropped to stay within the limits since the process started
+    pub evictions: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    #[cfg(feature = "fs")]
+    File(PathBuf),
+    #[cfg(feature = "system-fonts")]
+    System(String),
+    Bytes(FontData),
+}
+
+/// Font data compared by content, so equal fonts from separate buffers share an entry
+///
+/// Only the length and the first KiB are hashed, which keeps lookups cheap;
+/// fonts that agree on those are told apart by comparing all their bytes.
+#[derive(Clone)]
+struct FontData(Arc<[u8]>);
+
+impl PartialEq for FontData {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for FontData {}
+
+impl Hash for FontData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.len().hash(state);
+        self.0[..self.0.len().min(1024)].hash(state);
+    }
+}
+
+struct Entry {
+    font: Font<'static>,
+    size: usize,
+    last_used: u64,
+}
+
+/// Fonts that have already been parsed, shared by every CAPTCHA
+#[derive(Default)]
+struct FontCache {
+    entries: HashMap<Key, Entry>,
+    limits: FontCacheLimits,
+    bytes: usize,
+    clock: u64,
+    evictions: u64,
+}
+
+impl FontCache {
+    fn get(&mut self, key: &Key) -> Option<Font<'static>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry.font.clone())
+    }
+
+    fn insert(&mut self, key: Key, font: Font<'static>, size: usize) {
+        if size > self.limits.max_bytes || self.limits.max_fonts == 0 {
+            return;
+        }
+        if let Some(old) = self.entries.remove(&key) {
+            self.bytes -= old.size;
+        }
+        self.shrink(self.limits.max_fonts - 1, self.limits.max_bytes - size);
+        self.bytes += size;
+        self.entries.insert(
+            key,
+            Entry {
+                font,
+                size,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Drop least recently used fonts until at most `max_fonts` and `max_bytes` remain
+    fn shrink(&mut self, max_fonts: usize, max_bytes: usize) {
+        while self.entries.len() > max_fonts || self.bytes > max_bytes {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            let entry = self.entries.remove(&oldest).expect("key was just found");
+            self.bytes -= entry.size;
+            self.evictions += 1;
+        }
+    }
+
+    fn set_limits(&mut self, limits: FontCacheLimits) {
+        self.limits = limits;
+        self.shrink(limits.max_fonts, limits.max_bytes);
+    }
+
+    fn stats(&self) -> FontCacheStats {
+        FontCacheStats {
+            fonts: self.entries.len(),
+            bytes: self.bytes,
+            evictions: self.evictions,
+        }
+    }
+
+    /// Return the cached font for `source`, loading and caching it on a miss
+    fn load(cache: &Mutex<Self>, source: &FontSource) -> Result<Font<'static>, CaptchaError> {
+        let key = match source {
+            FontSource::Embedded => return Ok(embedded_font()),
+            #[cfg(feature = "fs")]
+            FontSource::File(path) => Key::File(path.clone()),
+            #[cfg(feature = "system-fonts")]
+            FontSource::System(name) => Key::System(name.clone()),
+            FontSource::Bytes(data) => Key::Bytes(FontData(data.clone())),
+        };
+        if let Some(font) = cache.lock().unwrap().get(&key) {
+            return Ok(font);
+        }
+        // Parsed without holding the lock; concurrent misses for one font may both parse it
+        let (font, size) = match source {
+            #[cfg(feature = "fs")]
+            FontSource::File(path) => {
+                let data = read_font_file(path)?;
+                let size = data.len();
+                (Font::try_from_vec(data), size)
+            }
+            #[cfg(feature = "system-fonts")]
+            FontSource::System(name) => {
+                let (data, index) = system_font_data(name)?;
+                let size = data.len();
+                (Font::try_from_vec_and_index(data, index), size)
+            }
+            // The key holds the caller's buffer next to the font's own copy
+            FontSource::Bytes(data) => (Font::try_from_vec(data.to_vec()), 2 * data.len()),
+            FontSource::Embedded => unreachable!("the embedded font is not cached"),
+        };
+        let font = font.ok_or(CaptchaError::InvalidFont)?;
+        cache.lock().unwrap().insert(key, font.clone(), size);
+        Ok(font)
+    }
+}
+
+fn font_cache() -> &'static Mutex<FontCache> {
+    static CACHE: OnceLock<Mutex<FontCache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Bound the fonts kept in memory, dropping the least recently used ones beyond the new limits
+///
+/// Fonts held by a [`PreparedConfig`](crate::PreparedConfig) stay loaded for
+/// as long as it lives, whatever the limits.
+pub fn set_font_cache_limits(limits: FontCacheLimits) {
+    font_cache().lock().unwrap().set_limits(limits);
+}
+
+/// How many fonts are cached and how much memory they take
+pub fn font_cache_stats() -> FontCacheStats {
+    font_cache().lock().unwrap().stats()
+}
+
+fn embedded_font() -> Font<'static> {
+    static EMBEDDED: OnceLock<Font<'static>> = OnceLock::new();
+    EMBEDDED
+        .get_or_init(|| Font::try_from_bytes(FONT_DATA).expect("embedded font is valid"))
+        .clone()
+}
+
+/// Load every configured font, falling back to the embedded font when none are given
+///
+/// Each font is parsed once and then served from the cache until it is
+/// evicted. Byte buffers are cached by content, file fonts by path, so later
+/// changes to the file on disk are not picked up.
+pub(crate) fn load_fonts(sources: &[FontSource]) -> Result<Vec<Font<'static>>, CaptchaError> {
+    if sources.is_empty() {
+        return Ok(vec![embedded_font()]);
+    }
+    sources
+        .iter()
+        .map(|source| FontCache::load(font_cache(), source))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_embedded_and_bytes() {
+        assert!(FontSource::Embedded.load().is_ok());
+        assert!(FontSource::from_bytes(FONT_DATA).load().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_font() {
+        let result = FontSource::from_bytes(vec![0u8; 16]).load();
+        assert!(matches!(result, Err(CaptchaError::InvalidFont)));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_missing_font_file() {
+        let result = FontSource::from_file("/nonexistent/font.ttf").load();
+        assert!(matches!(result, Err(CaptchaError::Io(_))));
+    }
+
+    #[cfg(feature = "system-fonts")]
+    #[test]
+    fn test_missing_system_font() {
+        let result = FontSource::system("No Such Font Family 1234").load();
+        assert!(
+            matches!(result, Err(CaptchaError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_empty_sources_fall_back_to_embedded() {
+        assert_eq!(load_fonts(&[]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cached_fonts() {
+        let source = FontSource::from_bytes(FONT_DATA);
+        let fonts = load_fonts(&[source.clone(), source]).unwrap();
+        assert_eq!(fonts.len(), 2);
+        assert!(load_fonts(&[FontSource::from_bytes(vec![1u8; 8])]).is_err());
+    }
+
+    /// A distinct copy of the embedded font, `FONT_DATA.len() + 1` bytes long
+    fn distinct_font(tag: u8) -> FontSource {
+        let mut data = FONT_DATA.to_vec();
+        data.push(tag);
+        FontSource::from_bytes(data)
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let size = 2 * (FONT_DATA.len() + 1);
+        let cache = Mutex::new(FontCache::default());
+        cache.lock().unwrap().set_limits(FontCacheLimits {
+            max_fonts: 2,
+            max_bytes: size * 3,
+        });
+        let sources: Vec<_> = (0..3).map(distinct_font).collect();
+        FontCache::load(&cache, &sources[0]).unwrap();
+        FontCache::load(&cache, &sources[1]).unwrap();
+        FontCache::load(&cache, &sources[0]).unwrap();
+        FontCache::load(&cache, &sources[2]).unwrap();
+
+        let mut cache = cache.into_inner().unwrap();
+        assert_eq!(
+            cache.stats(),
+            FontCacheStats {
+                fonts: 2,
+                bytes: size * 2,
+                evictions: 1,
+            }
+        );
+        let key = |source: &FontSource| match source {
+            FontSource::Bytes(data) => Key::Bytes(FontData(data.clone())),
+            _ => unreachable!(),
+        };
+        assert!(cache.get(&key(&sources[0])).is_some());
+        assert!(cache.get(&key(&sources[1])).is_none());
+
+        cache.set_limits(FontCacheLimits {
+            max_fonts: 8,
+            max_bytes: size,
+        });
+        assert_eq!(cache.stats().fonts, 1);
+    }
+
+    #[test]
+    fn test_equal_fonts_share_an_entry() {
+        let cache = Mutex::new(FontCache::default());
+        for _ in 0..3 {
+            FontCache::load(&cache, &FontSource::from_bytes(FONT_DATA)).unwrap();
+        }
+        FontCache::load(&cache, &distinct_font(0)).unwrap();
+
+        let stats = cache.into_inner().unwrap().stats();
+        assert_eq!(stats.fonts, 2);
+        assert_eq!(stats.bytes, 2 * FONT_DATA.len() + 2 * (FONT_DATA.len() + 1));
+        assert_eq!(stats.evictions, 0);
+    }
+
+    #[cfg(all(feature = "fs", unix))]
+    #[test]
+    fn test_font_file_size_is_limited() {
+        let result = FontSource::from_file("/dev/zero").load();
+        assert!(
+            matches!(result, Err(CaptchaError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn test_fonts_over_the_byte_limit_are_not_cached() {
+        let cache = Mutex::new(FontCache::default());
+        cache.lock().unwrap().set_limits(FontCacheLimits {
+            max_fonts: 8,
+            max_bytes: 2 * FONT_DATA.len() - 1,
+        });
+        assert!(FontCache::load(&cache, &FontSource::from_bytes(FONT_DATA)).is_ok());
+        assert_eq!(cache.lock().unwrap().stats().fonts, 0);
+    }
+}
@@ -0,0 +1,224 @@
+//! CAPTCHAs for classic server-rendered forms
+//!
+//! [`FormCaptcha`] renders the image inline together with a hidden field
+//! holding its signed token, so the answer posted back can be checked with
+//! [`verify_form`] without sessions, cookies or JavaScript. A [`TokenLedger`]
+//! makes sure each token is accepted only once:
+//!
+//! ```
+//! use captcha_generator::form::{verify_form, FormCaptcha, ANSWER_FIELD, TOKEN_FIELD};
+//! use captcha_generator::verify::{TokenLedger, TokenSigner, VerifyError};
+//! use captcha_generator::{Captcha, CaptchaConfig};
+//!
+//! let signer = TokenSigner::new(b"server secret key".to_vec());
+//! let ledger = TokenLedger::new();
+//! let config = CaptchaConfig {
+//!     seed: Some(7),
+//!     ..Default::default()
+//! };
+//! let form = FormCaptcha::issue(&signer, config.clone()).unwrap();
+//! let html = format!("<form method=\"post\">{}<button>Send</button></form>", form.html());
+//!
+//! // ...and when the form is posted:
+//! let code = Captcha::with_config(config).code;
+//! let posted = [(TOKEN_FIELD, form.token.as_str()), (ANSWER_FIELD, code.as_str())];
+//! assert!(verify_form(&signer, &ledger, posted).is_ok());
+//! assert_eq!(verify_form(&signer, &ledger, posted), Err(VerifyError::Used));
+//! ```
+
+use crate::verify::{TokenLedger, TokenSigner, VerifyError};
+use crate::{Captcha, CaptchaConfig, CaptchaError};
+
+/// Form field carrying the token
+pub const TOKEN_FIELD: &str = "captcha_token";
+/// Form field carrying the user's answer
+pub const ANSWER_FIELD: &str = "captcha_answer";
+
+/// An issued CAPTCHA ready to be embedded in a form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormCaptcha {
+    /// The signed answer token for the hidden field
+    pub token: String,
+    /// The PNG image as a `data:` URI
+    pub image: String,
+}
+
+impl FormCaptcha {
+    /// Generate a CAPTCHA from `config` and sign its code with `signer`
+    pub fn issue(signer: &TokenSigner, config: CaptchaConfig) -> Result<Self, CaptchaError> {
+        let captcha = Captcha::try_with_config(config)?;
+        Ok(Self {
+            token: signer.mint(&captcha.code),
+            image: captcha.to_data_uri()?,
+        })
+    }
+
+    /// The hidden `<input>` holding the token
+    pub fn hidden_field(&self) -> String {
+        // Tokens are URL-safe base64 and need no escaping
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            TOKEN_FIELD, self.token
+        )
+    }
+
+    /// The inline `<img>` of the CAPTCHA
+    pub fn image_tag(&self) -> String {
+        format!(r#"<img src="{}" alt="CAPTCHA">"#, self.image)
+    }
+
+    /// The image, the hidden token field and a text input for the answer
+    pub fn html(&self) -> String {
+        format!(
+            r#"{}{}<input type="text" name="{}" autocomplete="off" required>"#,
+            self.image_tag(),
+            self.hidden_field(),
+            ANSWER_FIELD
+        )
+    }
+}
+
+/// Check the token and answer fields of a posted form
+///
+/// `fields` are the decoded name/value pairs of the form; other fields are
+/// ignored. A missing token is [`VerifyError::Malformed`] and a missing answer
+/// a [`VerifyError::Mismatch`]. As with [`TokenSigner::verify_token_once`],
+/// `ledger` refuses a token that was already accepted or answered wrongly too
+/// often.
+pub fn verify_form<'a, I>(
+    signer: &TokenSigner,
+    ledger: &TokenLedger,
+    fields: I,
+) -> Result<(), VerifyError>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let (mut token, mut answer) = (None, None);
+    for (name, value) in fields {
+        match name {
+            TOKEN_FIELD => token = Some(value),
+            ANSWER_FIELD => answer = Some(value),
+            _ => {}
+        }
+    }
+    let token = token.ok_or(VerifyError::Malformed)?;
+    let answer = answer.ok_or(VerifyError::Mismatch)?;
+    signer.verify_token_once(token, answer, ledger)
+}
+
+/// Check an `application/x-www-form-urlencoded` request body
+///
+/// Like [`verify_form`]; a body that cannot be decoded is [`VerifyError::Malformed`].
+#[cfg(feature = "web")]
+pub fn verify_form_body(
+    signer: &TokenSigner,
+    ledger: &TokenLedger,
+    body: &[u8],
+) -> Result<(), VerifyError> {
+    let fields: Vec<(String, String)> =
+        serde_urlencoded::from_bytes(body).map_err(|_| VerifyError::Malformed)?;
+    verify_form(
+        signer,
+        ledger,
+        fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue() -> (TokenSigner, FormCaptcha, String) {
+        let signer = TokenSigner::new(b"key".to_vec());
+        let config = CaptchaConfig {
+            seed: Some(3),
+            ..Default::default()
+        };
+        let form = FormCaptcha::issue(&signer, config.clone()).unwrap();
+        (signer, form, Captcha::with_config(config).code)
+    }
+
+    #[test]
+    fn test_html() {
+        let (_, form, _) = issue();
+        assert!(form.image.starts_with("data:image/png;base64,"));
+        let html = form.html();
+        assert!(html.contains(&format!(r#"name="captcha_token" value="{}""#, form.token)));
+        assert!(html.contains(r#"name="captcha_answer""#));
+    }
+
+    #[test]
+    fn test_verify_form() {
+        let (signer, form, code) = issue();
+        let ledger = TokenLedger::new();
+        let answer = code.to_lowercase();
+        let token = form.token.as_str();
+        assert_eq!(
+            verify_form(&signer, &ledger, [(TOKEN_FIELD, token)]),
+            Err(VerifyError::Mismatch)
+        );
+        assert_eq!(
+            verify_form(&signer, &ledger, [(ANSWER_FIELD, answer.as_str())]),
+            Err(VerifyError::Malformed)
+        );
+        let posted = [
+            ("email", "a@b.c"),
+            (ANSWER_FIELD, &answer),
+            (TOKEN_FIELD, token),
+        ];
+        assert!(verify_form(&signer, &ledger, posted).is_ok());
+        assert_eq!(
+            verify_form(&signer, &ledger, posted),
+            Err(VerifyError::Used)
+        );
+    }
+
+    #[test]
+    fn test_verify_form_attempts() {
+        let (signer, form, code) = issue();
+        let ledger = TokenLedger::new().with_max_attempts(2);
+        let wrong = [(TOKEN_FIELD, form.token.as_str()), (ANSWER_FIELD, "nope")];
+        assert_eq!(
+            verify_form(&signer, &ledger, wrong),
+            Err(VerifyError::Mismatch)
+        );
+        // The last allowed wrong answer already locks the token
+        for _ in 0..2 {
+            assert_eq!(
+                verify_form(&signer, &ledger, wrong),
+                Err(VerifyError::TooManyAttempts)
+            );
+        }
+        let token = form.token.as_str();
+        assert_eq!(
+            verify_form(
+                &signer,
+                &ledger,
+                [(TOKEN_FIELD, token), (ANSWER_FIELD, &code)]
+            ),
+            Err(VerifyError::TooManyAttempts)
+        );
+    }
+
+    #[cfg(feature = "web")]
+    #[test]
+    fn test_verify_form_body() {
+        let (signer, form, code) = issue();
+        let ledger = TokenLedger::new();
+        let body = format!(
+            "name=J%C3%BCrgen&captcha_token={}&captcha_answer=+{}+",
+            form.token, code
+        );
+        assert_eq!(verify_form_body(&signer, &ledger, body.as_bytes()), Ok(()));
+        assert_eq!(
+            verify_form_body(&signer, &ledger, body.as_bytes()),
+            Err(VerifyError::Used)
+        );
+        assert_eq!(
+            verify_form_body(&signer, &ledger, b"captcha_answer=AB23"),
+            Err(VerifyError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Keyed hashing of answers
+//!
+//! Stores and tokens only ever hold a keyed hash of a normalized code, so a
+//! leaked store dump or token log does not reveal answers that could still be
+//! submitted. [`HmacSha256Hasher`] is the default; with the `blake3` feature,
+//! `Blake3Hasher` is a faster alternative. Other schemes plug in by
+//! implementing [`AnswerHasher`]:
+//!
+//! ```
+//! use captcha_generator::hash::{AnswerHasher, HmacSha256Hasher};
+//!
+//! let hasher = HmacSha256Hasher::new(b"server key".to_vec());
+//! let hash = hasher.hash(b"salt", "AB23CD");
+//! assert!(hasher.verify(b"salt", "AB23CD", &hash));
+//! assert!(!hasher.verify(b"salt", "AB23CE", &hash));
+//! ```
+
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+
+/// A keyed hash of an answer, such as an HMAC
+///
+/// Implementations must be deterministic for a given key, and should not be
+/// computable without the key: codes are short enough to brute-force an
+/// unkeyed hash.
+pub trait AnswerHasher: Send + Sync {
+    /// Hash `answer`, which is already normalized, under a per-challenge `salt`
+    fn hash(&self, salt: &[u8], answer: &str) -> [u8; 32];
+
+    /// Whether `answer` hashes to `expected`, compared in constant time
+    fn verify(&self, salt: &[u8], answer: &str, expected: &[u8; 32]) -> bool {
+        constant_time_eq(&self.hash(salt, answer), expected)
+    }
+}
+
+/// HMAC-SHA256 of the salt followed by the answer
+#[derive(Clone)]
+pub struct HmacSha256Hasher {
+    key: Vec<u8>,
+}
+
+impl HmacSha256Hasher {
+    /// Create a hasher with a secret key
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// Create a hasher with a random key, for hashes that never leave the process
+    pub fn random() -> Self {
+        let mut key = vec![0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self { key }
+    }
+}
+
+impl AnswerHasher for HmacSha256Hasher {
+    fn hash(&self, salt: &[u8], answer: &str) -> [u8; 32] {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(salt);
+        mac.update(answer.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+}
+
+impl fmt::Debug for HmacSha256Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HmacSha256Hasher").finish_non_exhaustive()
+    }
+}
+
+/// BLAKE3 in keyed mode over the salt followed by the answer
+#[cfg(feature = "blake3")]
+#[derive(Clone)]
+pub struct Blake3Hasher {
+    key: [u8; 32],
+}
+
+#[cfg(feature = "blake3")]
+impl Blake3Hasher {
+    /// Create a hasher with a 32 byte secret key
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Create a hasher with a key derived from a secret of any length
+    pub fn derive(secret: &[u8]) -> Self {
+        Self::new(blake3::derive_key("captcha-generator answer hash", secret))
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl AnswerHasher for Blake3Hasher {
+    fn hash(&self, salt: &[u8], answer: &str) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        hasher.update(salt);
+        hasher.update(answer.as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl fmt::Debug for Blake3Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blake3Hasher").finish_non_exhaustive()
+    }
+}
+
+/// Compare without returning early, so timing does not reveal how much of the code matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_depends_on_key_and_salt() {
+        let hasher = HmacSha256Hasher::new(b"key".to_vec());
+        let hash = hasher.hash(b"salt", "AB23CD");
+        assert_eq!(hash, hasher.hash(b"salt", "AB23CD"));
+        assert_ne!(hash, hasher.hash(b"other", "AB23CD"));
+        assert_ne!(hash, HmacSha256Hasher::random().hash(b"salt", "AB23CD"));
+        assert!(!hasher.verify(b"salt", "AB23C", &hash));
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3() {
+        let hasher = Blake3Hasher::derive(b"key");
+        let hash = hasher.hash(b"salt", "AB23CD");
+        assert!(hasher.verify(b"salt", "AB23CD", &hash));
+        assert!(!Blake3Hasher::derive(b"other").verify(b"salt", "AB23CD", &hash));
+    }
+}
@@ -0,0 +1,194 @@
+//! Translatable user-facing messages
+
+/// Identifies a user-facing message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MessageKey {
+    /// A font file could not be read
+    FontUnreadable,
+    /// Font data could not be parsed
+    InvalidFont,
+    /// The image could not be encoded or saved
+    ImageFailed,
+    /// The encoded image does not fit in the size budget
+    PayloadTooLarge,
+    /// A verification token could not be decoded
+    TokenMalformed,
+    /// The CAPTCHA has expired
+    CodeExpired,
+    /// The answer was wrong
+    CodeMismatch,
+    /// The CAPTCHA was already answered correctly
+    CodeUsed,
+    /// The CAPTCHA got too many wrong answers
+    TooManyAttempts,
+    /// No CAPTCHA is stored under the given id
+    ChallengeNotFound,
+    /// Too many CAPTCHAs were requested
+    QuotaExceeded,
+    /// The request lacks the CAPTCHA token or answer, or cannot be parsed
+    InvalidRequest,
+    /// The configured fonts and charset cannot render a CAPTCHA
+    InvalidConfig,
+}
+
+impl MessageKey {
+    /// Every message key, for catalogs that want to check their coverage
+    pub const ALL: &'static [MessageKey] = &[
+        MessageKey::FontUnreadable,
+        MessageKey::InvalidFont,
+        MessageKey::ImageFailed,
+        MessageKey::PayloadTooLarge,
+        MessageKey::TokenMalformed,
+        MessageKey::CodeExpired,
+        MessageKey::CodeMismatch,
+        MessageKey::CodeUsed,
+        MessageKey::TooManyAttempts,
+        MessageKey::ChallengeNotFound,
+        MessageKey::QuotaExceeded,
+        MessageKey::InvalidRequest,
+        MessageKey::InvalidConfig,
+    ];
+}
+
+/// A source of translated messages
+///
+/// Implement this to plug in your own translations; [`Locale`] provides the
+/// built-in ones.
+pub trait MessageCatalog {
+    /// The text for `key`
+    fn message(&self, key: MessageKey) -> &str;
+}
+
+/// Built-in message catalogs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Locale {
+    /// English
+    #[default]
+    En,
+    /// Spanish
+    Es,
+    /// French
+    Fr,
+    /// German
+    De,
+}
+
+impl Locale {
+    /// Pick a built-in locale from a language tag such as `"fr"` or `"de-AT"`
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.split(['-', '_']).next()?.trim().to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::En),
+            "es" => Some(Locale::Es),
+            "fr" => Some(Locale::Fr),
+            "de" => Some(Locale::De),
+            _ => None,
+        }
+    }
+}
+
+impl MessageCatalog for Locale {
+    fn message(&self, key: MessageKey) -> &str {
+        use MessageKey::*;
+        match (self, key) {
+            (Locale::En, FontUnreadable) => "The font file could not be read",
+            (Locale::En, InvalidFont) => "The font data is invalid",
+            (Locale::En, ImageFailed) => "The CAPTCHA image could not be created",
+            (Locale::En, PayloadTooLarge) => "The CAPTCHA image does not fit in the size limit",
+            (Locale::En, TokenMalformed) => "The CAPTCHA token is invalid",
+            (Locale::En, CodeExpired) => "The CAPTCHA has expired, please try a new one",
+            (Locale::En, CodeMismatch) => "The CAPTCHA answer is incorrect",
+            (Locale::En, CodeUsed) => "The CAPTCHA was already used, please try a new one",
+            (Locale::En, TooManyAttempts) => "Too many wrong answers, please try a new CAPTCHA",
+            (Locale::En, ChallengeNotFound) => "The CAPTCHA was not found, please try a new one",
+            (Locale::En, QuotaExceeded) => "Too many CAPTCHAs were requested, please wait a moment",
+            (Locale::En, InvalidRequest) => "The CAPTCHA answer is missing or unreadable",
+            (Locale::En, InvalidConfig) => "The CAPTCHA settings cannot be rendered",
+
+            (Locale::Es, FontUnreadable) => "No se pudo leer el archivo de fuente",
+            (Locale::Es, InvalidFont) => "Los datos de la fuente no son válidos",
+            (Locale::Es, ImageFailed) => "No se pudo crear la imagen CAPTCHA",
+            (Locale::Es, PayloadTooLarge) => "La imagen CAPTCHA supera el tamaño máximo",
+            (Locale::Es, TokenMalformed) => "El token CAPTCHA no es válido",
+            (Locale::Es, CodeExpired) => "El CAPTCHA ha caducado, inténtelo con uno nuevo",
+            (Locale::Es, CodeMismatch) => "La respuesta del CAPTCHA es incorrecta",
+            (Locale::Es, CodeUsed) => "El CAPTCHA ya se utilizó, inténtelo con uno nuevo",
+            (Locale::Es, TooManyAttempts) => {
+                "Demasiadas respuestas incorrectas, inténtelo con un CAPTCHA nuevo"
+            }
+            (Locale::Es, ChallengeNotFound) => "No se encontró el CAPTCHA, inténtelo con uno nuevo",
+            (Locale::Es, QuotaExceeded) => {
+                "Se han solicitado demasiados CAPTCHA, espere un momento"
+            }
+            (Locale::Es, InvalidRequest) => "Falta la respuesta del CAPTCHA o no se puede leer",
+            (Locale::Es, InvalidConfig) => "La configuración del CAPTCHA no se puede representar",
+
+            (Locale::Fr, FontUnreadable) => "Le fichier de police n'a pas pu être lu",
+            (Locale::Fr, InvalidFont) => "Les données de police ne sont pas valides",
+            (Locale::Fr, ImageFailed) => "L'image CAPTCHA n'a pas pu être créée",
+            (Locale::Fr, PayloadTooLarge) => "L'image CAPTCHA dépasse la taille maximale",
+            (Locale::Fr, TokenMalformed) => "Le jeton CAPTCHA n'est pas valide",
+            (Locale::Fr, CodeExpired) => "Le CAPTCHA a expiré, veuillez en essayer un nouveau",
+            (Locale::Fr, CodeMismatch) => "La réponse au CAPTCHA est incorrecte",
+            (Locale::Fr, CodeUsed) => {
+                "Le CAPTCHA a déjà été utilisé, veuillez en essayer un nouveau"
+            }
+            (Locale::Fr, TooManyAttempts) => {
+                "Trop de réponses incorrectes, veuillez essayer un nouveau CAPTCHA"
+            }
+            (Locale::Fr, ChallengeNotFound) => {
+                "Le CAPTCHA est introuvable, veuillez en essayer un nouveau"
+            }
+            (Locale::Fr, QuotaExceeded) => "Trop de CAPTCHA ont été demandés, veuillez patienter",
+            (Locale::Fr, InvalidRequest) => "La réponse au CAPTCHA est manquante ou illisible",
+            (Locale::Fr, InvalidConfig) => "Les paramètres du CAPTCHA ne peuvent pas être rendus",
+
+            (Locale::De, FontUnreadable) => "Die Schriftdatei konnte nicht gelesen werden",
+            (Locale::De, InvalidFont) => "Die Schriftdaten sind ungültig",
+            (Locale::De, ImageFailed) => "Das CAPTCHA-Bild konnte nicht erstellt werden",
+            (Locale::De, PayloadTooLarge) => "Das CAPTCHA-Bild überschreitet die Größenbegrenzung",
+            (Locale::De, TokenMalformed) => "Das CAPTCHA-Token ist ungültig",
+            (Locale::De, CodeExpired) => "Das CAPTCHA ist abgelaufen, bitte ein neues versuchen",
+            (Locale::De, CodeMismatch) => "Die CAPTCHA-Antwort ist falsch",
+            (Locale::De, CodeUsed) => {
+                "Das CAPTCHA wurde bereits verwendet, bitte ein neues versuchen"
+            }
+            (Locale::De, TooManyAttempts) => {
+                "Zu viele falsche Antworten, bitte ein neues CAPTCHA versuchen"
+            }
+            (Locale::De, ChallengeNotFound) => {
+                "Das CAPTCHA wurde nicht gefunden, bitte ein neues versuchen"
+            }
+            (Locale::De, QuotaExceeded) => {
+                "Es wurden zu viele CAPTCHAs angefordert, bitte kurz warten"
+            }
+            (Locale::De, InvalidRequest) => "Die CAPTCHA-Antwort fehlt oder ist unlesbar",
+            (Locale::De, InvalidConfig) => {
+                "Die CAPTCHA-Einstellungen können nicht dargestellt werden"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_locale_covers_every_key() {
+        for locale in [Locale::En, Locale::Es, Locale::Fr, Locale::De] {
+            for &key in MessageKey::ALL {
+                assert!(!locale.message(key).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_tag() {
+        assert_eq!(Locale::from_tag("fr"), Some(Locale::Fr));
+        assert_eq!(Locale::from_tag("de-AT"), Some(Locale::De));
+        assert_eq!(Locale::from_tag("ES_mx"), Some(Locale::Es));
+        assert_eq!(Locale::from_tag("ja"), None);
+    }
+}
@@ -0,0 +1,209 @@
+//! One interface for every kind of challenge
+//!
+//! A [`ChallengeKind`] renders a challenge to media for the client and
+//! defines how answers are compared. Frameworks and servers that work with
+//! `dyn ChallengeKind` handle the built-in [`TextChallenge`] and kinds of
+//! your own the same way, and a [`ChallengeRegistry`] picks them by name:
+//!
+//! ```
+//! use captcha_generator::kind::{ChallengeKind, ChallengeRegistry, TextChallenge};
+//! use captcha_generator::CaptchaConfig;
+//!
+//! let mut registry = ChallengeRegistry::new();
+//! registry.register(TextChallenge::new(CaptchaConfig::default()));
+//!
+//! let kind = registry.get("text").unwrap();
+//! let issued = kind.generate().unwrap();
+//! assert_eq!(issued.media[0].content_type, "image/png");
+//! assert!(kind.verify(&issued.answer, &issued.answer.to_lowercase()));
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::hash::constant_time_eq;
+use crate::verify::normalize;
+use crate::{Captcha, CaptchaConfig, CaptchaError, ImageOutputFormat};
+
+/// Something shown to the user as part of a challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Media {
+    /// MIME type of `data`, e.g. `image/png`
+    pub content_type: String,
+    /// The encoded media
+    pub data: Vec<u8>,
+}
+
+/// A freshly generated challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuedChallenge {
+    /// What to send to the client, in the kind's order of preference
+    pub media: Vec<Media>,
+    /// The expected answer; keep it on the server or sign it
+    pub answer: String,
+}
+
+/// A kind of challenge: how it is rendered and how answers are checked
+pub trait ChallengeKind: Send + Sync {
+    /// A short, stable name such as `"text"`, used to register and look up the kind
+    fn name(&self) -> &str;
+
+    /// Generate a new challenge
+    fn generate(&self) -> Result<IssuedChallenge, CaptchaError>;
+
+    /// The form answers are compared in, e.g. trimmed and uppercased
+    ///
+    /// Stores that only keep a hash of the answer hash this form.
+    fn normalize_answer(&self, input: &str) -> String {
+        input.trim().to_string()
+    }
+
+    /// Whether `input` answers a challenge whose expected answer is `answer`
+    ///
+    /// Compares the normalized forms in constant time.
+    fn verify(&self, answer: &str, input: &str) -> bool {
+        constant_time_eq(
+            self.normalize_answer(answer).as_bytes(),
+            self.normalize_answer(input).as_bytes(),
+        )
+    }
+}
+
+/// The distorted-text CAPTCHA as a [`ChallengeKind`] named `"text"`
+#[derive(Debug, Clone)]
+pub struct TextChallenge {
+    config: CaptchaConfig,
+    format: ImageOutputFormat,
+    case_sensitive: bool,
+}
+
+impl TextChallenge {
+    /// Generate PNG CAPTCHAs from `config`, with case-insensitive answers
+    pub fn new(config: CaptchaConfig) -> Self {
+        Self {
+            config,
+            format: ImageOutputFormat::Png,
+            case_sensitive: false,
+        }
+    }
+
+    /// Set the image format
+    pub fn with_format(mut self, format: ImageOutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Require answers to match the code's case exactly
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+}
+
+impl ChallengeKind for TextChallenge {
+    fn name(&self) -> &str {
+        "text"
+    }
+
+    fn generate(&self) -> Result<IssuedChallenge, CaptchaError> {
+        let captcha = Captcha::try_with_config(self.config.clone())?;
+        let encoder = self.format.encoder();
+        let media = Media {
+            content_type: encoder.content_type().to_string(),
+            data: captcha.encode_with(encoder.as_ref())?,
+        };
+        Ok(IssuedChallenge {
+            media: vec![media],
+            answer: captcha.code,
+        })
+    }
+
+    fn normalize_answer(&self, input: &str) -> String {
+        normalize(input, self.case_sensitive)
+    }
+}
+
+/// Challenge kinds by name
+#[derive(Default)]
+pub struct ChallengeRegistry {
+    kinds: BTreeMap<String, Box<dyn ChallengeKind>>,
+}
+
+impl ChallengeRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `kind` under its name, replacing a kind registered under the same name
+    pub fn register(&mut self, kind: impl ChallengeKind + 'static) -> &mut Self {
+        self.kinds.insert(kind.name().to_string(), Box::new(kind));
+        self
+    }
+
+    /// The kind registered as `name`
+    pub fn get(&self, name: &str) -> Option<&dyn ChallengeKind> {
+        self.kinds.get(name).map(|kind| kind.as_ref())
+    }
+
+    /// Names of every registered kind, in sorted order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.kinds.keys().map(String::as_str)
+    }
+}
+
+impl fmt::Debug for ChallengeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A custom kind: answer with the sum of two numbers
+    struct Sum;
+
+    impl ChallengeKind for Sum {
+        fn name(&self) -> &str {
+            "sum"
+        }
+
+        fn generate(&self) -> Result<IssuedChallenge, CaptchaError> {
+            Ok(IssuedChallenge {
+                media: vec![Media {
+                    content_type: "text/plain".to_string(),
+                    data: b"2 + 3".to_vec(),
+                }],
+                answer: "5".to_string(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_text_challenge() {
+        let kind = TextChallenge::new(CaptchaConfig::default())
+            .with_format(ImageOutputFormat::WebP)
+            .case_sensitive(true);
+        let issued = kind.generate().unwrap();
+        assert_eq!(issued.media[0].content_type, "image/webp");
+        assert!(kind.verify(&issued.answer, &format!(" {} ", issued.answer)));
+        assert!(!kind.verify(&issued.answer, "nope"));
+    }
+
+    #[test]
+    fn test_registry_with_custom_kind() {
+        let mut registry = ChallengeRegistry::new();
+        registry
+            .register(Sum)
+            .register(TextChallenge::new(CaptchaConfig::default()));
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["sum", "text"]);
+        assert_eq!(format!("{:?}", registry), r#"{"sum", "text"}"#);
+
+        let sum = registry.get("sum").unwrap();
+        let issued = sum.generate().unwrap();
+        assert!(sum.verify(&issued.answer, " 5"));
+        assert!(registry.get("audio").is_none());
+    }
+}
@@ -1,9 +1,90 @@
-use image::{Rgb, RgbImage};
-use rand::Rng;
-use rusttype::{point, Font, Scale};
+use std::io::Write;
 
-/// Embedded DejaVu Sans font
-const FONT_DATA: &[u8] = include_bytes!("../assets/dejavusans.ttf");
+use image::{DynamicImage, RgbImage, RgbaImage};
+use rand::Rng;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "challenge")]
+pub mod challenge;
+pub mod charset;
+mod clamp;
+pub mod clock;
+pub mod degrade;
+mod difficulty;
+mod effect;
+mod encode;
+mod error;
+#[cfg(any(feature = "cache", feature = "challenge"))]
+mod fingerprint;
+mod font;
+#[cfg(feature = "tokens")]
+pub mod form;
+#[cfg(feature = "tokens")]
+pub mod hash;
+pub mod i18n;
+#[cfg(feature = "tokens")]
+pub mod kind;
+#[cfg(feature = "utoipa")]
+pub mod openapi;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+mod portable;
+mod prepared;
+pub mod problem;
+pub mod quantize;
+pub mod render;
+#[cfg(feature = "tokens")]
+pub mod select;
+#[cfg(feature = "store")]
+pub mod store;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod theme;
+pub mod trace;
+#[cfg(not(target_arch = "wasm32"))]
+mod tune;
+#[cfg(feature = "tokens")]
+pub mod verify;
+mod warmup;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "web")]
+pub mod web;
+#[cfg(feature = "tokens")]
+pub mod wire;
+
+#[cfg(feature = "actix-web")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+
+pub use image;
+#[cfg(feature = "rayon")]
+pub use rayon;
+
+pub use charset::CharsetPreset;
+pub use clamp::Adjustment;
+pub use difficulty::Difficulty;
+pub use effect::{CustomEffect, Effect};
+pub use encode::{
+    EncodedImage, Encoder, ImageOutputFormat, JpegEncoder, Png8Encoder, PngEncoder, WebPEncoder,
+};
+pub use error::CaptchaError;
+pub use font::{
+    font_cache_stats, set_font_cache_limits, FontCacheLimits, FontCacheStats, FontSource,
+};
+pub use i18n::{Locale, MessageCatalog, MessageKey};
+pub use prepared::PreparedConfig;
+pub use quantize::{Dithering, Quantization};
+
+pub use render::{Background, HorizontalAlign, Spacing, TextAlignment, VerticalAnchor};
+pub use theme::{ColorRange, ColorTheme};
+pub use warmup::warmup;
+
+use render::{AsciiRenderer, GifRenderer, RasterRenderer, Renderer, Scene, SvgRenderer};
 
 /// Configuration for CAPTCHA generation
 #[derive(Debug, Clone)]
@@ -14,14 +95,39 @@ pub struct CaptchaConfig {
     pub height: u32,
     /// Length of the CAPTCHA code
     pub code_length: usize,
+    /// Characters the code is drawn from, usually a [`CharsetPreset`]
+    pub charset: String,
     /// Font size for the text
     pub font_size: f32,
+    /// How neighbouring characters are spaced
+    pub spacing: Spacing,
+    /// Where the text sits in the image
+    pub alignment: TextAlignment,
+    /// Background and palettes; see [`Captcha::rgba_image`] for non-opaque backgrounds
+    pub theme: ColorTheme,
     /// Number of interference lines (min, max)
     pub interference_lines: (usize, usize),
     /// Number of noise dots
     pub noise_dots: usize,
     /// Wave distortion amplitude range (min, max)
     pub wave_amplitude: (f32, f32),
+    /// Largest character rotation either way, in radians
+    pub rotation: f32,
+    /// Largest random character offset (horizontal, vertical) in pixels
+    pub jitter: (f32, f32),
+    /// Extra distortions, applied in order
+    pub effects: Vec<Effect>,
+    /// Fonts to draw the text with (the embedded font is used when empty)
+    pub fonts: Vec<FontSource>,
+    /// Pick a random font for every character instead of one per CAPTCHA
+    pub random_font_per_char: bool,
+    /// Seed for reproducible output, byte-identical on every platform
+    /// (a fresh random seed is used when `None`)
+    pub seed: Option<u64>,
+    /// Maximum encoded size in bytes for [`Captcha::encode_within_budget`]
+    pub max_bytes: Option<usize>,
+    /// Reduce the raster image to a limited palette as a final texture effect
+    pub quantization: Option<Quantization>,
 }
 
 impl Default for CaptchaConfig {
@@ -30,10 +136,22 @@ impl Default for CaptchaConfig {
             width: 280,
             height: 100,
             code_length: 6,
+            charset: CharsetPreset::Default.chars(),
             font_size: 52.0,
+            spacing: Spacing::default(),
+            alignment: TextAlignment::default(),
+            theme: ColorTheme::default(),
             interference_lines: (2, 4),
             noise_dots: 100,
             wave_amplitude: (1.5, 2.5),
+            rotation: 0.26,
+            jitter: (2.0, 5.0),
+            effects: Vec::new(),
+            fonts: Vec::new(),
+            random_font_per_char: false,
+            seed: None,
+            max_bytes: None,
+            quantization: None,
         }
     }
 }
@@ -43,8 +161,14 @@ impl Default for CaptchaConfig {
 pub struct Captcha {
     /// The generated code string
     pub code: String,
-    /// The CAPTCHA image
+    /// The CAPTCHA image, flattened onto white if the background is not opaque
     pub image: RgbImage,
+    /// The image with its alpha channel, kept when the background is not opaque
+    rgba: Option<RgbaImage>,
+    /// Layout and distortion parameters the image was rendered from
+    scene: Scene,
+    /// Payload budget from the configuration
+    max_bytes: Option<usize>,
 }
 
 impl Captcha {
@@ -54,281 +178,349 @@ impl Captcha {
     }
 
     /// Generate a new CAPTCHA with custom configuration
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the configured fonts cannot be loaded or the charset
+    /// is empty; use [`Captcha::try_with_config`] to handle those cases.
     pub fn with_config(config: CaptchaConfig) -> Self {
-        let code = generate_code(config.code_length);
-        let image = generate_captcha_image(&code, &config);
-
-        Self { code, image }
+        Self::try_with_config(config).expect("Error loading font")
     }
 
-    /// Save the CAPTCHA image to a file
-    pub fn save(&self, path: &str) -> Result<(), image::ImageError> {
-        self.image.save(path)
+    /// Generate a new CAPTCHA with custom configuration, reporting font errors
+    pub fn try_with_config(config: CaptchaConfig) -> Result<Self, CaptchaError> {
+        match config.seed {
+            Some(seed) => Self::try_with_config_and_rng(config, &mut portable::seeded_rng(seed)),
+            None => Self::try_with_config_and_rng(config, &mut portable::entropy_rng()),
+        }
     }
 
-    /// Get the CAPTCHA image as PNG bytes
-    pub fn to_png_bytes(&self) -> Result<Vec<u8>, image::ImageError> {
-        let mut bytes = Vec::new();
-        self.image.write_to(
-            &mut std::io::Cursor::new(&mut bytes),
-            image::ImageFormat::Png,
-        )?;
-        Ok(bytes)
+    /// Generate a new CAPTCHA drawing all randomness from `rng`
+    ///
+    /// The same configuration and RNG state always produce the same code and
+    /// byte-identical image. `config.seed` is ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the configured fonts cannot be loaded or the charset
+    /// is empty.
+    pub fn with_config_and_rng<R: Rng + ?Sized>(config: CaptchaConfig, rng: &mut R) -> Self {
+        Self::try_with_config_and_rng(config, rng).expect("Error loading font")
     }
-}
 
-impl Default for Captcha {
-    fn default() -> Self {
-        Self::new()
+    /// Generate a new CAPTCHA drawing all randomness from `rng`, reporting font errors
+    pub fn try_with_config_and_rng<R: Rng + ?Sized>(
+        config: CaptchaConfig,
+        rng: &mut R,
+    ) -> Result<Self, CaptchaError> {
+        let (code, scene) = prepare(&config, rng)?;
+        Ok(Self::render(&config, code, scene))
     }
-}
 
-/// Generate a random CAPTCHA code
-fn generate_code(len: usize) -> String {
-    let mut rng = rand::thread_rng();
-    // Use only readable characters (avoiding 0/O, 1/I/l, etc.)
-    let charset = "23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
-    (0..len)
-        .map(|_| {
-            let idx = rng.gen_range(0..charset.len());
-            charset.chars().nth(idx).unwrap()
-        })
-        .collect()
-}
+    /// Rasterize `scene` and apply the configuration's post-processing
+    fn render(config: &CaptchaConfig, code: String, scene: Scene) -> Self {
+        let mut rgba = RasterRenderer.render(&scene);
+        if let Some(settings) = config.quantization {
+            let colors = DynamicImage::ImageRgba8(rgba.clone()).into_rgb8();
+            let quantized = quantize::quantize(&colors, settings).to_rgb_image();
+            for (pixel, color) in rgba.pixels_mut().zip(quantized.pixels()) {
+                pixel.0[..3].copy_from_slice(&color.0);
+            }
+        }
+        let (image, rgba) = if config.theme.background.is_opaque() {
+            (DynamicImage::ImageRgba8(rgba).into_rgb8(), None)
+        } else {
+            (encode::flatten(&rgba), Some(rgba))
+        };
 
-/// Create a gradient background
-fn create_background(width: u32, height: u32) -> RgbImage {
-    let mut rng = rand::thread_rng();
-    let mut img = RgbImage::new(width, height);
-
-    for y in 0..height {
-        for x in 0..width {
-            let base = 245 + rng.gen_range(0..10);
-            let r = base;
-            let g = (base - rng.gen_range(0..5)).clamp(240, 255);
-            let b = (base - rng.gen_range(0..5)).clamp(240, 255);
-            img.put_pixel(x, y, Rgb([r, g, b]));
+        Self {
+            code,
+            image,
+            rgba,
+            scene,
+            max_bytes: config.max_bytes,
         }
     }
-    img
-}
 
-/// Parameters for drawing a character
-struct CharDrawParams {
-    x_offset: f32,
-    y_offset: f32,
-    rotation: f32,
-    color: [u8; 3],
-}
-
-/// Draw a single character with rotation and positioning
-fn draw_character(img: &mut RgbImage, ch: char, params: CharDrawParams, font: &Font, scale: Scale) {
-    let glyph = font.glyph(ch).scaled(scale);
-
-    if let Some(bb) = glyph.exact_bounding_box() {
-        let glyph = glyph.positioned(point(0.0, 0.0));
+    /// Generate a CAPTCHA and write it to `writer` as PNG, returning the code
+    ///
+    /// Rows are rendered and compressed one at a time instead of holding
+    /// several full-size buffers, which keeps memory flat for very large
+    /// canvases. The pixels match [`Captcha::try_with_config`] for the same
+    /// seed; `quantization` and `max_bytes` are not applied.
+    pub fn write_png_streaming<W: Write>(
+        config: CaptchaConfig,
+        writer: W,
+    ) -> Result<String, CaptchaError> {
+        let (code, scene) = match config.seed {
+            Some(seed) => prepare(&config, &mut portable::seeded_rng(seed))?,
+            None => prepare(&config, &mut portable::entropy_rng())?,
+        };
+        render::write_png(&scene, writer)?;
+        Ok(code)
+    }
 
-        glyph.draw(|gx, gy, v| {
-            if v < 0.01 {
-                return;
+    /// Generate `n` CAPTCHAs with the same configuration
+    ///
+    /// With the `rayon` feature the CAPTCHAs are generated in parallel. Each one
+    /// gets its own seed drawn from `config.seed` (or a random seed), so a
+    /// seeded batch is reproducible regardless of thread scheduling.
+    pub fn generate_batch(n: usize, config: &CaptchaConfig) -> Result<Vec<Self>, CaptchaError> {
+        let seeds: Vec<u64> = match config.seed {
+            Some(seed) => {
+                let mut rng = portable::seeded_rng(seed);
+                (0..n).map(|_| rng.gen()).collect()
             }
+            None => {
+                let mut rng = portable::entropy_rng();
+                (0..n).map(|_| rng.gen()).collect()
+            }
+        };
+        let generate = |seed: u64| {
+            Self::try_with_config_and_rng(config.clone(), &mut portable::seeded_rng(seed))
+        };
 
-            let cx = bb.width() / 2.0;
-            let cy = bb.height() / 2.0;
-            let gx_f = gx as f32 - cx;
-            let gy_f = gy as f32 - cy;
-
-            let cos_r = params.rotation.cos();
-            let sin_r = params.rotation.sin();
-
-            let rotated_x = gx_f * cos_r - gy_f * sin_r;
-            let rotated_y = gx_f * sin_r + gy_f * cos_r;
+        #[cfg(feature = "rayon")]
+        return seeds.into_par_iter().map(generate).collect();
+        #[cfg(not(feature = "rayon"))]
+        seeds.into_iter().map(generate).collect()
+    }
 
-            let final_x = (rotated_x + cx + params.x_offset + bb.min.x) as i32;
-            let final_y = (rotated_y + cy + params.y_offset + bb.min.y) as i32;
+    /// Like [`Captcha::try_with_config`], with parallel rendering on `pool`
+    ///
+    /// Rayon's global pool uses every core; a dedicated pool built with
+    /// [`rayon::ThreadPoolBuilder::num_threads`] caps the CPU the crate takes.
+    #[cfg(feature = "rayon")]
+    pub fn try_with_config_in(
+        config: CaptchaConfig,
+        pool: &rayon::ThreadPool,
+    ) -> Result<Self, CaptchaError> {
+        pool.install(|| Self::try_with_config(config))
+    }
 
-            if final_x >= 0 && final_y >= 0 {
-                let fx = final_x as u32;
-                let fy = final_y as u32;
+    /// Like [`Captcha::generate_batch`], running every render on `pool`
+    ///
+    /// The output is the same as with the global pool for a seeded `config`.
+    #[cfg(feature = "rayon")]
+    pub fn generate_batch_in(
+        n: usize,
+        config: &CaptchaConfig,
+        pool: &rayon::ThreadPool,
+    ) -> Result<Vec<Self>, CaptchaError> {
+        pool.install(|| Self::generate_batch(n, config))
+    }
 
-                if fx < img.width() && fy < img.height() {
-                    let bg = img.get_pixel(fx, fy).0;
+    /// The image with its alpha channel, if the background is not opaque
+    pub fn rgba_image(&self) -> Option<&RgbaImage> {
+        self.rgba.as_ref()
+    }
 
-                    let alpha = v;
-                    let r = (bg[0] as f32 * (1.0 - alpha) + params.color[0] as f32 * alpha) as u8;
-                    let g = (bg[1] as f32 * (1.0 - alpha) + params.color[1] as f32 * alpha) as u8;
-                    let b = (bg[2] as f32 * (1.0 - alpha) + params.color[2] as f32 * alpha) as u8;
+    /// The image as RGBA when the background is not opaque, RGB otherwise
+    pub fn to_dynamic_image(&self) -> DynamicImage {
+        match &self.rgba {
+            Some(rgba) => DynamicImage::ImageRgba8(rgba.clone()),
+            None => DynamicImage::ImageRgb8(self.image.clone()),
+        }
+    }
 
-                    img.put_pixel(fx, fy, Rgb([r, g, b]));
+    /// Draw the CAPTCHA onto `canvas` with its top-left corner at (`x`, `y`)
+    ///
+    /// Transparent and translucent backgrounds are alpha-blended over the
+    /// canvas. Parts that fall outside the canvas are clipped.
+    pub fn composite_onto(&self, canvas: &mut RgbImage, x: i64, y: i64) {
+        let (width, height) = self.image.dimensions();
+        for sy in 0..height {
+            let ty = y + sy as i64;
+            if ty < 0 || ty >= canvas.height() as i64 {
+                continue;
+            }
+            for sx in 0..width {
+                let tx = x + sx as i64;
+                if tx < 0 || tx >= canvas.width() as i64 {
+                    continue;
+                }
+                let target = canvas.get_pixel_mut(tx as u32, ty as u32);
+                match &self.rgba {
+                    Some(rgba) => {
+                        let [r, g, b, a] = rgba.get_pixel(sx, sy).0;
+                        let alpha = a as u32;
+                        for (t, c) in target.0.iter_mut().zip([r, g, b]) {
+                            *t = ((c as u32 * alpha + *t as u32 * (255 - alpha) + 127) / 255) as u8;
+                        }
+                    }
+                    None => *target = *self.image.get_pixel(sx, sy),
                 }
             }
-        });
+        }
     }
-}
 
-/// Draw the CAPTCHA text on the image
-fn draw_text(img: &mut RgbImage, text: &str, font_size: f32) {
-    let font = Font::try_from_bytes(FONT_DATA).expect("Error loading font");
-    let mut rng = rand::thread_rng();
+    /// Save the CAPTCHA image to a file
+    #[cfg(feature = "fs")]
+    pub fn save(&self, path: &str) -> Result<(), image::ImageError> {
+        match &self.rgba {
+            Some(rgba) => rgba.save(path),
+            None => self.image.save(path),
+        }
+    }
 
-    let scale = Scale::uniform(font_size);
-    let char_spacing = 8.0;
-    let mut total_width = 0.0;
+    /// Get the CAPTCHA image as PNG bytes
+    pub fn to_png_bytes(&self) -> Result<Vec<u8>, image::ImageError> {
+        let mut bytes = Vec::new();
+        let mut cursor = std::io::Cursor::new(&mut bytes);
+        match &self.rgba {
+            Some(rgba) => rgba.write_to(&mut cursor, image::ImageFormat::Png)?,
+            None => self.image.write_to(&mut cursor, image::ImageFormat::Png)?,
+        }
+        Ok(bytes)
+    }
 
-    for ch in text.chars() {
-        let glyph = font.glyph(ch).scaled(scale);
-        total_width += glyph.h_metrics().advance_width + char_spacing;
+    /// Encode the CAPTCHA image in one of the built-in formats
+    pub fn to_bytes(&self, format: ImageOutputFormat) -> Result<Vec<u8>, CaptchaError> {
+        self.encode_with(format.encoder().as_ref())
     }
-    total_width -= char_spacing;
 
-    let start_x = (img.width() as f32 - total_width) / 2.0;
-    let base_y = (img.height() as f32 / 2.0) + (font_size / 3.0);
+    /// Get the PNG image as a base64 string
+    pub fn to_base64(&self) -> Result<String, CaptchaError> {
+        Ok(self.encode_png()?.to_base64())
+    }
 
-    let mut current_x = start_x;
+    /// Get the PNG image as a `data:image/png;base64,...` URI for inlining in `<img src>`
+    pub fn to_data_uri(&self) -> Result<String, CaptchaError> {
+        Ok(self.encode_png()?.to_data_uri())
+    }
 
-    for ch in text.chars() {
-        let glyph = font.glyph(ch).scaled(scale);
-        let advance = glyph.h_metrics().advance_width;
+    /// Encode the CAPTCHA image with a custom [`Encoder`]
+    ///
+    /// The alpha channel is passed to [`Encoder::encode_rgba`] when the
+    /// background is not opaque.
+    pub fn encode_with<E: Encoder + ?Sized>(&self, encoder: &E) -> Result<Vec<u8>, CaptchaError> {
+        match &self.rgba {
+            Some(rgba) => encoder.encode_rgba(rgba),
+            None => encoder.encode(&self.image),
+        }
+    }
 
-        let rotation = rng.gen_range(-0.26..0.26);
-        let y_offset = base_y + rng.gen_range(-5.0..5.0);
-        let x_offset = current_x + rng.gen_range(-2.0..2.0);
+    fn encode_png(&self) -> Result<EncodedImage, CaptchaError> {
+        Ok(EncodedImage {
+            bytes: self.encode_with(&PngEncoder)?,
+            content_type: PngEncoder.content_type().to_string(),
+        })
+    }
 
-        let color = [
-            rng.gen_range(30..70),
-            rng.gen_range(30..70),
-            rng.gen_range(30..70),
-        ];
+    /// Encode the image so it fits in the configured `max_bytes`
+    ///
+    /// Plain PNG is tried first, with an alpha channel when the background is
+    /// not opaque. If it is over the budget, increasingly coarse color
+    /// quantization and then lower JPEG quality are tried until the payload
+    /// fits; check [`EncodedImage::content_type`] for the format that was used.
+    /// These smaller encodings are opaque, with a transparent background
+    /// flattened onto white as in [`Captcha::image`].
+    pub fn encode_within_budget(&self) -> Result<EncodedImage, CaptchaError> {
+        encode::encode_within_budget(&self.image, self.rgba.as_ref(), self.max_bytes)
+    }
 
-        let params = CharDrawParams {
-            x_offset,
-            y_offset,
-            rotation,
-            color,
-        };
+    /// Render the CAPTCHA as an SVG document
+    ///
+    /// Glyphs are emitted as vector outlines using the same layout,
+    /// interference lines, noise and wave distortion as the raster image.
+    pub fn to_svg_string(&self) -> String {
+        SvgRenderer.render(&self.scene)
+    }
 
-        draw_character(img, ch, params, &font, scale);
+    /// Render the CAPTCHA as a looping animated GIF
+    ///
+    /// Characters fade in and out and drift slightly, so no single frame
+    /// contains the complete code. Use at least as many `frames` as there are
+    /// characters so every character is fully visible in some frame; at least
+    /// two frames are always rendered.
+    pub fn to_gif_bytes(&self, frames: u32, delay_ms: u32) -> Result<Vec<u8>, CaptchaError> {
+        GifRenderer { frames, delay_ms }.render(&self.scene)
+    }
 
-        current_x += advance + char_spacing;
+    /// Render the CAPTCHA as ASCII art for terminals and plain-text email
+    ///
+    /// The art is `columns` characters wide, each standing for a cell twice as
+    /// tall as wide, and shows the same layout, lines, noise and wave as the
+    /// image. 70 columns suit the default size.
+    pub fn to_ascii_art(&self, columns: usize) -> String {
+        AsciiRenderer { columns }.render(&self.scene)
     }
-}
 
-/// Add curved interference lines to the image
-fn add_interference_lines(img: &mut RgbImage, line_range: (usize, usize)) {
-    let mut rng = rand::thread_rng();
-    let width = img.width();
-    let height = img.height();
-
-    for _ in 0..rng.gen_range(line_range.0..line_range.1) {
-        let color = Rgb([
-            rng.gen_range(180..210),
-            rng.gen_range(180..210),
-            rng.gen_range(180..210),
-        ]);
-
-        let start_y = rng.gen_range(0..height) as f32;
-        let amplitude = rng.gen_range(8.0..12.0);
-        let frequency = rng.gen_range(0.02..0.04);
-        let thickness = 1;
-
-        for x in 0..width {
-            let y = start_y + (x as f32 * frequency).sin() * amplitude;
-
-            for dy in -thickness..=thickness {
-                let py = (y as i32 + dy).max(0).min(height as i32 - 1) as u32;
-                if x < width && py < height {
-                    img.put_pixel(x, py, color);
-                }
-            }
-        }
+    /// Render the image as Unicode Braille patterns for terminals without image support
+    ///
+    /// The rendered image, noise and effects included, is scaled to `columns`
+    /// characters of 2×4 dots each, which are square in most terminal fonts.
+    pub fn to_braille(&self, columns: usize) -> String {
+        render::braille(&self.image, columns)
     }
 }
 
-/// Add random noise dots to the image
-fn add_noise_dots(img: &mut RgbImage, count: usize) {
-    let mut rng = rand::thread_rng();
-    let width = img.width();
-    let height = img.height();
-
-    for _ in 0..count {
-        let x = rng.gen_range(0..width);
-        let y = rng.gen_range(0..height);
-
-        let color = if rng.gen_bool(0.5) {
-            Rgb([
-                rng.gen_range(200..230),
-                rng.gen_range(200..230),
-                rng.gen_range(200..230),
-            ])
-        } else {
-            Rgb([
-                rng.gen_range(80..140),
-                rng.gen_range(80..140),
-                rng.gen_range(80..140),
-            ])
-        };
-
-        img.put_pixel(x, y, color);
-
-        if rng.gen_bool(0.2) {
-            for dx in -1..=1 {
-                for dy in -1..=1 {
-                    let nx = (x as i32 + dx).max(0).min(width as i32 - 1) as u32;
-                    let ny = (y as i32 + dy).max(0).min(height as i32 - 1) as u32;
-                    if rng.gen_bool(0.3) {
-                        img.put_pixel(nx, ny, color);
-                    }
-                }
-            }
-        }
+impl Default for Captcha {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-/// Apply wave distortion to the image
-fn add_wave_distortion(img: &mut RgbImage, amplitude_range: (f32, f32)) -> RgbImage {
-    let mut rng = rand::thread_rng();
-    let width = img.width();
-    let height = img.height();
-    let mut new_img = create_background(width, height);
-
-    let amplitude = rng.gen_range(amplitude_range.0..amplitude_range.1);
-    let frequency = rng.gen_range(0.06..0.09);
-
-    for y in 0..height {
-        for x in 0..width {
-            let offset = (y as f32 * frequency).sin() * amplitude;
-            let src_x = (x as i32 + offset as i32).max(0).min(width as i32 - 1) as u32;
-
-            let pixel = img.get_pixel(src_x, y);
-            new_img.put_pixel(x, y, *pixel);
-        }
+/// Pick the code and lay out the scene it is rendered from
+fn prepare<R: Rng + ?Sized>(
+    config: &CaptchaConfig,
+    rng: &mut R,
+) -> Result<(String, Scene), CaptchaError> {
+    if config.charset.is_empty() {
+        return Err(CaptchaError::EmptyCharset);
     }
-
-    new_img
+    let fonts = font::load_fonts(&config.fonts)?;
+    let code = generate_code(&config.charset, config.code_length, rng);
+    let scene = Scene::generate(&code, config, fonts, rng);
+    Ok((code, scene))
 }
 
-/// Generate a complete CAPTCHA image from a code string
-fn generate_captcha_image(code: &str, config: &CaptchaConfig) -> RgbImage {
-    let mut img = create_background(config.width, config.height);
-    draw_text(&mut img, code, config.font_size);
-    add_interference_lines(&mut img, config.interference_lines);
-    add_noise_dots(&mut img, config.noise_dots);
-    add_wave_distortion(&mut img, config.wave_amplitude)
+/// Generate a random CAPTCHA code from the characters in `charset`
+fn generate_code<R: Rng + ?Sized>(charset: &str, len: usize, rng: &mut R) -> String {
+    let chars: Vec<char> = charset.chars().collect();
+    (0..len)
+        .map(|_| chars[portable::index(rng, chars.len())])
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn test_generate_code() {
-        let code = generate_code(6);
+        let charset = CharsetPreset::Default.chars();
+        let code = generate_code(&charset, 6, &mut rand::thread_rng());
         assert_eq!(code.len(), 6);
         assert!(code
             .chars()
             .all(|c| "23456789ABCDEFGHJKLMNPQRSTUVWXYZ".contains(c)));
     }
 
+    #[test]
+    fn test_empty_charset() {
+        let config = CaptchaConfig {
+            charset: charset::without_confusables("0O", charset::Strictness::General),
+            ..Default::default()
+        };
+        let error = Captcha::try_with_config(config).unwrap_err();
+        assert!(matches!(error, CaptchaError::EmptyCharset));
+        assert_eq!(error.message_key(), MessageKey::InvalidConfig);
+    }
+
+    #[test]
+    fn test_charset_preset() {
+        let config = CaptchaConfig {
+            charset: CharsetPreset::from_name("numeric").unwrap().chars(),
+            code_length: 12,
+            ..Default::default()
+        };
+        let captcha = Captcha::with_config(config);
+        assert!(captcha.code.chars().all(|c| c.is_ascii_digit()));
+    }
+
     #[test]
     fn test_captcha_creation() {
         let captcha = Captcha::new();
@@ -350,4 +542,283 @@ mod tests {
         assert_eq!(captcha.image.width(), 300);
         assert_eq!(captcha.image.height(), 120);
     }
+
+    #[test]
+    fn test_custom_fonts() {
+        let config = CaptchaConfig {
+            fonts: vec![
+                FontSource::Embedded,
+                FontSource::from_bytes(font::FONT_DATA),
+            ],
+            random_font_per_char: true,
+            ..Default::default()
+        };
+        let captcha = Captcha::try_with_config(config).unwrap();
+        assert_eq!(captcha.code.len(), 6);
+
+        let config = CaptchaConfig {
+            fonts: vec![FontSource::from_bytes(vec![0u8; 4])],
+            ..Default::default()
+        };
+        assert!(Captcha::try_with_config(config).is_err());
+    }
+
+    #[test]
+    fn test_quantization_effect() {
+        let config = CaptchaConfig {
+            quantization: Some(Quantization {
+                colors: 8,
+                dithering: Dithering::FloydSteinberg,
+            }),
+            ..Default::default()
+        };
+        let captcha = Captcha::with_config(config);
+        let colors: std::collections::HashSet<_> = captcha.image.pixels().collect();
+        assert!(colors.len() <= 8);
+    }
+
+    #[test]
+    fn test_transparent_background() {
+        let config = CaptchaConfig {
+            theme: ColorTheme {
+                background: Background::Transparent,
+                ..Default::default()
+            },
+            seed: Some(4),
+            ..Default::default()
+        };
+        let captcha = Captcha::with_config(config);
+        let rgba = captcha.rgba_image().unwrap();
+        assert!(rgba.pixels().any(|p| p[3] == 0));
+        assert!(rgba.pixels().any(|p| p[3] == 255));
+        assert!(captcha.to_dynamic_image().color().has_alpha());
+        let encoded = captcha.encode_within_budget().unwrap();
+        assert_eq!(encoded.bytes, captcha.to_png_bytes().unwrap());
+
+        let opaque = Captcha::with_config(CaptchaConfig {
+            theme: ColorTheme {
+                background: Background::Solid(image::Rgba([20, 20, 30, 255])),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert!(opaque.rgba_image().is_none());
+        let background = opaque
+            .image
+            .pixels()
+            .filter(|p| p.0 == [20, 20, 30])
+            .count();
+        assert!(background > opaque.image.len() / 3 / 2);
+    }
+
+    #[test]
+    fn test_composite_onto() {
+        let captcha = Captcha::with_config(CaptchaConfig {
+            theme: ColorTheme {
+                background: Background::Transparent,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let mut canvas = RgbImage::from_pixel(400, 200, image::Rgb([0, 0, 255]));
+        captcha.composite_onto(&mut canvas, 300, -20);
+
+        // Untouched outside the stamp and showing through the transparent background
+        assert_eq!(canvas.get_pixel(10, 10).0, [0, 0, 255]);
+        let blue = (300..400)
+            .flat_map(|x| (0..80).map(move |y| (x, y)))
+            .filter(|&(x, y)| canvas.get_pixel(x, y).0 == [0, 0, 255])
+            .count();
+        assert!(blue > 100 * 80 / 2);
+        assert!((300..400).any(|x| (0..80).any(|y| canvas.get_pixel(x, y).0 != [0, 0, 255])));
+
+        let opaque = Captcha::new();
+        let mut canvas = RgbImage::new(300, 120);
+        opaque.composite_onto(&mut canvas, 10, 10);
+        assert_eq!(canvas.get_pixel(10, 10), opaque.image.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_streaming_png_matches_raster() {
+        for background in [Background::Noise, Background::Transparent] {
+            let config = CaptchaConfig {
+                seed: Some(8),
+                theme: ColorTheme {
+                    background,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut bytes = Vec::new();
+            let code = Captcha::write_png_streaming(config.clone(), &mut bytes).unwrap();
+            let captcha = Captcha::with_config(config);
+            assert_eq!(code, captcha.code);
+
+            let streamed = image::load_from_memory(&bytes).unwrap();
+            assert_eq!(streamed, captcha.to_dynamic_image());
+        }
+    }
+
+    #[test]
+    fn test_streaming_png_writer_error() {
+        /// Accepts the PNG signature and header, then fails
+        struct Failing(usize);
+
+        impl Write for Failing {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.0 >= 64 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        "closed",
+                    ));
+                }
+                self.0 += buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let config = CaptchaConfig {
+            width: 1200,
+            height: 400,
+            seed: Some(2),
+            ..Default::default()
+        };
+        let error = Captcha::write_png_streaming(config, Failing(0)).unwrap_err();
+        assert!(matches!(error, CaptchaError::Image(_)), "{:?}", error);
+        assert_ne!(error.message_key(), MessageKey::FontUnreadable);
+    }
+
+    #[test]
+    fn test_effects() {
+        let config = CaptchaConfig {
+            seed: Some(3),
+            noise_dots: 0,
+            ..Default::default()
+        };
+        let filled = Captcha::with_config(config.clone());
+        let hollow = Captcha::with_config(CaptchaConfig {
+            effects: vec![Effect::Outline(2.0)],
+            ..config.clone()
+        });
+        // The strokes themselves are left empty inside the outline
+        let emptied = filled
+            .image
+            .pixels()
+            .zip(hollow.image.pixels())
+            .filter(|(f, h)| f.0[0] < 100 && h.0[0] > 200)
+            .count();
+        assert!(emptied > 100);
+
+        let config = CaptchaConfig {
+            effects: vec![
+                Effect::Ripple {
+                    amplitude: 2.0,
+                    wavelength: 30.0,
+                },
+                Effect::Custom(CustomEffect::new(|image, _| {
+                    image.put_pixel(0, 0, image::Rgba([1, 2, 3, 255]))
+                })),
+            ],
+            ..config
+        };
+        let captcha = Captcha::with_config(config.clone());
+        assert_eq!(captcha.image.get_pixel(0, 0).0, [1, 2, 3]);
+
+        let mut bytes = Vec::new();
+        Captcha::write_png_streaming(config, &mut bytes).unwrap();
+        assert_eq!(
+            image::load_from_memory(&bytes).unwrap(),
+            captcha.to_dynamic_image()
+        );
+    }
+
+    #[test]
+    fn test_dark_theme() {
+        let config = CaptchaConfig {
+            theme: ColorTheme::dark(),
+            ..Default::default()
+        };
+        let captcha = Captcha::with_config(config);
+        let dark = captcha.image.pixels().filter(|p| p.0[0] < 60).count();
+        assert!(dark > captcha.image.len() / 3 / 2);
+        assert!(captcha.image.pixels().any(|p| p.0[0] >= 200));
+    }
+
+    #[test]
+    fn test_generate_batch() {
+        let config = CaptchaConfig {
+            seed: Some(11),
+            ..Default::default()
+        };
+        let a = Captcha::generate_batch(4, &config).unwrap();
+        let b = Captcha::generate_batch(4, &config).unwrap();
+        assert_eq!(a.len(), 4);
+        for (a, b) in a.iter().zip(&b) {
+            assert_eq!(a.code, b.code);
+            assert_eq!(a.image.as_raw(), b.image.as_raw());
+        }
+        assert_ne!(a[0].image.as_raw(), a[1].image.as_raw());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_dedicated_pool() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let config = CaptchaConfig {
+            seed: Some(11),
+            ..Default::default()
+        };
+        let batch = Captcha::generate_batch_in(3, &config, &pool).unwrap();
+        let global = Captcha::generate_batch(3, &config).unwrap();
+        for (a, b) in batch.iter().zip(&global) {
+            assert_eq!(a.image.as_raw(), b.image.as_raw());
+        }
+
+        let single = Captcha::try_with_config_in(config.clone(), &pool).unwrap();
+        assert_eq!(single.code, Captcha::with_config(config).code);
+    }
+
+    #[test]
+    fn test_seeded_generation_is_reproducible() {
+        let config = CaptchaConfig {
+            seed: Some(42),
+            ..Default::default()
+        };
+        let a = Captcha::with_config(config.clone());
+        let b = Captcha::with_config(config);
+        assert_eq!(a.code, b.code);
+        assert_eq!(a.image.as_raw(), b.image.as_raw());
+
+        let c =
+            Captcha::with_config_and_rng(CaptchaConfig::default(), &mut StdRng::seed_from_u64(7));
+        let d =
+            Captcha::with_config_and_rng(CaptchaConfig::default(), &mut StdRng::seed_from_u64(7));
+        assert_eq!(c.code, d.code);
+        assert_eq!(c.image.as_raw(), d.image.as_raw());
+    }
+
+    #[test]
+    fn test_seeded_output_is_pinned() {
+        use sha2::{Digest, Sha256};
+
+        // Any change here breaks cross-platform and cross-version caches of seeded output
+        let captcha = Captcha::with_config(CaptchaConfig {
+            seed: Some(2024),
+            ..Default::default()
+        });
+        let digest = Sha256::digest(captcha.image.as_raw());
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(captcha.code, "KUJFM8");
+        assert_eq!(
+            hex,
+            "6f6d93f7f81fc1d3ffaf07f48b3e409c550e1d3480d88db03e4848a50596c75f"
+        );
+    }
 }
@@ -1,14 +1,216 @@
-use captcha_generator::Captcha;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-fn main() {
-    // Generate a CAPTCHA with default settings
-    let captcha = Captcha::new();
+use captcha_generator::{Captcha, CaptchaConfig, CharsetPreset, Difficulty, ImageOutputFormat};
+use clap::builder::RangedU64ValueParser;
+use clap::{value_parser, Parser, ValueEnum};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 
-    println!("Generated CAPTCHA code: {}", captcha.code);
+/// Generate CAPTCHA images, one at a time or as a labelled dataset
+#[derive(Debug, Parser)]
+#[command(name = "captcha-gen", version)]
+struct Args {
+    /// Image width in pixels, from 16 to 8192
+    #[arg(long, default_value_t = 280, value_parser = value_parser!(u32).range(16..=8192))]
+    width: u32,
+    /// Image height in pixels, from 16 to 8192
+    #[arg(long, default_value_t = 100, value_parser = value_parser!(u32).range(16..=8192))]
+    height: u32,
+    /// Number of characters in the code, from 1 to 64
+    #[arg(short, long, default_value_t = 6, value_parser = RangedU64ValueParser::<usize>::new().range(1..=64))]
+    length: usize,
+    /// How hard the text is to read
+    #[arg(short, long, value_enum, default_value_t = DifficultyArg::Medium)]
+    difficulty: DifficultyArg,
+    /// Character set preset: default, elderly, numeric, letters or mixed-case
+    #[arg(long, default_value = "default", value_parser = parse_charset)]
+    charset: CharsetPreset,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Png)]
+    format: Format,
+    /// JPEG quality from 1 to 100
+    #[arg(long, default_value_t = 85)]
+    quality: u8,
+    /// Output file, or the output directory with --count (default: captcha.<ext> or .)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Generate this many images named captcha_0001.<ext>, ... plus a manifest
+    #[arg(short = 'n', long)]
+    count: Option<usize>,
+    /// Manifest format mapping file names to codes in batch mode
+    #[arg(long, value_enum, default_value_t = Manifest::Csv)]
+    manifest: Manifest,
+    /// Seed for reproducible runs
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DifficultyArg {
+    Easy,
+    Medium,
+    Hard,
+    Extreme,
+}
 
-    // Save the image
-    match captcha.save("captcha.png") {
-        Ok(_) => println!("CAPTCHA saved as captcha.png"),
-        Err(e) => eprintln!("Failed to save CAPTCHA: {}", e),
+impl From<DifficultyArg> for Difficulty {
+    fn from(arg: DifficultyArg) -> Self {
+        match arg {
+            DifficultyArg::Easy => Difficulty::Easy,
+            DifficultyArg::Medium => Difficulty::Medium,
+            DifficultyArg::Hard => Difficulty::Hard,
+            DifficultyArg::Extreme => Difficulty::Extreme,
+        }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Png,
+    Jpeg,
+    Webp,
+    Svg,
+    Gif,
+    Ascii,
+    Braille,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::Webp => "webp",
+            Format::Svg => "svg",
+            Format::Gif => "gif",
+            Format::Ascii | Format::Braille => "txt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Manifest {
+    Csv,
+    Json,
+}
+
+fn parse_charset(name: &str) -> Result<CharsetPreset, String> {
+    CharsetPreset::from_name(name).ok_or_else(|| {
+        let names: Vec<_> = CharsetPreset::ALL.iter().map(|p| p.name()).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("captcha-gen: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = CaptchaConfig::with_difficulty(args.difficulty.into());
+    config.width = args.width;
+    config.height = args.height;
+    config.code_length = args.length;
+    config.charset = args.charset.chars();
+
+    let Some(count) = args.count else {
+        config.seed = args.seed;
+        let path = args
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("captcha.{}", args.format.extension())));
+        let code = write_captcha(&args, config, &path)?;
+        println!("{}\t{}", path.display(), code);
+        return Ok(());
+    };
+
+    let dir = args.output.clone().unwrap_or_else(|| PathBuf::from("."));
+    fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    let digits = count.to_string().len().max(4);
+    let mut labels = Vec::with_capacity(count);
+    // Per-image seeds are drawn like those of `Captcha::generate_batch`, so runs
+    // with different seeds share no images, without holding the batch in memory
+    let mut seeds = args.seed.map(ChaCha12Rng::seed_from_u64);
+    for i in 1..=count {
+        config.seed = seeds.as_mut().map(|rng| rng.gen());
+        let name = format!(
+            "captcha_{:0width$}.{}",
+            i,
+            args.format.extension(),
+            width = digits
+        );
+        let code = write_captcha(&args, config.clone(), &dir.join(&name))?;
+        labels.push((name, code));
+    }
+
+    let manifest = match args.manifest {
+        Manifest::Csv => {
+            let mut csv = String::from("file,code\n");
+            for (name, code) in &labels {
+                csv.push_str(&format!("{},{}\n", name, code));
+            }
+            ("labels.csv", csv)
+        }
+        Manifest::Json => {
+            let entries: Vec<_> = labels
+                .iter()
+                .map(|(name, code)| serde_json::json!({ "file": name, "code": code }))
+                .collect();
+            (
+                "labels.json",
+                serde_json::to_string_pretty(&entries)? + "\n",
+            )
+        }
+    };
+    write_file(&dir.join(manifest.0), manifest.1)?;
+    println!(
+        "wrote {} CAPTCHAs and {} to {}",
+        count,
+        manifest.0,
+        dir.display()
+    );
+    Ok(())
+}
+
+/// Generate one CAPTCHA, write it to `path` and return its code
+fn write_captcha(
+    args: &Args,
+    config: CaptchaConfig,
+    path: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let captcha = Captcha::try_with_config(config)?;
+    let bytes = match args.format {
+        Format::Png => captcha.to_bytes(ImageOutputFormat::Png)?,
+        Format::Jpeg => captcha.to_bytes(ImageOutputFormat::Jpeg {
+            quality: args.quality,
+        })?,
+        Format::Webp => captcha.to_bytes(ImageOutputFormat::WebP)?,
+        Format::Svg => captcha.to_svg_string().into_bytes(),
+        // Twice as many frames as characters so each is fully shown at some point
+        Format::Gif => captcha.to_gif_bytes(captcha.code.chars().count() as u32 * 2, 120)?,
+        // A column per 4 pixels of width, so each cell covers 4×8 pixels and
+        // the default width gives 70 columns
+        Format::Ascii => captcha
+            .to_ascii_art(captcha.image.width() as usize / 4)
+            .into_bytes(),
+        // A character per 4 pixels of width too: its 2×4 dots cover 4×8
+        // pixels, 2×2 pixels per dot
+        Format::Braille => captcha
+            .to_braille(captcha.image.width() as usize / 4)
+            .into_bytes(),
+    };
+    write_file(path, bytes)?;
+    Ok(captcha.code)
+}
+
+/// Write `contents` to `path`, naming the path in the error
+fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(path, contents).map_err(|e| format!("cannot write {}: {}", path.display(), e).into())
+}
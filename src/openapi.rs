@@ -0,0 +1,127 @@
+//! OpenAPI description of the web integration, for generating client SDKs
+//!
+//! [`CaptchaApi`] describes the image and widget routes as mounted at
+//! `/captcha.png` and `/widget`, and the [`Problem`] body of every error.
+//! Merge it into the application's own description, nesting it under a
+//! prefix if the routes are mounted elsewhere:
+//!
+//! ```
+//! use captcha_generator::openapi::{CaptchaAnswer, CaptchaApi};
+//! use captcha_generator::problem::Problem;
+//! use utoipa::OpenApi;
+//!
+//! /// Create an account
+//! #[utoipa::path(
+//!     post,
+//!     path = "/signup",
+//!     request_body = CaptchaAnswer,
+//!     responses(
+//!         (status = 200, description = "Welcome"),
+//!         (status = 400, response = Problem),
+//!         (status = 429, response = Problem),
+//!     )
+//! )]
+//! async fn signup() {}
+//!
+//! #[derive(OpenApi)]
+//! #[openapi(paths(signup))]
+//! struct AppApi;
+//!
+//! let mut api = AppApi::openapi();
+//! api.merge(CaptchaApi::openapi());
+//! assert!(api.paths.paths.contains_key("/captcha.png"));
+//! ```
+//!
+//! Routes guarded by [`VerifiedCaptcha`](crate::web::VerifiedCaptcha) belong
+//! to the application, so they are not described here; [`CaptchaAnswer`] and
+//! the `Problem` response describe their CAPTCHA fields and rejections.
+
+use utoipa::{OpenApi, ToSchema};
+
+use crate::problem::Problem;
+
+/// The OpenAPI description of the CAPTCHA routes
+#[derive(OpenApi)]
+#[openapi(
+    paths(captcha_image, widget),
+    components(schemas(Problem, CaptchaAnswer), responses(Problem)),
+    tags((name = "captcha", description = "CAPTCHA images and their answer tokens"))
+)]
+pub struct CaptchaApi;
+
+/// The CAPTCHA fields of a JSON or form body checked by `VerifiedCaptcha`
+///
+/// Either can also be sent in the `X-Captcha-Token` and `X-Captcha-Answer`
+/// headers, and the token in the `captcha_token` cookie set by the image route.
+#[derive(Debug, Clone, Default, ToSchema)]
+pub struct CaptchaAnswer {
+    /// The token returned with the image
+    pub captcha_token: Option<String>,
+    /// The characters the user read from the image
+    pub captcha_answer: Option<String>,
+}
+
+/// A fresh CAPTCHA image
+///
+/// Only described here; the route is served by `captcha_route` of the axum
+/// and actix modules.
+#[utoipa::path(
+    get,
+    path = "/captcha.png",
+    tag = "captcha",
+    responses(
+        (
+            status = 200,
+            description = "The CAPTCHA image",
+            content_type = "image/png",
+            headers(
+                ("x-captcha-token" = String, description = "The answer token to send back with the answer"),
+                ("set-cookie" = String, description = "The same token in the `captcha_token` cookie"),
+            )
+        ),
+        (status = 429, response = Problem),
+        (status = 500, response = Problem),
+    )
+)]
+#[allow(dead_code)]
+fn captcha_image() {}
+
+/// The embeddable widget script
+///
+/// Placed inside a form with `<script src="/widget"></script>`, it inserts the
+/// image, a refresh button, the `captcha_answer` field and a hidden
+/// `captcha_token` field. Served by `widget_route`.
+#[utoipa::path(
+    get,
+    path = "/widget",
+    tag = "captcha",
+    responses(
+        (status = 200, description = "The widget script", content_type = "text/javascript"),
+    )
+)]
+#[allow(dead_code)]
+fn widget() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::{ANSWER_FIELD, TOKEN_COOKIE, TOKEN_FIELD, TOKEN_HEADER};
+
+    #[test]
+    fn test_description() {
+        let api = CaptchaApi::openapi();
+        let json = api.to_json().unwrap();
+        assert!(api.paths.paths.contains_key("/captcha.png"));
+        assert!(api.paths.paths.contains_key("/widget"));
+        assert!(json.contains(&format!("\"{}\"", TOKEN_HEADER)));
+        assert!(json.contains(&format!("`{}` cookie", TOKEN_COOKIE)));
+        assert!(json.contains(Problem::CONTENT_TYPE));
+
+        let components = api.components.unwrap();
+        assert!(components.schemas.contains_key("Problem"));
+        assert!(components.responses.contains_key("Problem"));
+        let schema = serde_json::to_string(&components.schemas["CaptchaAnswer"]).unwrap();
+        assert!(schema.contains(TOKEN_FIELD));
+        assert!(schema.contains(ANSWER_FIELD));
+    }
+}
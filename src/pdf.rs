@@ -0,0 +1,316 @@
+//! Printable PDFs of CAPTCHAs
+//!
+//! For printed registration forms and vouchers, [`Captcha::to_pdf`] places one
+//! CAPTCHA on a page of exactly its size, and [`batch_to_pdf`] lays a batch
+//! out in a grid on pages of a given paper size:
+//!
+//! ```
+//! use captcha_generator::pdf::{batch_to_pdf, PdfLayout};
+//! use captcha_generator::{Captcha, CaptchaConfig};
+//!
+//! let single = Captcha::new().to_pdf(50.0);
+//! assert!(single.starts_with(b"%PDF-"));
+//!
+//! let batch = Captcha::generate_batch(12, &CaptchaConfig::default()).unwrap();
+//! let sheet = batch_to_pdf(&batch, &PdfLayout::default());
+//! assert!(sheet.ends_with(b"%%EOF\n"));
+//! ```
+
+use std::fmt::Write;
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+use crate::Captcha;
+
+/// Points per millimetre; PDF measures in points of 1/72 inch
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+/// Paper size and the placement of CAPTCHAs on it, in millimetres
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdfLayout {
+    /// Page width
+    pub page_width: f32,
+    /// Page height
+    pub page_height: f32,
+    /// Empty border around the grid
+    pub margin: f32,
+    /// Space between neighbouring CAPTCHAs
+    pub gap: f32,
+    /// Printed width of each CAPTCHA; the height keeps the image's aspect ratio
+    pub captcha_width: f32,
+}
+
+impl Default for PdfLayout {
+    /// A4 portrait with 55 mm wide CAPTCHAs
+    fn default() -> Self {
+        Self {
+            page_width: 210.0,
+            page_height: 297.0,
+            margin: 15.0,
+            gap: 5.0,
+            captcha_width: 55.0,
+        }
+    }
+}
+
+impl PdfLayout {
+    /// US Letter portrait with 55 mm wide CAPTCHAs
+    pub fn letter() -> Self {
+        Self {
+            page_width: 215.9,
+            page_height: 279.4,
+            ..Self::default()
+        }
+    }
+
+    /// Columns and rows of CAPTCHAs of `height` mm that fit on a page, at least one each
+    fn grid(&self, height: f32) -> (usize, usize) {
+        let fit = |space: f32, size: f32| {
+            let space = space - 2.0 * self.margin + self.gap;
+            ((space / (size + self.gap)).floor() as usize).max(1)
+        };
+        (
+            fit(self.page_width, self.captcha_width),
+            fit(self.page_height, height),
+        )
+    }
+}
+
+impl Captcha {
+    /// A single-page PDF holding the CAPTCHA at `width_mm` millimetres wide
+    ///
+    /// The page is exactly the size of the image, which keeps its aspect ratio.
+    pub fn to_pdf(&self, width_mm: f32) -> Vec<u8> {
+        let (width, height) = self.image.dimensions();
+        let height_mm = width_mm * height as f32 / width as f32;
+        batch_to_pdf(
+            std::slice::from_ref(self),
+            &PdfLayout {
+                page_width: width_mm,
+                page_height: height_mm,
+                margin: 0.0,
+                gap: 0.0,
+                captcha_width: width_mm,
+            },
+        )
+    }
+}
+
+/// A PDF with `captchas` laid out in rows, filling as many pages as needed
+///
+/// The grid is sized for the first CAPTCHA; all of them are printed at
+/// `layout.captcha_width` and keep their own aspect ratio. Transparent
+/// backgrounds stay transparent.
+pub fn batch_to_pdf(captchas: &[Captcha], layout: &PdfLayout) -> Vec<u8> {
+    let aspect = |captcha: &Captcha| {
+        let (width, height) = captcha.image.dimensions();
+        height as f32 / width as f32
+    };
+    let cell_height = captchas
+        .first()
+        .map_or(layout.captcha_width, |c| layout.captcha_width * aspect(c));
+    let (columns, rows) = layout.grid(cell_height);
+    let per_page = columns * rows;
+    let page_count = captchas.len().div_ceil(per_page).max(1);
+
+    let mut pdf = PdfWriter::default();
+    let catalog = pdf.reserve();
+    let pages = pdf.reserve();
+    let mut page_ids = Vec::with_capacity(page_count);
+    for page in 0..page_count {
+        let on_page = captchas.iter().skip(page * per_page).take(per_page);
+        let mut content = String::new();
+        let mut resources = String::new();
+        for (i, captcha) in on_page.enumerate() {
+            let image = pdf.image(captcha);
+            let (column, row) = (i % columns, i / columns);
+            let x = layout.margin + column as f32 * (layout.captcha_width + layout.gap);
+            // PDF pages grow upwards, so rows are placed from the top edge down
+            let top = layout.margin + row as f32 * (cell_height + layout.gap);
+            let height = layout.captcha_width * aspect(captcha);
+            let y = layout.page_height - top - height;
+            let _ = writeln!(
+                content,
+                "q {:.3} 0 0 {:.3} {:.3} {:.3} cm /Im{} Do Q",
+                layout.captcha_width * POINTS_PER_MM,
+                height * POINTS_PER_MM,
+                x * POINTS_PER_MM,
+                y * POINTS_PER_MM,
+                i
+            );
+            let _ = write!(resources, "/Im{} {} 0 R ", i, image);
+        }
+        let contents = pdf.stream("", content.as_bytes());
+        page_ids.push(pdf.add(format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {:.3} {:.3}] /Resources << /XObject << {}>> >> /Contents {} 0 R >>",
+            pages,
+            layout.page_width * POINTS_PER_MM,
+            layout.page_height * POINTS_PER_MM,
+            resources,
+            contents
+        ).into_bytes()));
+    }
+
+    let kids: Vec<_> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    pdf.set(
+        pages,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            page_ids.len()
+        )
+        .into_bytes(),
+    );
+    pdf.set(
+        catalog,
+        format!("<< /Type /Catalog /Pages {} 0 R >>", pages).into_bytes(),
+    );
+    pdf.finish(catalog)
+}
+
+/// Numbered PDF objects, serialized with their cross-reference table at the end
+#[derive(Default)]
+struct PdfWriter {
+    objects: Vec<Vec<u8>>,
+}
+
+impl PdfWriter {
+    /// Number a new object whose body is set later
+    fn reserve(&mut self) -> usize {
+        self.add(Vec::new())
+    }
+
+    fn add(&mut self, body: Vec<u8>) -> usize {
+        self.objects.push(body);
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, body: Vec<u8>) {
+        self.objects[id - 1] = body;
+    }
+
+    /// Add a zlib-compressed stream object with extra dictionary `entries`
+    fn stream(&mut self, entries: &str, data: &[u8]) -> usize {
+        let data = compress_to_vec_zlib(data, 6);
+        let mut body = format!(
+            "<< {}/Filter /FlateDecode /Length {} >>\nstream\n",
+            entries,
+            data.len()
+        )
+        .into_bytes();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\nendstream");
+        self.add(body)
+    }
+
+    /// Add the image of `captcha`, with its alpha channel as a soft mask when it has one
+    fn image(&mut self, captcha: &Captcha) -> usize {
+        let (width, height) = captcha.image.dimensions();
+        let dictionary = |color_space: &str, mask: &str| {
+            format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {} /BitsPerComponent 8 {}",
+                width, height, color_space, mask
+            )
+        };
+        let mask = captcha.rgba_image().map(|rgba| {
+            let alpha: Vec<u8> = rgba.pixels().map(|p| p.0[3]).collect();
+            let id = self.stream(&dictionary("/DeviceGray", ""), &alpha);
+            format!("/SMask {} 0 R ", id)
+        });
+        self.stream(
+            &dictionary("/DeviceRGB", mask.as_deref().unwrap_or("")),
+            captcha.image.as_raw(),
+        )
+    }
+
+    fn finish(self, root: usize) -> Vec<u8> {
+        // The binary comment marks the file as binary for transfer tools
+        let mut out = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, body) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(table, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.objects.len() + 1,
+            root,
+            xref
+        );
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Background, CaptchaConfig, ColorTheme};
+
+    fn count(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .filter(|w| *w == needle)
+            .count()
+    }
+
+    /// Every cross-reference entry points at the start of its object
+    fn assert_xref_valid(pdf: &[u8]) {
+        let text = String::from_utf8_lossy(pdf);
+        let xref = text.rfind("xref\n").unwrap();
+        for (id, line) in text[xref..].lines().skip(3).enumerate() {
+            let Some(offset) = line.strip_suffix(" 00000 n ") else {
+                break;
+            };
+            let offset: usize = offset.parse().unwrap();
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj", id + 1).as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_single_page() {
+        let pdf = Captcha::new().to_pdf(70.0);
+        assert!(pdf.starts_with(b"%PDF-1.4"));
+        assert_eq!(count(&pdf, b"/Type /Page "), 1);
+        // 70 mm by 25 mm in points
+        assert_eq!(count(&pdf, b"/MediaBox [0 0 198.425 70.866]"), 1);
+        assert_xref_valid(&pdf);
+    }
+
+    #[test]
+    fn test_batch_fills_pages() {
+        // Three columns and eleven rows of 55x19.6 mm fit on A4
+        let layout = PdfLayout::default();
+        assert_eq!(layout.grid(55.0 * 100.0 / 280.0), (3, 11));
+
+        let batch = Captcha::generate_batch(40, &CaptchaConfig::default()).unwrap();
+        let pdf = batch_to_pdf(&batch, &layout);
+        assert_eq!(count(&pdf, b"/Type /Page "), 2);
+        assert_eq!(count(&pdf, b"/Subtype /Image"), 40);
+        assert_xref_valid(&pdf);
+
+        assert_eq!(count(&batch_to_pdf(&[], &layout), b"/Type /Page "), 1);
+    }
+
+    #[test]
+    fn test_transparent_background_gets_a_mask() {
+        let captcha = Captcha::with_config(CaptchaConfig {
+            theme: ColorTheme {
+                background: Background::Transparent,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let pdf = captcha.to_pdf(50.0);
+        assert_eq!(count(&pdf, b"/SMask"), 1);
+        assert_xref_valid(&pdf);
+    }
+}
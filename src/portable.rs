@@ -0,0 +1,41 @@
+//! Platform-independent math and sampling
+//!
+//! `f32::sin` and `f32::cos` call the platform's math library, whose results
+//! can differ in the last bit between targets, and `usize` ranges are sampled
+//! differently on 32- and 64-bit targets. Rendering goes through these helpers
+//! instead, so a seed produces byte-identical pixels on every platform.
+
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+
+/// The RNG behind seeded generation, named explicitly so its stream cannot change with `rand`
+pub(crate) fn seeded_rng(seed: u64) -> ChaCha12Rng {
+    ChaCha12Rng::seed_from_u64(seed)
+}
+
+/// The same RNG seeded from the operating system through `getrandom`
+///
+/// Used instead of `rand::thread_rng` so that every target, including
+/// `wasm32-unknown-unknown`, only needs a `getrandom` backend.
+pub(crate) fn entropy_rng() -> ChaCha12Rng {
+    ChaCha12Rng::from_entropy()
+}
+
+pub(crate) fn sin(x: f32) -> f32 {
+    libm::sinf(x)
+}
+
+pub(crate) fn cos(x: f32) -> f32 {
+    libm::cosf(x)
+}
+
+/// A random index below `len`, sampled the same way on 32- and 64-bit targets
+pub(crate) fn index<R: Rng + ?Sized>(rng: &mut R, len: usize) -> usize {
+    rng.gen_range(0..len as u32) as usize
+}
+
+/// A random count in `range`, sampled the same way on 32- and 64-bit targets
+pub(crate) fn count<R: Rng + ?Sized>(rng: &mut R, range: (usize, usize)) -> usize {
+    rng.gen_range(range.0 as u32..range.1 as u32) as usize
+}
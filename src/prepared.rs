@@ -0,0 +1,186 @@
+use std::f32::consts::FRAC_PI_2;
+use std::fmt;
+
+use rand::Rng;
+use rusttype::{Font, Scale};
+
+use crate::portable::{self, cos};
+use crate::render::{GlyphMetrics, Scene};
+use crate::{font, Captcha, CaptchaConfig, CaptchaError, Spacing};
+
+/// A configuration whose fonts are loaded, checked and measured
+///
+/// Created with [`CaptchaConfig::prepare`]. Generating from it renders the
+/// same CAPTCHAs as [`Captcha::try_with_config`], but never has to load a
+/// font and takes advance widths and ink rows from a table measured once.
+/// Only [`Spacing::Optical`] still measures the ink of each rotated glyph.
+#[derive(Clone)]
+pub struct PreparedConfig {
+    config: CaptchaConfig,
+    fonts: Vec<Font<'static>>,
+    metrics: GlyphMetrics,
+}
+
+impl CaptchaConfig {
+    /// Load the fonts, check that they can render the charset and measure every glyph
+    ///
+    /// Fails with [`CaptchaError::EmptyCharset`] if there is no character to
+    /// draw, with [`CaptchaError::MissingGlyphs`] if a font lacks a character
+    /// of the charset, which would otherwise be drawn as an empty box, and with
+    /// [`CaptchaError::TextTooWide`] if even a code of the narrowest characters
+    /// cannot fit in the width.
+    pub fn prepare(self) -> Result<PreparedConfig, CaptchaError> {
+        if self.charset.is_empty() {
+            return Err(CaptchaError::EmptyCharset);
+        }
+        let fonts = font::load_fonts(&self.fonts)?;
+        for (index, font) in fonts.iter().enumerate() {
+            let mut missing: Vec<char> = self
+                .charset
+                .chars()
+                .filter(|&ch| font.glyph(ch).id().0 == 0)
+                .collect();
+            if !missing.is_empty() {
+                missing.sort_unstable();
+                missing.dedup();
+                return Err(CaptchaError::MissingGlyphs {
+                    font: index,
+                    chars: missing,
+                });
+            }
+        }
+
+        let metrics = GlyphMetrics::measure(&fonts, &self.charset, Scale::uniform(self.font_size));
+        let gaps = self.code_length.saturating_sub(1) as f32;
+        let narrowest = match self.spacing {
+            Spacing::Advance(extra) => {
+                metrics.advance_range().0 * self.code_length as f32 + extra * gaps
+            }
+            Spacing::Optical(gap) => {
+                // Rotating by up to `rotation` keeps at least `cos(rotation)` of the upright ink width
+                let ink = metrics.ink_width_range().0 * cos(self.rotation.min(FRAC_PI_2));
+                ink * self.code_length as f32 + gap * gaps
+            }
+        };
+        if narrowest > self.width as f32 {
+            return Err(CaptchaError::TextTooWide {
+                needed: narrowest.ceil() as u32,
+                width: self.width,
+            });
+        }
+
+        Ok(PreparedConfig {
+            config: self,
+            fonts,
+            metrics,
+        })
+    }
+}
+
+impl PreparedConfig {
+    /// The configuration that was prepared
+    pub fn config(&self) -> &CaptchaConfig {
+        &self.config
+    }
+
+    /// Advance width in pixels of the widest character of the charset
+    pub fn max_glyph_width(&self) -> f32 {
+        self.metrics.advance_range().1
+    }
+
+    /// Generate a CAPTCHA, seeded by `config.seed` if it is set
+    pub fn generate(&self) -> Captcha {
+        match self.config.seed {
+            Some(seed) => self.generate_with_rng(&mut portable::seeded_rng(seed)),
+            None => self.generate_with_rng(&mut portable::entropy_rng()),
+        }
+    }
+
+    /// Generate a CAPTCHA drawing all randomness from `rng`
+    ///
+    /// Matches [`Captcha::try_with_config_and_rng`] for the same RNG state.
+    pub fn generate_with_rng<R: Rng + ?Sized>(&self, rng: &mut R) -> Captcha {
+        let code = crate::generate_code(&self.config.charset, self.config.code_length, rng);
+        let scene = Scene::generate_measured(
+            &code,
+            &self.config,
+            self.fonts.clone(),
+            Some(&self.metrics),
+            rng,
+        );
+        Captcha::render(&self.config, code, scene)
+    }
+}
+
+impl fmt::Debug for PreparedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreparedConfig")
+            .field("config", &self.config)
+            .field("fonts", &self.fonts.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FontSource, VerticalAnchor};
+
+    #[test]
+    fn test_prepared_matches_unprepared() {
+        for vertical in [VerticalAnchor::Top, VerticalAnchor::Bottom] {
+            let config = CaptchaConfig {
+                seed: Some(3),
+                alignment: crate::TextAlignment {
+                    vertical,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let prepared = config.clone().prepare().unwrap();
+            let a = prepared.generate();
+            let b = Captcha::with_config(config);
+            assert_eq!(a.code, b.code);
+            assert_eq!(a.image.as_raw(), b.image.as_raw());
+        }
+    }
+
+    #[test]
+    fn test_missing_glyphs() {
+        let config = CaptchaConfig {
+            charset: "\u{E001}AB\u{E000}\u{E001}".to_string(),
+            fonts: vec![FontSource::Embedded],
+            ..Default::default()
+        };
+        let err = config.prepare().unwrap_err();
+        assert!(matches!(
+            err,
+            CaptchaError::MissingGlyphs { font: 0, ref chars } if chars == &['\u{E000}', '\u{E001}']
+        ));
+    }
+
+    #[test]
+    fn test_empty_charset() {
+        let config = CaptchaConfig {
+            charset: String::new(),
+            ..Default::default()
+        };
+        assert!(matches!(config.prepare(), Err(CaptchaError::EmptyCharset)));
+    }
+
+    #[test]
+    fn test_text_too_wide() {
+        let config = CaptchaConfig {
+            width: 100,
+            code_length: 12,
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.prepare(),
+            Err(CaptchaError::TextTooWide { width: 100, .. })
+        ));
+
+        let prepared = CaptchaConfig::default().prepare().unwrap();
+        assert!(prepared.max_glyph_width() > 40.0);
+    }
+}
@@ -0,0 +1,275 @@
+//! Stable error codes and RFC 9457 problem details for HTTP APIs
+//!
+//! Every error type of the crate implements [`ApiError`], which gives it a
+//! machine-readable code that will not change between releases and the HTTP
+//! status it should be answered with. [`Problem`] turns any of them into an
+//! `application/problem+json` body (serializable with the `serde` feature):
+//!
+//! ```
+//! # #[cfg(feature = "tokens")] {
+//! use captcha_generator::problem::{ApiError, Problem};
+//! use captcha_generator::verify::VerifyError;
+//! use captcha_generator::Locale;
+//!
+//! let problem = Problem::new(&VerifyError::Expired, &Locale::Fr);
+//! assert_eq!(problem.code, "code_expired");
+//! assert_eq!(problem.status, 400);
+//! assert_eq!(VerifyError::Expired.code(), "code_expired");
+//! # }
+//! ```
+
+use crate::i18n::{MessageCatalog, MessageKey};
+#[cfg(feature = "tokens")]
+use crate::verify::VerifyError;
+use crate::CaptchaError;
+
+/// An error with a stable code for API consumers
+pub trait ApiError: std::error::Error {
+    /// A short snake_case identifier that is never renamed, e.g. `"code_mismatch"`
+    fn code(&self) -> &'static str;
+
+    /// The HTTP status code the error should be answered with
+    fn status(&self) -> u16;
+
+    /// The catalog key for the error's user-facing message
+    fn message_key(&self) -> MessageKey;
+}
+
+/// Problem details for an [`ApiError`], as described by RFC 9457
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(
+    feature = "utoipa",
+    derive(utoipa::ToSchema, utoipa::ToResponse),
+    response(
+        description = "The error as problem details",
+        content_type = "application/problem+json"
+    )
+)]
+pub struct Problem {
+    /// A URI identifying the problem type, `urn:captcha-generator:error:<code>`
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub problem_type: String,
+    /// The localized user-facing message
+    pub title: String,
+    /// The HTTP status code
+    pub status: u16,
+    /// The stable error code
+    pub code: &'static str,
+    /// The error's full description, left out for server errors so internals do not leak
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub detail: Option<String>,
+}
+
+impl Problem {
+    /// Media type of a serialized problem
+    pub const CONTENT_TYPE: &'static str = "application/problem+json";
+
+    /// Describe `error` with its title taken from `catalog`
+    pub fn new<E: ApiError + ?Sized>(error: &E, catalog: &dyn MessageCatalog) -> Self {
+        let status = error.status();
+        Self {
+            problem_type: format!("urn:captcha-generator:error:{}", error.code()),
+            title: catalog.message(error.message_key()).to_string(),
+            status,
+            code: error.code(),
+            detail: (status < 500).then(|| error.to_string()),
+        }
+    }
+}
+
+impl ApiError for CaptchaError {
+    fn code(&self) -> &'static str {
+        match self {
+            CaptchaError::Io(_) => "font_unreadable",
+            CaptchaError::InvalidFont => "invalid_font",
+            CaptchaError::Image(_) => "image_failed",
+            CaptchaError::BudgetExceeded { .. } => "budget_exceeded",
+            CaptchaError::MissingGlyphs { .. } => "missing_glyphs",
+            CaptchaError::TextTooWide { .. } => "text_too_wide",
+            CaptchaError::EmptyCharset => "empty_charset",
+        }
+    }
+
+    fn status(&self) -> u16 {
+        500
+    }
+
+    fn message_key(&self) -> MessageKey {
+        CaptchaError::message_key(self)
+    }
+}
+
+#[cfg(feature = "tokens")]
+impl ApiError for VerifyError {
+    fn code(&self) -> &'static str {
+        match self {
+            VerifyError::Malformed => "token_malformed",
+            VerifyError::Expired => "code_expired",
+            VerifyError::Mismatch => "code_mismatch",
+            VerifyError::Used => "code_used",
+            VerifyError::TooManyAttempts => "too_many_attempts",
+        }
+    }
+
+    fn status(&self) -> u16 {
+        match self {
+            VerifyError::TooManyAttempts => 429,
+            _ => 400,
+        }
+    }
+
+    fn message_key(&self) -> MessageKey {
+        VerifyError::message_key(self)
+    }
+}
+
+#[cfg(feature = "store")]
+impl ApiError for crate::store::StoreError {
+    fn code(&self) -> &'static str {
+        use crate::store::StoreError;
+        match self {
+            StoreError::QuotaExceeded { .. } => "quota_exceeded",
+            StoreError::Captcha(e) => e.code(),
+        }
+    }
+
+    fn status(&self) -> u16 {
+        use crate::store::StoreError;
+        match self {
+            StoreError::QuotaExceeded { .. } => 429,
+            StoreError::Captcha(e) => e.status(),
+        }
+    }
+
+    fn message_key(&self) -> MessageKey {
+        crate::store::StoreError::message_key(self)
+    }
+}
+
+/// Serialize an error as its code and English message
+#[cfg(feature = "serde")]
+fn serialize_error<E, S>(error: &E, serializer: S) -> Result<S::Ok, S::Error>
+where
+    E: ApiError + ?Sized,
+    S: serde::Serializer,
+{
+    use serde::ser::SerializeStruct;
+
+    let mut state = serializer.serialize_struct("Error", 2)?;
+    state.serialize_field("code", error.code())?;
+    state.serialize_field("message", &error.to_string())?;
+    state.end()
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CaptchaError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(self, serializer)
+    }
+}
+
+#[cfg(all(feature = "serde", feature = "tokens"))]
+impl serde::Serialize for VerifyError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(self, serializer)
+    }
+}
+
+#[cfg(all(feature = "serde", feature = "store"))]
+impl serde::Serialize for crate::store::StoreError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(self, serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Locale;
+
+    #[test]
+    fn test_codes_are_distinct() {
+        use image::error::{LimitError, LimitErrorKind};
+
+        #[cfg_attr(not(feature = "tokens"), allow(unused_mut))]
+        let mut errors: Vec<Box<dyn ApiError>> = vec![
+            Box::new(CaptchaError::Io(std::io::ErrorKind::NotFound.into())),
+            Box::new(CaptchaError::InvalidFont),
+            Box::new(CaptchaError::Image(image::ImageError::Limits(
+                LimitError::from_kind(LimitErrorKind::InsufficientMemory),
+            ))),
+            Box::new(CaptchaError::BudgetExceeded {
+                max_bytes: 1,
+                smallest: 2,
+            }),
+            Box::new(CaptchaError::MissingGlyphs {
+                font: 0,
+                chars: vec!['?'],
+            }),
+            Box::new(CaptchaError::TextTooWide {
+                needed: 2,
+                width: 1,
+            }),
+            Box::new(CaptchaError::EmptyCharset),
+        ];
+        #[cfg(feature = "tokens")]
+        errors.extend([
+            Box::new(VerifyError::Malformed) as Box<dyn ApiError>,
+            Box::new(VerifyError::Expired),
+            Box::new(VerifyError::Mismatch),
+            Box::new(VerifyError::Used),
+            Box::new(VerifyError::TooManyAttempts),
+        ]);
+        #[cfg(feature = "web")]
+        {
+            use crate::web::CaptchaRejection;
+            errors.extend([
+                Box::new(CaptchaRejection::MissingToken) as Box<dyn ApiError>,
+                Box::new(CaptchaRejection::MissingAnswer),
+                Box::new(CaptchaRejection::InvalidBody(String::new())),
+                Box::new(CaptchaRejection::NotFound),
+            ]);
+            // Wrapped errors answer with the code of the error they wrap
+            assert_eq!(
+                CaptchaRejection::Verify(VerifyError::Used).code(),
+                VerifyError::Used.code()
+            );
+        }
+        #[cfg(feature = "store")]
+        {
+            use crate::store::{QuotaLimit, StoreError};
+            errors.push(Box::new(StoreError::QuotaExceeded {
+                tenant: "tenant".to_string(),
+                limit: QuotaLimit::Outstanding(1),
+            }));
+            assert_eq!(
+                StoreError::Captcha(CaptchaError::InvalidFont).code(),
+                CaptchaError::InvalidFont.code()
+            );
+        }
+        let mut codes: Vec<_> = errors.iter().map(|e| e.code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    #[cfg(feature = "tokens")]
+    #[test]
+    fn test_problem_for_verify_error() {
+        let problem = Problem::new(&VerifyError::Mismatch, &Locale::De);
+        assert_eq!(problem.title, "Die CAPTCHA-Antwort ist falsch");
+        assert_eq!(
+            problem.problem_type,
+            "urn:captcha-generator:error:code_mismatch"
+        );
+        assert!(problem.detail.is_some());
+    }
+
+    #[test]
+    fn test_problem() {
+        let problem = Problem::new(&CaptchaError::InvalidFont, &Locale::En);
+        assert_eq!(problem.status, 500);
+        assert_eq!(problem.detail, None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Color quantization with a median-cut palette and optional dithering
+
+use std::collections::HashMap;
+
+use image::{Rgb, RgbImage};
+
+/// How quantization errors are spread to neighbouring pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dithering {
+    /// Map every pixel to its nearest palette color
+    #[default]
+    None,
+    /// Floyd–Steinberg error diffusion
+    FloydSteinberg,
+}
+
+/// Settings for the quantization stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantization {
+    /// Maximum palette size, from 1 to 256
+    pub colors: usize,
+    /// Dithering applied while mapping pixels to the palette
+    pub dithering: Dithering,
+}
+
+impl Default for Quantization {
+    fn default() -> Self {
+        Self {
+            colors: 256,
+            dithering: Dithering::None,
+        }
+    }
+}
+
+/// An image reduced to a palette of at most 256 colors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuantizedImage {
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+    /// The palette colors
+    pub palette: Vec<[u8; 3]>,
+    /// One palette index per pixel, row by row
+    pub indices: Vec<u8>,
+}
+
+impl QuantizedImage {
+    /// Expand the palette indices back into an RGB image
+    pub fn to_rgb_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let index = self.indices[(y * self.width + x) as usize];
+            Rgb(self.palette[index as usize])
+        })
+    }
+}
+
+/// Reduce `image` to a median-cut palette
+pub fn quantize(image: &RgbImage, settings: Quantization) -> QuantizedImage {
+    let palette = median_cut(image, settings.colors);
+    let indices = match settings.dithering {
+        Dithering::None => map_nearest(image, &palette),
+        Dithering::FloydSteinberg => map_floyd_steinberg(image, &palette),
+    };
+
+    QuantizedImage {
+        width: image.width(),
+        height: image.height(),
+        palette,
+        indices,
+    }
+}
+
+/// Build a palette of at most `max_colors` colors with the median-cut algorithm
+pub fn median_cut(image: &RgbImage, max_colors: usize) -> Vec<[u8; 3]> {
+    let max_colors = max_colors.clamp(1, 256);
+
+    let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+    for pixel in image.pixels() {
+        *histogram.entry(pixel.0).or_insert(0) += 1;
+    }
+    if histogram.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    // Sorted so the palette does not depend on hash map iteration order
+    let mut colors: Vec<_> = histogram.into_iter().collect();
+    colors.sort_unstable();
+
+    let mut boxes = vec![ColorBox::new(colors)];
+    while boxes.len() < max_colors {
+        let Some((index, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .max_by_key(|(_, b)| b.range().0 as u64 * b.count())
+        else {
+            break;
+        };
+        let (a, b) = boxes.swap_remove(index).split();
+        boxes.push(a);
+        boxes.push(b);
+    }
+
+    boxes.iter().map(ColorBox::average).collect()
+}
+
+/// A set of histogram entries covering part of the color space
+struct ColorBox {
+    colors: Vec<([u8; 3], u32)>,
+}
+
+impl ColorBox {
+    fn new(colors: Vec<([u8; 3], u32)>) -> Self {
+        Self { colors }
+    }
+
+    fn count(&self) -> u64 {
+        self.colors.iter().map(|(_, n)| *n as u64).sum()
+    }
+
+    /// The widest channel range and its channel
+    fn range(&self) -> (u8, usize) {
+        (0..3)
+            .map(|c| {
+                let min = self.colors.iter().map(|(p, _)| p[c]).min().unwrap_or(0);
+                let max = self.colors.iter().map(|(p, _)| p[c]).max().unwrap_or(0);
+                (max - min, c)
+            })
+            .max()
+            .unwrap_or((0, 0))
+    }
+
+    /// Split at the pixel-weighted median of the widest channel
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (_, channel) = self.range();
+        self.colors.sort_unstable_by_key(|(p, _)| p[channel]);
+
+        let half = self.count() / 2;
+        let mut seen = 0;
+        let mut at = 1;
+        for (i, (_, n)) in self.colors.iter().enumerate() {
+            seen += *n as u64;
+            if seen >= half {
+                at = (i + 1).clamp(1, self.colors.len() - 1);
+                break;
+            }
+        }
+
+        let rest = self.colors.split_off(at);
+        (self, ColorBox::new(rest))
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let total = self.count().max(1);
+        let mut sum = [0u64; 3];
+        for (p, n) in &self.colors {
+            for c in 0..3 {
+                sum[c] += p[c] as u64 * *n as u64;
+            }
+        }
+        sum.map(|s| ((s + total / 2) / total) as u8)
+    }
+}
+
+fn nearest(palette: &[[u8; 3]], color: [i32; 3]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| (0..3).map(|c| (p[c] as i32 - color[c]).pow(2)).sum::<i32>())
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+fn map_nearest(image: &RgbImage, palette: &[[u8; 3]]) -> Vec<u8> {
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    image
+        .pixels()
+        .map(|p| {
+            *cache
+                .entry(p.0)
+                .or_insert_with(|| nearest(palette, p.0.map(i32::from)) as u8)
+        })
+        .collect()
+}
+
+fn map_floyd_steinberg(image: &RgbImage, palette: &[[u8; 3]]) -> Vec<u8> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let mut buffer: Vec<[f32; 3]> = image.pixels().map(|p| p.0.map(f32::from)).collect();
+    let mut indices = Vec::with_capacity(width * height);
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            let old = buffer[y * width + x];
+            let color = old.map(|v| v.round().clamp(0.0, 255.0) as u8);
+            let index = *cache
+                .entry(color)
+                .or_insert_with(|| nearest(palette, color.map(i32::from)) as u8)
+                as usize;
+            indices.push(index as u8);
+
+            let new = palette[index];
+            let error = [0, 1, 2].map(|c| old[c] - new[c] as f32);
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                let ny = y + dy;
+                if nx >= 0 && (nx as usize) < width && ny < height {
+                    let target = &mut buffer[ny * width + nx as usize];
+                    for c in 0..3 {
+                        target[c] += error[c] * weight;
+                    }
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> RgbImage {
+        RgbImage::from_fn(64, 16, |x, y| Rgb([(x * 4) as u8, (y * 16) as u8, 128]))
+    }
+
+    #[test]
+    fn test_palette_size() {
+        let image = gradient();
+        for colors in [1, 2, 16, 256] {
+            let quantized = quantize(
+                &image,
+                Quantization {
+                    colors,
+                    dithering: Dithering::None,
+                },
+            );
+            assert!(quantized.palette.len() <= colors);
+            assert_eq!(quantized.indices.len(), 64 * 16);
+            assert!(quantized
+                .indices
+                .iter()
+                .all(|&i| (i as usize) < quantized.palette.len()));
+        }
+    }
+
+    #[test]
+    fn test_few_colors_are_kept_exactly() {
+        let image = RgbImage::from_fn(8, 8, |x, _| {
+            if x < 4 {
+                Rgb([10, 20, 30])
+            } else {
+                Rgb([200, 100, 50])
+            }
+        });
+        let quantized = quantize(&image, Quantization::default());
+        assert_eq!(quantized.to_rgb_image(), image);
+    }
+
+    #[test]
+    fn test_dithering_keeps_dimensions() {
+        let image = gradient();
+        let quantized = quantize(
+            &image,
+            Quantization {
+                colors: 4,
+                dithering: Dithering::FloydSteinberg,
+            },
+        );
+        assert_eq!(quantized.to_rgb_image().dimensions(), image.dimensions());
+    }
+}
@@ -0,0 +1,115 @@
+use super::raster::{dot_pixels, glyph_pixels, line_pixels};
+use super::{Renderer, Scene};
+
+/// Characters from empty to fully covered
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Renders a scene as ASCII art, `columns` characters wide
+///
+/// The glyphs, interference lines and noise of the scene are drawn into a
+/// coverage map, wave-distorted like the image and averaged per character
+/// cell. Cells are twice as tall as wide to suit terminal fonts. Colors and
+/// image effects are not represented.
+pub(crate) struct AsciiRenderer {
+    pub columns: usize,
+}
+
+impl Renderer for AsciiRenderer {
+    type Output = String;
+
+    fn render(&self, scene: &Scene) -> String {
+        let (width, height) = (scene.width as usize, scene.height as usize);
+        let mut coverage = vec![0.0f32; width * height];
+        for glyph in &scene.glyphs {
+            glyph_pixels(
+                glyph,
+                &scene.fonts[glyph.font],
+                scene.scale,
+                1.0,
+                scene.width,
+                scene.height,
+                |x, y, alpha| {
+                    let c = &mut coverage[y as usize * width + x as usize];
+                    *c = c.max(alpha);
+                },
+            );
+        }
+        for line in &scene.lines {
+            line_pixels(line, scene.width, scene.height, |x, y| {
+                coverage[y as usize * width + x as usize] = 1.0;
+            });
+        }
+        for dot in &scene.dots {
+            dot_pixels(dot, scene.width, scene.height, |x, y| {
+                coverage[y as usize * width + x as usize] = 1.0;
+            });
+        }
+
+        let columns = self.columns.clamp(1, width.max(1));
+        let cell_width = width as f32 / columns as f32;
+        let cell_height = cell_width * 2.0;
+        let rows = ((height as f32 / cell_height).round() as usize).max(1);
+
+        let mut art = String::with_capacity((columns + 1) * rows);
+        for row in 0..rows {
+            let y0 = (row as f32 * cell_height) as usize;
+            let y1 = (((row + 1) as f32 * cell_height) as usize).clamp(y0 + 1, height);
+            let line_start = art.len();
+            for column in 0..columns {
+                let x0 = (column as f32 * cell_width) as usize;
+                let x1 = (((column + 1) as f32 * cell_width) as usize).clamp(x0 + 1, width);
+                let mut sum = 0.0;
+                for y in y0..y1 {
+                    // The wave shifts whole rows, so sample each row at its source offset
+                    let offset = scene.wave.offset(y as f32) as i32;
+                    for x in x0..x1 {
+                        let src_x = (x as i32 + offset).clamp(0, width as i32 - 1) as usize;
+                        sum += coverage[y * width + src_x];
+                    }
+                }
+                let mean = sum / ((y1 - y0) * (x1 - x0)) as f32;
+                // Strokes rarely fill a whole cell, so lift partial coverage
+                let level = (mean.sqrt() * (RAMP.len() - 1) as f32).round() as usize;
+                art.push(RAMP[level.min(RAMP.len() - 1)] as char);
+            }
+            let trimmed = art[line_start..].trim_end().len();
+            art.truncate(line_start + trimmed);
+            art.push('\n');
+        }
+        art
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Captcha, CaptchaConfig};
+
+    #[test]
+    fn test_ascii_art() {
+        let captcha = Captcha::with_config(CaptchaConfig {
+            seed: Some(5),
+            noise_dots: 0,
+            interference_lines: (0, 1),
+            ..Default::default()
+        });
+        let art = AsciiRenderer { columns: 70 }.render(&captcha.scene);
+        let lines: Vec<_> = art.lines().collect();
+        // 280x100 in 4x8 cells
+        assert_eq!(lines.len(), 13);
+        assert!(lines.iter().all(|line| line.len() <= 70));
+        assert!(art.contains('#'));
+        assert!(art.chars().all(|c| c == '\n' || RAMP.contains(&(c as u8))));
+    }
+
+    #[test]
+    fn test_degenerate_columns() {
+        let captcha = Captcha::new();
+        let art = AsciiRenderer { columns: 0 }.render(&captcha.scene);
+        assert!(art.ends_with('\n'));
+        let art = AsciiRenderer { columns: 10_000 }.render(&captcha.scene);
+        assert!(art
+            .lines()
+            .all(|line| line.len() <= captcha.image.width() as usize));
+    }
+}
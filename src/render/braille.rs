@@ -0,0 +1,112 @@
+use image::imageops::{self, FilterType};
+use image::{GrayImage, RgbImage};
+
+/// Bit of each dot of a Braille cell, indexed by `[row][column]`
+const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// Render an image as Unicode Braille patterns, `columns` characters wide
+///
+/// Every character holds 2×4 dots. The image is scaled to one pixel per dot
+/// and split in two with Otsu's threshold. The side with fewer pixels is the
+/// ink, so light and dark backgrounds both work.
+pub(crate) fn braille(img: &RgbImage, columns: usize) -> String {
+    let (width, height) = img.dimensions();
+    let columns = columns.clamp(1, width.div_ceil(2).max(1) as usize) as u32;
+    let dots_wide = columns * 2;
+    let rows = ((height as f32 * dots_wide as f32 / width as f32) / 4.0)
+        .round()
+        .max(1.0) as u32;
+    let gray: GrayImage = imageops::grayscale(img);
+    let small = imageops::resize(&gray, dots_wide, rows * 4, FilterType::Triangle);
+
+    let threshold = otsu_threshold(&small);
+    let below = small.pixels().filter(|p| p.0[0] <= threshold).count();
+    let dark_background = below * 2 > small.pixels().len();
+    let is_ink = |x: u32, y: u32| (small.get_pixel(x, y).0[0] <= threshold) != dark_background;
+
+    let mut art = String::with_capacity((columns as usize * 3 + 1) * rows as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let mut bits = 0;
+            for (dy, row_bits) in DOTS.iter().enumerate() {
+                for (dx, bit) in row_bits.iter().enumerate() {
+                    if is_ink(column * 2 + dx as u32, row * 4 + dy as u32) {
+                        bits |= bit;
+                    }
+                }
+            }
+            art.push(char::from_u32(0x2800 + bits).expect("Braille patterns are valid chars"));
+        }
+        art.push('\n');
+    }
+    art
+}
+
+/// The gray level that best separates the pixels into two classes
+fn otsu_threshold(img: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    let total = img.pixels().len() as f64;
+    let sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(level, &n)| level as f64 * n as f64)
+        .sum();
+
+    let (mut best, mut best_variance) = (0, 0.0);
+    let (mut count_below, mut sum_below) = (0.0, 0.0);
+    for (level, &n) in histogram.iter().enumerate() {
+        count_below += n as f64;
+        sum_below += level as f64 * n as f64;
+        let count_above = total - count_below;
+        if count_below == 0.0 || count_above == 0.0 {
+            continue;
+        }
+        let mean_below = sum_below / count_below;
+        let mean_above = (sum - sum_below) / count_above;
+        let variance = count_below * count_above * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            (best, best_variance) = (level as u8, variance);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_braille_dots() {
+        // A dark square in the top-left corner of a light 8x8 image
+        let img = RgbImage::from_fn(8, 8, |x, y| {
+            if x < 2 && y < 4 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([250, 250, 250])
+            }
+        });
+        assert_eq!(braille(&img, 4), "⣿⠀⠀⠀\n⠀⠀⠀⠀\n");
+        // Inverted colors give the same dots
+        let inverted = RgbImage::from_fn(8, 8, |x, y| {
+            let Rgb([v, ..]) = *img.get_pixel(x, y);
+            Rgb([255 - v; 3])
+        });
+        assert_eq!(braille(&inverted, 4), braille(&img, 4));
+    }
+
+    #[test]
+    fn test_captcha_braille() {
+        let captcha = crate::Captcha::new();
+        let art = braille(&captcha.image, 70);
+        let lines: Vec<_> = art.lines().collect();
+        // 280x100 at 2 pixels per dot
+        assert_eq!(lines.len(), 13);
+        assert!(lines.iter().all(|line| line.chars().count() == 70));
+        assert!(art.chars().any(|c| c != '⠀' && c != '\n'));
+        assert_eq!(braille(&captcha.image, 0).lines().count(), 1);
+    }
+}
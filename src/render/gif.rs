@@ -0,0 +1,121 @@
+use std::f32::consts::TAU;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame};
+
+use super::{PlacedGlyph, RasterRenderer, Renderer, Scene};
+use crate::portable::{cos, sin};
+use crate::CaptchaError;
+
+/// Maximum distance in pixels a character drifts away from its resting position
+const DRIFT: f32 = 3.0;
+
+/// Renders a scene as a looping animated GIF in which characters fade in and out
+pub(crate) struct GifRenderer {
+    pub frames: u32,
+    pub delay_ms: u32,
+}
+
+impl Renderer for GifRenderer {
+    type Output = Result<Vec<u8>, CaptchaError>;
+
+    fn render(&self, scene: &Scene) -> Self::Output {
+        let frames = self.frames.max(2);
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut bytes, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            for frame in 0..frames {
+                let glyphs = frame_glyphs(scene, frame, frames);
+                let rgba = RasterRenderer.render_glyphs(scene, &glyphs);
+                let delay = Delay::from_numer_denom_ms(self.delay_ms, 1);
+                encoder.encode_frame(Frame::from_parts(rgba, 0, 0, delay))?;
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// Glyph placement and opacity for one frame of the animation
+///
+/// Every character peaks at full opacity once per loop, at a phase given by
+/// its position in the code, and the least visible character of each frame is
+/// hidden completely so no single frame shows the whole code.
+fn frame_glyphs(scene: &Scene, frame: u32, frames: u32) -> Vec<(PlacedGlyph, f32)> {
+    let n = scene.glyphs.len();
+    let t = frame as f32 / frames as f32;
+
+    let mut glyphs: Vec<(PlacedGlyph, f32)> = scene
+        .glyphs
+        .iter()
+        .enumerate()
+        .map(|(i, glyph)| {
+            let angle = TAU * (t - i as f32 / n as f32);
+            let visibility = 0.5 + 0.5 * cos(angle);
+            let opacity = ((visibility - 0.2) / 0.6).clamp(0.0, 1.0);
+
+            let mut glyph = glyph.clone();
+            glyph.x_offset += sin(angle) * DRIFT;
+            glyph.y_offset += sin(angle * 2.0) * DRIFT / 2.0;
+            (glyph, opacity)
+        })
+        .collect();
+
+    if n > 1 {
+        let hidden = glyphs
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1 .1.total_cmp(&b.1 .1))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        glyphs[hidden].1 = 0.0;
+    }
+
+    glyphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CaptchaConfig;
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn scene() -> Scene {
+        let fonts = crate::font::load_fonts(&[]).unwrap();
+        let config = CaptchaConfig::default();
+        Scene::generate("ABC234", &config, fonts, &mut StdRng::seed_from_u64(9))
+    }
+
+    #[test]
+    fn test_no_frame_shows_every_character() {
+        let scene = scene();
+        let frames = 12;
+        let mut peak = vec![0.0f32; scene.glyphs.len()];
+
+        for frame in 0..frames {
+            let glyphs = frame_glyphs(&scene, frame, frames);
+            assert!(glyphs.iter().any(|(_, opacity)| *opacity == 0.0));
+            for (i, (_, opacity)) in glyphs.iter().enumerate() {
+                peak[i] = peak[i].max(*opacity);
+            }
+        }
+
+        assert!(peak.iter().all(|&p| p == 1.0));
+    }
+
+    #[test]
+    fn test_gif_frames() {
+        let renderer = GifRenderer {
+            frames: 4,
+            delay_ms: 100,
+        };
+        let bytes = renderer.render(&scene()).unwrap();
+        let decoder = GifDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].buffer().dimensions(), (280, 100));
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use rusttype::{Font, Scale};
+
+/// Advance width and unrotated ink rows of one glyph
+#[derive(Debug, Clone, Copy)]
+struct Metric {
+    advance: f32,
+    /// Width of the upright ink, zero for blank glyphs
+    ink_width: f32,
+    /// Highest and lowest ink row relative to the baseline, `None` for blank glyphs
+    rows: Option<(f32, f32)>,
+}
+
+/// Metrics of every charset glyph in every font, measured once up front
+///
+/// Lets layout skip the font queries for advance-spaced text and for the
+/// top and bottom anchors.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GlyphMetrics {
+    glyphs: HashMap<(usize, char), Metric>,
+}
+
+impl GlyphMetrics {
+    /// Measure each character of `charset` in each of `fonts` at `scale`
+    pub fn measure(fonts: &[Font<'static>], charset: &str, scale: Scale) -> Self {
+        let mut glyphs = HashMap::new();
+        for (index, font) in fonts.iter().enumerate() {
+            for ch in charset.chars() {
+                let glyph = font.glyph(ch).scaled(scale);
+                let bb = glyph.exact_bounding_box();
+                let metric = Metric {
+                    advance: glyph.h_metrics().advance_width,
+                    ink_width: bb.map_or(0.0, |bb| bb.width()),
+                    rows: bb.map(|bb| (bb.min.y, bb.max.y)),
+                };
+                glyphs.insert((index, ch), metric);
+            }
+        }
+        Self { glyphs }
+    }
+
+    /// Advance width of `ch` in font `font`, if it was measured
+    pub fn advance(&self, font: usize, ch: char) -> Option<f32> {
+        self.glyphs.get(&(font, ch)).map(|m| m.advance)
+    }
+
+    /// Ink rows of `ch` in font `font`, if it was measured
+    pub fn rows(&self, font: usize, ch: char) -> Option<Option<(f32, f32)>> {
+        self.glyphs.get(&(font, ch)).map(|m| m.rows)
+    }
+
+    /// Smallest and largest advance width of all measured glyphs
+    pub fn advance_range(&self) -> (f32, f32) {
+        range(self.glyphs.values().map(|m| m.advance))
+    }
+
+    /// Smallest and largest upright ink width of all measured glyphs
+    pub fn ink_width_range(&self) -> (f32, f32) {
+        range(self.glyphs.values().map(|m| m.ink_width))
+    }
+}
+
+/// Minimum and maximum of `values`, or zeros if there are none
+fn range(values: impl Iterator<Item = f32>) -> (f32, f32) {
+    values
+        .fold(None, |range: Option<(f32, f32)>, v| {
+            Some(match range {
+                Some((min, max)) => (min.min(v), max.max(v)),
+                None => (v, v),
+            })
+        })
+        .unwrap_or((0.0, 0.0))
+}
@@ -0,0 +1,667 @@
+//! Layout of CAPTCHAs and the renderers that draw them
+
+use std::fmt;
+
+use image::Rgba;
+use rand::Rng;
+use rusttype::{point, Font, Scale};
+
+use crate::effect::{CustomEffect, Effect};
+use crate::portable::{self, cos, index, sin};
+use crate::theme::{pick_color, ColorRange};
+use crate::CaptchaConfig;
+
+mod ascii;
+mod braille;
+mod gif;
+mod metrics;
+pub mod primitives;
+mod raster;
+mod stream;
+mod svg;
+
+pub(crate) use ascii::AsciiRenderer;
+pub(crate) use braille::braille;
+pub(crate) use gif::GifRenderer;
+pub(crate) use metrics::GlyphMetrics;
+pub(crate) use raster::RasterRenderer;
+pub(crate) use stream::write_png;
+pub(crate) use svg::SvgRenderer;
+
+/// Turns a laid-out [`Scene`] into a concrete output
+pub(crate) trait Renderer {
+    type Output;
+
+    fn render(&self, scene: &Scene) -> Self::Output;
+}
+
+/// A single character with its randomized placement
+#[derive(Debug, Clone)]
+pub(crate) struct PlacedGlyph {
+    pub ch: char,
+    /// Index into [`Scene::fonts`]
+    pub font: usize,
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub rotation: f32,
+    /// Horizontal shear factor applied before the rotation
+    pub shear: f32,
+    /// Outline thickness when the glyph is drawn hollow
+    pub outline: Option<f32>,
+    pub color: [u8; 3],
+}
+
+/// A sine-shaped interference line spanning the full width
+#[derive(Debug, Clone)]
+pub(crate) struct InterferenceLine {
+    pub color: [u8; 3],
+    pub start_y: f32,
+    pub amplitude: f32,
+    pub frequency: f32,
+    pub thickness: i32,
+}
+
+/// A noise dot, optionally surrounded by a small cluster of pixels
+#[derive(Debug, Clone)]
+pub(crate) struct NoiseDot {
+    pub x: u32,
+    pub y: u32,
+    pub color: [u8; 3],
+    /// Neighbouring offsets that are painted with the same color
+    pub cluster: Vec<(i32, i32)>,
+}
+
+/// Horizontal sine displacement applied to every row
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Wave {
+    pub amplitude: f32,
+    pub frequency: f32,
+}
+
+impl Wave {
+    /// Horizontal source offset for row `y`
+    pub fn offset(&self, y: f32) -> f32 {
+        sin(y * self.frequency) * self.amplitude
+    }
+}
+
+/// What the CAPTCHA is drawn on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Background {
+    /// A near-white, slightly noisy texture
+    #[default]
+    Noise,
+    /// Fully transparent, for overlaying on your own background
+    Transparent,
+    /// A single color, which may be translucent
+    Solid(Rgba<u8>),
+    /// A vertical gradient from `top` to `bottom`
+    Gradient {
+        /// Color of the first row
+        top: Rgba<u8>,
+        /// Color of the last row
+        bottom: Rgba<u8>,
+    },
+}
+
+impl Background {
+    /// Whether every background pixel is fully opaque
+    pub fn is_opaque(&self) -> bool {
+        match self {
+            Background::Noise => true,
+            Background::Transparent => false,
+            Background::Solid(color) => color[3] == 255,
+            Background::Gradient { top, bottom } => top[3] == 255 && bottom[3] == 255,
+        }
+    }
+
+    /// Color of row `y` in an image `height` pixels tall, for the non-textured variants
+    pub(crate) fn row_color(&self, y: u32, height: u32) -> Rgba<u8> {
+        match *self {
+            Background::Noise | Background::Transparent => Rgba([0, 0, 0, 0]),
+            Background::Solid(color) => color,
+            Background::Gradient { top, bottom } => {
+                let t = y as f32 / height.saturating_sub(1).max(1) as f32;
+                Rgba([0, 1, 2, 3].map(|c| {
+                    (top[c] as f32 + (bottom[c] as f32 - top[c] as f32) * t).round() as u8
+                }))
+            }
+        }
+    }
+}
+
+/// An effect on the finished raster, with its random parameters chosen
+#[derive(Debug, Clone)]
+pub(crate) enum ImageEffect {
+    Ripple {
+        amplitude: f32,
+        wavelength: f32,
+        phase: (f32, f32),
+    },
+    Custom(CustomEffect, u64),
+}
+
+/// Every randomly chosen parameter of a CAPTCHA, independent of the output format
+#[derive(Clone)]
+pub(crate) struct Scene {
+    pub width: u32,
+    pub height: u32,
+    pub fonts: Vec<Font<'static>>,
+    pub scale: Scale,
+    pub background: Background,
+    /// Seed for the per-pixel background texture
+    pub background_seed: u64,
+    pub glyphs: Vec<PlacedGlyph>,
+    pub lines: Vec<InterferenceLine>,
+    pub dots: Vec<NoiseDot>,
+    pub wave: Wave,
+    /// Applied in order after the wave distortion
+    pub image_effects: Vec<ImageEffect>,
+}
+
+impl fmt::Debug for Scene {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scene")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("fonts", &self.fonts.len())
+            .field("background", &self.background)
+            .field("glyphs", &self.glyphs)
+            .field("lines", &self.lines)
+            .field("dots", &self.dots.len())
+            .field("wave", &self.wave)
+            .field("image_effects", &self.image_effects)
+            .finish()
+    }
+}
+
+impl Scene {
+    /// Lay out `code` and pick all distortion parameters
+    pub fn generate<R: Rng + ?Sized>(
+        code: &str,
+        config: &CaptchaConfig,
+        fonts: Vec<Font<'static>>,
+        rng: &mut R,
+    ) -> Self {
+        Self::generate_measured(code, config, fonts, None, rng)
+    }
+
+    /// Like [`Scene::generate`], taking glyph sizes from `metrics` where possible
+    ///
+    /// The result is the same as without metrics.
+    pub fn generate_measured<R: Rng + ?Sized>(
+        code: &str,
+        config: &CaptchaConfig,
+        fonts: Vec<Font<'static>>,
+        metrics: Option<&GlyphMetrics>,
+        rng: &mut R,
+    ) -> Self {
+        let background_seed = rng.gen();
+        let char_fonts = pick_fonts(
+            fonts.len(),
+            code.chars().count(),
+            config.random_font_per_char,
+            rng,
+        );
+        let scale = Scale::uniform(config.font_size);
+        let mut glyphs = layout_text(config, code, &fonts, metrics, &char_fonts, scale, rng);
+        let lines = pick_interference_lines(
+            config.height,
+            config.interference_lines,
+            &config.theme.interference,
+            rng,
+        );
+        let dots = pick_noise_dots(
+            config.width,
+            config.height,
+            config.noise_dots,
+            &config.theme.noise,
+            rng,
+        );
+        let wave = Wave {
+            amplitude: rng.gen_range(config.wave_amplitude.0..config.wave_amplitude.1),
+            frequency: rng.gen_range(0.06..0.09),
+        };
+        let image_effects = apply_effects(&config.effects, &mut glyphs, rng);
+
+        Self {
+            width: config.width,
+            height: config.height,
+            fonts,
+            scale,
+            background: config.theme.background,
+            background_seed,
+            glyphs,
+            lines,
+            dots,
+            wave,
+            image_effects,
+        }
+    }
+}
+
+/// Choose the font index used for each character of the text
+fn pick_fonts<R: Rng + ?Sized>(
+    count: usize,
+    len: usize,
+    per_char: bool,
+    rng: &mut R,
+) -> Vec<usize> {
+    let shared = index(rng, count);
+    (0..len)
+        .map(|_| if per_char { index(rng, count) } else { shared })
+        .collect()
+}
+
+/// How neighbouring characters are spaced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spacing {
+    /// Advance widths plus this many extra pixels
+    Advance(f32),
+    /// This many pixels between the rotated ink of neighbouring characters
+    ///
+    /// Narrow or heavily rotated glyphs such as "J" no longer leave gaps that
+    /// look like a word break.
+    Optical(f32),
+}
+
+impl Default for Spacing {
+    fn default() -> Self {
+        Spacing::Advance(8.0)
+    }
+}
+
+/// Horizontal placement of the text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HorizontalAlign {
+    /// Start at the left margin
+    Left,
+    /// Centered in the image
+    #[default]
+    Center,
+    /// End at the right margin
+    Right,
+}
+
+/// Vertical placement of the text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerticalAnchor {
+    /// Tallest character touches the top margin
+    Top,
+    /// Baseline a third of the font size below the middle of the image
+    #[default]
+    Middle,
+    /// Lowest character touches the bottom margin
+    Bottom,
+}
+
+/// Where the text sits in the image
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TextAlignment {
+    /// Horizontal placement
+    pub horizontal: HorizontalAlign,
+    /// Vertical placement
+    pub vertical: VerticalAnchor,
+    /// Distance in pixels kept from the edges the text is aligned to
+    pub margin: f32,
+}
+
+/// Align the text and give every character a random offset, rotation and color
+fn layout_text<R: Rng + ?Sized>(
+    config: &CaptchaConfig,
+    text: &str,
+    fonts: &[Font<'static>],
+    metrics: Option<&GlyphMetrics>,
+    char_fonts: &[usize],
+    scale: Scale,
+    rng: &mut R,
+) -> Vec<PlacedGlyph> {
+    let alignment = config.alignment;
+    let base_y = match alignment.vertical {
+        VerticalAnchor::Middle => (config.height as f32 / 2.0) + (config.font_size / 3.0),
+        VerticalAnchor::Top => {
+            let top = ink_rows(text, fonts, metrics, char_fonts, scale).0;
+            alignment.margin - top
+        }
+        VerticalAnchor::Bottom => {
+            let bottom = ink_rows(text, fonts, metrics, char_fonts, scale).1;
+            config.height as f32 - alignment.margin - bottom
+        }
+    };
+
+    let mut glyphs: Vec<PlacedGlyph> = text
+        .chars()
+        .zip(char_fonts)
+        .map(|(ch, &font)| {
+            let rotation = symmetric(config.rotation, rng);
+            let y_offset = base_y + symmetric(config.jitter.1, rng);
+            let x_offset = symmetric(config.jitter.0, rng);
+            let color = pick_color(&config.theme.text, rng);
+            PlacedGlyph {
+                ch,
+                font,
+                x_offset,
+                y_offset,
+                rotation,
+                shear: 0.0,
+                outline: None,
+                color,
+            }
+        })
+        .collect();
+
+    // Horizontal extent of every character relative to its pen position
+    let (extents, spacing): (Vec<(f32, f32)>, f32) = match config.spacing {
+        Spacing::Advance(spacing) => (
+            glyphs
+                .iter()
+                .map(|g| {
+                    let advance = metrics
+                        .and_then(|m| m.advance(g.font, g.ch))
+                        .unwrap_or_else(|| {
+                            fonts[g.font]
+                                .glyph(g.ch)
+                                .scaled(scale)
+                                .h_metrics()
+                                .advance_width
+                        });
+                    (0.0, advance)
+                })
+                .collect(),
+            spacing,
+        ),
+        Spacing::Optical(gap) => (
+            glyphs
+                .iter()
+                .map(|g| {
+                    ink_extent(&fonts[g.font], scale, g.ch, g.rotation).unwrap_or_else(|| {
+                        let glyph = fonts[g.font].glyph(g.ch).scaled(scale);
+                        (0.0, glyph.h_metrics().advance_width)
+                    })
+                })
+                .collect(),
+            gap,
+        ),
+    };
+
+    let total_width = extents
+        .iter()
+        .map(|(left, right)| right - left)
+        .sum::<f32>()
+        + spacing * extents.len().saturating_sub(1) as f32;
+    let mut current_x = match alignment.horizontal {
+        HorizontalAlign::Left => alignment.margin,
+        HorizontalAlign::Center => (config.width as f32 - total_width) / 2.0,
+        HorizontalAlign::Right => config.width as f32 - alignment.margin - total_width,
+    };
+
+    for (glyph, (left, right)) in glyphs.iter_mut().zip(extents) {
+        glyph.x_offset += current_x - left;
+        current_x += right - left + spacing;
+    }
+
+    glyphs
+}
+
+/// A random value in `-max..max`, or zero without drawing from `rng` if `max` is not positive
+fn symmetric<R: Rng + ?Sized>(max: f32, rng: &mut R) -> f32 {
+    if max > 0.0 {
+        rng.gen_range(-max..max)
+    } else {
+        0.0
+    }
+}
+
+/// Highest and lowest ink row of the unrotated text, relative to the baseline
+fn ink_rows(
+    text: &str,
+    fonts: &[Font<'static>],
+    metrics: Option<&GlyphMetrics>,
+    char_fonts: &[usize],
+    scale: Scale,
+) -> (f32, f32) {
+    text.chars()
+        .zip(char_fonts)
+        .filter_map(|(ch, &font)| {
+            metrics.and_then(|m| m.rows(font, ch)).unwrap_or_else(|| {
+                let bb = fonts[font].glyph(ch).scaled(scale).exact_bounding_box()?;
+                Some((bb.min.y, bb.max.y))
+            })
+        })
+        .fold(None, |rows: Option<(f32, f32)>, (min, max)| {
+            Some(match rows {
+                Some((top, bottom)) => (top.min(min), bottom.max(max)),
+                None => (min, max),
+            })
+        })
+        .unwrap_or((0.0, 0.0))
+}
+
+/// Horizontal ink extent of `ch` drawn with `rotation`, relative to its pen position
+///
+/// Uses the same rotation about the bounding box center as the renderers.
+fn ink_extent(font: &Font<'static>, scale: Scale, ch: char, rotation: f32) -> Option<(f32, f32)> {
+    let glyph = font.glyph(ch).scaled(scale);
+    let bb = glyph.exact_bounding_box()?;
+    let (sin_r, cos_r) = (sin(rotation), cos(rotation));
+    let cx = bb.width() / 2.0;
+    let cy = bb.height() / 2.0;
+
+    let mut extent: Option<(f32, f32)> = None;
+    glyph.positioned(point(0.0, 0.0)).draw(|gx, gy, v| {
+        if v < 0.01 {
+            return;
+        }
+        let x = (gx as f32 - cx) * cos_r - (gy as f32 - cy) * sin_r + cx + bb.min.x;
+        extent = Some(match extent {
+            Some((min, max)) => (min.min(x), max.max(x + 1.0)),
+            None => (x, x + 1.0),
+        });
+    });
+    extent
+}
+
+/// Apply the character effects to `glyphs` in order and resolve the image effects
+fn apply_effects<R: Rng + ?Sized>(
+    effects: &[Effect],
+    glyphs: &mut [PlacedGlyph],
+    rng: &mut R,
+) -> Vec<ImageEffect> {
+    let mut image_effects = Vec::new();
+    for effect in effects {
+        match effect {
+            Effect::Shear(max) => {
+                for glyph in glyphs.iter_mut() {
+                    glyph.shear += symmetric(*max, rng);
+                }
+            }
+            Effect::Overlap(pixels) => {
+                // Pull towards the middle so the text stays where it was aligned
+                let middle = glyphs.len().saturating_sub(1) as f32 / 2.0;
+                for (i, glyph) in glyphs.iter_mut().enumerate() {
+                    glyph.x_offset -= (i as f32 - middle) * pixels;
+                }
+            }
+            Effect::Outline(thickness) => {
+                for glyph in glyphs.iter_mut() {
+                    glyph.outline = Some(thickness.max(1.0));
+                }
+            }
+            Effect::Ripple {
+                amplitude,
+                wavelength,
+            } => image_effects.push(ImageEffect::Ripple {
+                amplitude: *amplitude,
+                wavelength: wavelength.max(1.0),
+                phase: (
+                    rng.gen_range(0.0..std::f32::consts::TAU),
+                    rng.gen_range(0.0..std::f32::consts::TAU),
+                ),
+            }),
+            Effect::Custom(custom) => {
+                image_effects.push(ImageEffect::Custom(custom.clone(), rng.gen()))
+            }
+        }
+    }
+    image_effects
+}
+
+/// Pick the curved interference lines
+fn pick_interference_lines<R: Rng + ?Sized>(
+    height: u32,
+    line_range: (usize, usize),
+    palette: &[ColorRange],
+    rng: &mut R,
+) -> Vec<InterferenceLine> {
+    (0..portable::count(rng, line_range))
+        .map(|_| InterferenceLine {
+            color: pick_color(palette, rng),
+            start_y: rng.gen_range(0..height) as f32,
+            amplitude: rng.gen_range(8.0..12.0),
+            frequency: rng.gen_range(0.02..0.04),
+            thickness: 1,
+        })
+        .collect()
+}
+
+/// Pick the random noise dots
+fn pick_noise_dots<R: Rng + ?Sized>(
+    width: u32,
+    height: u32,
+    count: usize,
+    palette: &[ColorRange],
+    rng: &mut R,
+) -> Vec<NoiseDot> {
+    (0..count)
+        .map(|_| {
+            let x = rng.gen_range(0..width);
+            let y = rng.gen_range(0..height);
+
+            let color = pick_color(palette, rng);
+
+            let mut cluster = Vec::new();
+            if rng.gen_bool(0.2) {
+                for dx in -1..=1 {
+                    for dy in -1..=1 {
+                        if rng.gen_bool(0.3) {
+                            cluster.push((dx, dy));
+                        }
+                    }
+                }
+            }
+
+            NoiseDot {
+                x,
+                y,
+                color,
+                cluster,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn scene(seed: u64) -> Scene {
+        let fonts = crate::font::load_fonts(&[]).unwrap();
+        let config = CaptchaConfig::default();
+        Scene::generate("ABC234", &config, fonts, &mut StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn test_scene_layout() {
+        let scene = scene(1);
+        assert_eq!(scene.glyphs.len(), 6);
+        assert_eq!(scene.dots.len(), 100);
+        assert!((2..4).contains(&scene.lines.len()));
+        assert!(scene
+            .glyphs
+            .windows(2)
+            .all(|w| w[0].x_offset < w[1].x_offset));
+    }
+
+    #[test]
+    fn test_optical_spacing_equalizes_ink_gaps() {
+        let fonts = crate::font::load_fonts(&[]).unwrap();
+        let config = CaptchaConfig {
+            spacing: Spacing::Optical(6.0),
+            ..Default::default()
+        };
+        let scene = Scene::generate("AJ1W", &config, fonts, &mut StdRng::seed_from_u64(5));
+        let extents: Vec<_> = scene
+            .glyphs
+            .iter()
+            .map(|g| {
+                let (left, right) =
+                    ink_extent(&scene.fonts[g.font], scene.scale, g.ch, g.rotation).unwrap();
+                (g.x_offset + left, g.x_offset + right)
+            })
+            .collect();
+        // The random +-2px jitter on both sides is the only variation left
+        for pair in extents.windows(2) {
+            let gap = pair[1].0 - pair[0].1;
+            assert!((2.0..=10.0).contains(&gap), "gap {}", gap);
+        }
+    }
+
+    #[test]
+    fn test_alignment() {
+        let fonts = crate::font::load_fonts(&[]).unwrap();
+        let layout = |horizontal, vertical| {
+            let config = CaptchaConfig {
+                alignment: TextAlignment {
+                    horizontal,
+                    vertical,
+                    margin: 10.0,
+                },
+                ..Default::default()
+            };
+            Scene::generate("ABC", &config, fonts.clone(), &mut StdRng::seed_from_u64(2))
+        };
+
+        let left = layout(HorizontalAlign::Left, VerticalAnchor::Top);
+        let right = layout(HorizontalAlign::Right, VerticalAnchor::Bottom);
+        assert!(left.glyphs[0].x_offset < 15.0);
+        assert!(right.glyphs[2].x_offset > 280.0 - 60.0);
+        assert!(left.glyphs[0].y_offset < right.glyphs[0].y_offset);
+        assert!(left.glyphs[0].y_offset < 60.0);
+        assert!(right.glyphs[0].y_offset > 80.0);
+    }
+
+    #[test]
+    fn test_character_effects() {
+        let fonts = crate::font::load_fonts(&[]).unwrap();
+        let plain = Scene::generate(
+            "ABCD",
+            &CaptchaConfig::default(),
+            fonts.clone(),
+            &mut StdRng::seed_from_u64(6),
+        );
+        let config = CaptchaConfig {
+            effects: vec![
+                Effect::Shear(0.3),
+                Effect::Overlap(6.0),
+                Effect::Outline(2.0),
+            ],
+            ..Default::default()
+        };
+        let styled = Scene::generate("ABCD", &config, fonts, &mut StdRng::seed_from_u64(6));
+
+        let span = |scene: &Scene| scene.glyphs[3].x_offset - scene.glyphs[0].x_offset;
+        assert!((span(&plain) - span(&styled) - 18.0).abs() < 0.01);
+        assert!(styled.glyphs.iter().all(|g| g.shear != 0.0));
+        assert!(styled.glyphs.iter().all(|g| g.outline == Some(2.0)));
+        assert!(styled.image_effects.is_empty());
+    }
+
+    #[test]
+    fn test_scene_is_deterministic() {
+        let a = format!("{:?}", scene(3));
+        let b = format!("{:?}", scene(3));
+        assert_eq!(a, b);
+    }
+}
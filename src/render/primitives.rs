@@ -0,0 +1,244 @@
+//! The drawing operations CAPTCHAs are built from
+//!
+//! Each stage of the built-in renderer is available on its own, working on
+//! plain [`RgbImage`]s, so custom challenge types can be assembled from the
+//! same building blocks:
+//!
+//! ```
+//! use captcha_generator::render::primitives::{self, GlyphStyle};
+//! use captcha_generator::FontSource;
+//!
+//! let mut img = primitives::noise_background(120, 60, 7);
+//! let style = GlyphStyle {
+//!     x: 20.0,
+//!     y: 45.0,
+//!     rotation: 0.2,
+//!     ..Default::default()
+//! };
+//! primitives::draw_character(&mut img, 'A', &FontSource::Embedded, &style).unwrap();
+//! primitives::draw_interference_line(&mut img, 30.0, 10.0, 0.03, 1, [90, 90, 200]);
+//! let img = primitives::wave_distort(&img, 2.0, 0.07);
+//! assert_eq!(img.dimensions(), (120, 60));
+//! ```
+
+use image::{DynamicImage, Rgb, RgbImage, Rgba};
+use rand::Rng;
+use rusttype::Scale;
+
+use super::raster::{self, blend, dot_pixels, glyph_pixels, line_pixels};
+use super::{pick_noise_dots, Background, InterferenceLine, NoiseDot, PlacedGlyph, Wave};
+use crate::{font, CaptchaError, ColorRange, FontSource};
+
+/// How [`draw_character`] places and paints a character
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphStyle {
+    /// Left edge of the character's ink in pixels
+    pub x: f32,
+    /// Baseline in pixels
+    pub y: f32,
+    /// Font size in pixels
+    pub size: f32,
+    /// Rotation about the character's center, in radians
+    pub rotation: f32,
+    /// Horizontal shear factor, applied before the rotation
+    pub shear: f32,
+    /// Draw only a ring of this thickness around the strokes
+    pub outline: Option<f32>,
+    /// Text color
+    pub color: [u8; 3],
+    /// Opacity from 0.0 to 1.0
+    pub opacity: f32,
+}
+
+impl Default for GlyphStyle {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            size: 52.0,
+            rotation: 0.0,
+            shear: 0.0,
+            outline: None,
+            color: [0, 0, 0],
+            opacity: 1.0,
+        }
+    }
+}
+
+/// A textured, near-white background like the default CAPTCHA's
+///
+/// The same `seed` always produces the same texture.
+pub fn noise_background(width: u32, height: u32, seed: u64) -> RgbImage {
+    let rgba = raster::create_background(width, height, Background::Noise, seed);
+    DynamicImage::ImageRgba8(rgba).into_rgb8()
+}
+
+/// Draw `ch` from `font` onto `img`, anti-aliased
+///
+/// Fails only if the font cannot be loaded; parsed fonts are cached.
+pub fn draw_character(
+    img: &mut RgbImage,
+    ch: char,
+    font: &FontSource,
+    style: &GlyphStyle,
+) -> Result<(), CaptchaError> {
+    let fonts = font::load_fonts(std::slice::from_ref(font))?;
+    let glyph = PlacedGlyph {
+        ch,
+        font: 0,
+        x_offset: style.x,
+        y_offset: style.y,
+        rotation: style.rotation,
+        shear: style.shear,
+        outline: style.outline,
+        color: style.color,
+    };
+    let (width, height) = img.dimensions();
+    glyph_pixels(
+        &glyph,
+        &fonts[0],
+        Scale::uniform(style.size),
+        style.opacity,
+        width,
+        height,
+        |x, y, alpha| {
+            let [r, g, b] = img.get_pixel(x, y).0;
+            let [r, g, b, _] = blend(Rgba([r, g, b, 255]), style.color, alpha).0;
+            img.put_pixel(x, y, Rgb([r, g, b]));
+        },
+    );
+    Ok(())
+}
+
+/// Draw a sine-shaped line across the full width of `img`
+///
+/// The line starts at `start_y` and swings `amplitude` pixels either way with
+/// an angular `frequency` per pixel; `thickness` pixels are drawn above and
+/// below the curve.
+pub fn draw_interference_line(
+    img: &mut RgbImage,
+    start_y: f32,
+    amplitude: f32,
+    frequency: f32,
+    thickness: i32,
+    color: [u8; 3],
+) {
+    let line = InterferenceLine {
+        color,
+        start_y,
+        amplitude,
+        frequency,
+        thickness,
+    };
+    let (width, height) = img.dimensions();
+    line_pixels(&line, width, height, |x, y| img.put_pixel(x, y, Rgb(color)));
+}
+
+/// Paint the pixel at (`x`, `y`) and the pixels at the `cluster` offsets from it
+///
+/// Offsets past the edges are clamped to the image.
+pub fn draw_noise_dot(img: &mut RgbImage, x: u32, y: u32, cluster: &[(i32, i32)], color: [u8; 3]) {
+    let dot = NoiseDot {
+        x,
+        y,
+        color,
+        cluster: cluster.to_vec(),
+    };
+    let (width, height) = img.dimensions();
+    if x < width && y < height {
+        dot_pixels(&dot, width, height, |x, y| img.put_pixel(x, y, Rgb(color)));
+    }
+}
+
+/// Scatter `count` noise dots with small random clusters over `img`
+///
+/// Colors are picked from `palette`, as for [`ColorTheme::noise`](crate::ColorTheme::noise).
+pub fn scatter_noise<R: Rng + ?Sized>(
+    img: &mut RgbImage,
+    count: usize,
+    palette: &[ColorRange],
+    rng: &mut R,
+) {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    for dot in pick_noise_dots(width, height, count, palette, rng) {
+        dot_pixels(&dot, width, height, |x, y| {
+            img.put_pixel(x, y, Rgb(dot.color))
+        });
+    }
+}
+
+/// Shift every row horizontally by a sine of its position
+///
+/// Row `y` moves by `sin(y * frequency) * amplitude` pixels.
+pub fn wave_distort(img: &RgbImage, amplitude: f32, frequency: f32) -> RgbImage {
+    let rgba = DynamicImage::ImageRgb8(img.clone()).into_rgba8();
+    let wave = Wave {
+        amplitude,
+        frequency,
+    };
+    DynamicImage::ImageRgba8(raster::add_wave_distortion(&rgba, wave)).into_rgb8()
+}
+
+/// Displace pixels along both axes with crossing sine waves
+///
+/// `phase` offsets the horizontal and vertical waves, in radians.
+pub fn ripple(img: &RgbImage, amplitude: f32, wavelength: f32, phase: (f32, f32)) -> RgbImage {
+    let rgba = DynamicImage::ImageRgb8(img.clone()).into_rgba8();
+    DynamicImage::ImageRgba8(raster::ripple(&rgba, amplitude, wavelength, phase)).into_rgb8()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn dark_pixels(img: &RgbImage) -> usize {
+        img.pixels().filter(|p| p.0[0] < 128).count()
+    }
+
+    #[test]
+    fn test_primitives_draw() {
+        let mut img = noise_background(100, 60, 1);
+        assert_eq!(img, noise_background(100, 60, 1));
+        assert_eq!(dark_pixels(&img), 0);
+
+        let style = GlyphStyle {
+            x: 20.0,
+            y: 45.0,
+            ..Default::default()
+        };
+        draw_character(&mut img, 'W', &FontSource::Embedded, &style).unwrap();
+        let ink = dark_pixels(&img);
+        assert!(ink > 100);
+
+        draw_interference_line(&mut img, 5.0, 0.0, 0.0, 0, [0, 0, 0]);
+        assert!((0..100).all(|x| img.get_pixel(x, 5).0 == [0, 0, 0]));
+        draw_noise_dot(&mut img, 99, 59, &[(1, 1)], [255, 0, 0]);
+        assert_eq!(img.get_pixel(99, 59).0, [255, 0, 0]);
+
+        let mut blank = RgbImage::from_pixel(50, 50, Rgb([255, 255, 255]));
+        let black = ColorRange::new(Rgb([0, 0, 0]), Rgb([0, 0, 0]));
+        scatter_noise(&mut blank, 10, &[black], &mut StdRng::seed_from_u64(3));
+        assert!(dark_pixels(&blank) >= 10);
+    }
+
+    #[test]
+    fn test_distortions_keep_size() {
+        let mut img = noise_background(80, 40, 2);
+        let style = GlyphStyle {
+            x: 10.0,
+            y: 35.0,
+            size: 30.0,
+            ..Default::default()
+        };
+        draw_character(&mut img, 'H', &FontSource::Embedded, &style).unwrap();
+        let waved = wave_distort(&img, 3.0, 0.1);
+        assert_eq!(waved.dimensions(), (80, 40));
+        assert_ne!(waved, img);
+        assert_eq!(ripple(&img, 0.0, 30.0, (0.0, 0.0)), img);
+    }
+}
@@ -0,0 +1,425 @@
+use image::{Rgba, RgbaImage};
+use rand::Rng;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use rusttype::{point, Font, PositionedGlyph, Scale};
+
+use crate::portable::{cos, seeded_rng, sin};
+
+use super::{
+    Background, ImageEffect, InterferenceLine, NoiseDot, PlacedGlyph, Renderer, Scene, Wave,
+};
+
+/// Renders a scene into an RGBA image
+pub(crate) struct RasterRenderer;
+
+impl RasterRenderer {
+    /// Render the scene with per-glyph overrides and opacities instead of `scene.glyphs`
+    pub fn render_glyphs(&self, scene: &Scene, glyphs: &[(PlacedGlyph, f32)]) -> RgbaImage {
+        let mut img = create_background(
+            scene.width,
+            scene.height,
+            scene.background,
+            scene.background_seed,
+        );
+        for (glyph, opacity) in glyphs {
+            draw_character(
+                &mut img,
+                glyph,
+                &scene.fonts[glyph.font],
+                scene.scale,
+                *opacity,
+            );
+        }
+        for line in &scene.lines {
+            draw_interference_line(&mut img, line);
+        }
+        for dot in &scene.dots {
+            draw_noise_dot(&mut img, dot);
+        }
+        let mut img = add_wave_distortion(&img, scene.wave);
+        apply_image_effects(&mut img, &scene.image_effects);
+        img
+    }
+}
+
+impl Renderer for RasterRenderer {
+    type Output = RgbaImage;
+
+    fn render(&self, scene: &Scene) -> RgbaImage {
+        let glyphs: Vec<_> = scene.glyphs.iter().map(|g| (g.clone(), 1.0)).collect();
+        self.render_glyphs(scene, &glyphs)
+    }
+}
+
+/// Run `f` on every row of pixel data, in parallel when the `rayon` feature is enabled
+fn for_each_row<F>(data: &mut [u8], width: u32, f: F)
+where
+    F: Fn(u32, &mut [u8]) + Send + Sync,
+{
+    let row_len = (width as usize * 4).max(1);
+    #[cfg(feature = "rayon")]
+    data.par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| f(y as u32, row));
+    #[cfg(not(feature = "rayon"))]
+    data.chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| f(y as u32, row));
+}
+
+/// Create the background
+pub(super) fn create_background(
+    width: u32,
+    height: u32,
+    background: Background,
+    seed: u64,
+) -> RgbaImage {
+    let mut img = RgbaImage::new(width, height);
+    for_each_row(&mut img, width, |y, row| {
+        fill_background_row(row, y, height, background, seed)
+    });
+    img
+}
+
+/// Fill one RGBA row of the background
+///
+/// For the noise texture every row draws from its own RNG derived from
+/// `seed`, so rows can be generated in any order with the same result.
+pub(super) fn fill_background_row(
+    row: &mut [u8],
+    y: u32,
+    height: u32,
+    background: Background,
+    seed: u64,
+) {
+    if background != Background::Noise {
+        let color = background.row_color(y, height);
+        for pixel in row.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&color.0);
+        }
+        return;
+    }
+
+    let mut rng = seeded_rng(seed ^ (y as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    for pixel in row.chunks_exact_mut(4) {
+        let base = 245 + rng.gen_range(0..10);
+        let r = base;
+        let g = (base - rng.gen_range(0..5)).clamp(240, 255);
+        let b = (base - rng.gen_range(0..5)).clamp(240, 255);
+        pixel.copy_from_slice(&[r, g, b, 255]);
+    }
+}
+
+/// Draw a single character with rotation, positioning and opacity
+fn draw_character(
+    img: &mut RgbaImage,
+    params: &PlacedGlyph,
+    font: &Font,
+    scale: Scale,
+    opacity: f32,
+) {
+    let (width, height) = img.dimensions();
+    glyph_pixels(
+        params,
+        font,
+        scale,
+        opacity,
+        width,
+        height,
+        |x, y, alpha| {
+            let pixel = img.get_pixel_mut(x, y);
+            *pixel = blend(*pixel, params.color, alpha);
+        },
+    );
+}
+
+impl Scene {
+    /// Number of distinct pixels inside the canvas covered by text
+    ///
+    /// Taken from the glyph layout, before noise and distortion are applied.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn text_pixels(&self) -> usize {
+        let mut covered = vec![false; self.width as usize * self.height as usize];
+        for glyph in &self.glyphs {
+            glyph_pixels(
+                glyph,
+                &self.fonts[glyph.font],
+                self.scale,
+                1.0,
+                self.width,
+                self.height,
+                |x, y, _| covered[y as usize * self.width as usize + x as usize] = true,
+            );
+        }
+        covered.into_iter().filter(|&c| c).count()
+    }
+}
+
+/// Call `f` with the position and coverage of every pixel of a rotated character inside the image
+///
+/// A position can be reported more than once; every report is blended.
+pub(super) fn glyph_pixels<F>(
+    params: &PlacedGlyph,
+    font: &Font,
+    scale: Scale,
+    opacity: f32,
+    width: u32,
+    height: u32,
+    mut f: F,
+) where
+    F: FnMut(u32, u32, f32),
+{
+    let glyph = font.glyph(params.ch).scaled(scale);
+    let Some(bb) = glyph.exact_bounding_box() else {
+        return;
+    };
+    let glyph = glyph.positioned(point(0.0, 0.0));
+
+    let cx = bb.width() / 2.0;
+    let cy = bb.height() / 2.0;
+    let cos_r = cos(params.rotation);
+    let sin_r = sin(params.rotation);
+
+    let mut place = |gx: f32, gy: f32, v: f32| {
+        if v * opacity < 0.01 {
+            return;
+        }
+
+        let mut gx_f = gx - cx;
+        let gy_f = gy - cy;
+        if params.shear != 0.0 {
+            gx_f += params.shear * gy_f;
+        }
+
+        let rotated_x = gx_f * cos_r - gy_f * sin_r;
+        let rotated_y = gx_f * sin_r + gy_f * cos_r;
+
+        let final_x = (rotated_x + cx + params.x_offset + bb.min.x) as i32;
+        let final_y = (rotated_y + cy + params.y_offset + bb.min.y) as i32;
+
+        if final_x >= 0 && final_y >= 0 {
+            let fx = final_x as u32;
+            let fy = final_y as u32;
+
+            if fx < width && fy < height {
+                f(fx, fy, v * opacity);
+            }
+        }
+    };
+
+    match params.outline {
+        None => glyph.draw(|gx, gy, v| place(gx as f32, gy as f32, v)),
+        Some(thickness) => {
+            let (w, h, pad, coverage) = outline_coverage(&glyph, thickness);
+            for gy in 0..h {
+                for gx in 0..w {
+                    let v = coverage[(gy * w + gx) as usize];
+                    place(gx as f32 - pad as f32, gy as f32 - pad as f32, v);
+                }
+            }
+        }
+    }
+}
+
+/// Coverage of a ring `thickness` pixels wide around the glyph's strokes, leaving the strokes empty
+///
+/// Returns the width, height and padding of the grid; the glyph's own pixels
+/// start at (`padding`, `padding`).
+fn outline_coverage(glyph: &PositionedGlyph, thickness: f32) -> (u32, u32, u32, Vec<f32>) {
+    let Some(bb) = glyph.pixel_bounding_box() else {
+        return (0, 0, 0, Vec::new());
+    };
+    let radius = thickness.round().max(1.0) as i32;
+    let pad = radius as u32;
+    let (w, h) = (bb.width() as u32 + 2 * pad, bb.height() as u32 + 2 * pad);
+    let mut coverage = vec![0.0f32; (w * h) as usize];
+    glyph.draw(|x, y, v| {
+        let (x, y) = (x + pad, y + pad);
+        if x < w && y < h {
+            coverage[(y * w + x) as usize] = v;
+        }
+    });
+
+    // Dilate by a disk: `spans[s]` holds each row's maximum over `x - s..=x + s`,
+    // built from `spans[s - 1]`, and each disk row is one of those spans
+    let (wi, hi) = (w as i32, h as i32);
+    let mut spans = vec![coverage];
+    for _ in 0..radius {
+        let prev = spans.last().expect("starts with the coverage");
+        let at = |x: i32, y: i32| {
+            if x < 0 || x >= wi {
+                0.0
+            } else {
+                prev[(y * wi + x) as usize]
+            }
+        };
+        let next = (0..hi)
+            .flat_map(|y| (0..wi).map(move |x| (x, y)))
+            .map(|(x, y)| at(x - 1, y).max(at(x, y)).max(at(x + 1, y)))
+            .collect();
+        spans.push(next);
+    }
+    let half_width = |dy: i32| {
+        (0..=radius)
+            .rev()
+            .find(|s| s * s + dy * dy <= radius * radius)
+    };
+    let rows: Vec<_> = (-radius..=radius)
+        .filter_map(|dy| Some((dy, half_width(dy)? as usize)))
+        .collect();
+
+    let ring = (0..hi)
+        .flat_map(|y| (0..wi).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let dilated = rows
+                .iter()
+                .filter(|(dy, _)| (0..hi).contains(&(y + dy)))
+                .map(|&(dy, s)| spans[s][((y + dy) * wi + x) as usize])
+                .fold(0.0f32, f32::max);
+            (dilated - spans[0][(y * wi + x) as usize]).max(0.0)
+        })
+        .collect();
+    (w, h, pad, ring)
+}
+
+/// Apply the resolved image effects in order
+pub(super) fn apply_image_effects(img: &mut RgbaImage, effects: &[ImageEffect]) {
+    for effect in effects {
+        match effect {
+            ImageEffect::Ripple {
+                amplitude,
+                wavelength,
+                phase,
+            } => *img = ripple(img, *amplitude, *wavelength, *phase),
+            ImageEffect::Custom(custom, seed) => custom.apply(img, *seed),
+        }
+    }
+}
+
+/// Displace every pixel along both axes with crossing sine waves
+pub(super) fn ripple(
+    img: &RgbaImage,
+    amplitude: f32,
+    wavelength: f32,
+    phase: (f32, f32),
+) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let k = std::f32::consts::TAU / wavelength;
+    let mut out = RgbaImage::new(width, height);
+
+    for_each_row(&mut out, width, |y, row| {
+        let dx = (sin(y as f32 * k + phase.0) * amplitude) as i32;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let dy = (sin(x as f32 * k + phase.1) * amplitude) as i32;
+            let sx = (x as i32 + dx).clamp(0, width as i32 - 1) as u32;
+            let sy = (y as i32 + dy).clamp(0, height as i32 - 1) as u32;
+            pixel.copy_from_slice(&img.get_pixel(sx, sy).0);
+        }
+    });
+    out
+}
+
+/// Composite `color` with coverage `alpha` over `bg`
+pub(super) fn blend(bg: Rgba<u8>, color: [u8; 3], alpha: f32) -> Rgba<u8> {
+    let [r, g, b, a] = bg.0;
+    if a == 255 {
+        // Opaque backgrounds keep the exact arithmetic of plain RGB blending
+        let mix = |bg: u8, fg: u8| (bg as f32 * (1.0 - alpha) + fg as f32 * alpha) as u8;
+        return Rgba([mix(r, color[0]), mix(g, color[1]), mix(b, color[2]), 255]);
+    }
+
+    let bg_alpha = a as f32 / 255.0;
+    let out_alpha = alpha + bg_alpha * (1.0 - alpha);
+    if out_alpha <= 0.0 {
+        return bg;
+    }
+    let mix = |bg: u8, fg: u8| {
+        ((bg as f32 * bg_alpha * (1.0 - alpha) + fg as f32 * alpha) / out_alpha) as u8
+    };
+    Rgba([
+        mix(r, color[0]),
+        mix(g, color[1]),
+        mix(b, color[2]),
+        (out_alpha * 255.0).round() as u8,
+    ])
+}
+
+/// Draw a curved interference line across the image
+fn draw_interference_line(img: &mut RgbaImage, line: &InterferenceLine) {
+    let (width, height) = img.dimensions();
+    let color = opaque(line.color);
+    line_pixels(line, width, height, |x, y| img.put_pixel(x, y, color));
+}
+
+/// Call `f` with every pixel covered by an interference line
+pub(super) fn line_pixels<F: FnMut(u32, u32)>(
+    line: &InterferenceLine,
+    width: u32,
+    height: u32,
+    mut f: F,
+) {
+    for x in 0..width {
+        let y = line.start_y + sin(x as f32 * line.frequency) * line.amplitude;
+
+        for dy in -line.thickness..=line.thickness {
+            let py = (y as i32 + dy).max(0).min(height as i32 - 1) as u32;
+            if x < width && py < height {
+                f(x, py);
+            }
+        }
+    }
+}
+
+pub(super) fn opaque(color: [u8; 3]) -> Rgba<u8> {
+    Rgba([color[0], color[1], color[2], 255])
+}
+
+/// Draw a noise dot and its cluster
+fn draw_noise_dot(img: &mut RgbaImage, dot: &NoiseDot) {
+    let (width, height) = img.dimensions();
+    let color = opaque(dot.color);
+    dot_pixels(dot, width, height, |x, y| img.put_pixel(x, y, color));
+}
+
+/// Call `f` with the pixel of a noise dot and then those of its cluster
+pub(super) fn dot_pixels<F: FnMut(u32, u32)>(dot: &NoiseDot, width: u32, height: u32, mut f: F) {
+    f(dot.x, dot.y);
+
+    for &(dx, dy) in &dot.cluster {
+        let nx = (dot.x as i32 + dx).max(0).min(width as i32 - 1) as u32;
+        let ny = (dot.y as i32 + dy).max(0).min(height as i32 - 1) as u32;
+        f(nx, ny);
+    }
+}
+
+/// Apply wave distortion to the image
+pub(super) fn add_wave_distortion(img: &RgbaImage, wave: Wave) -> RgbaImage {
+    let width = img.width();
+    let height = img.height();
+    // Every pixel is overwritten below, so no background is needed
+    let mut new_img = RgbaImage::new(width, height);
+
+    for_each_row(&mut new_img, width, |y, row| {
+        let (start, end) = (
+            y as usize * width as usize * 4,
+            (y as usize + 1) * width as usize * 4,
+        );
+        wave_row(&img.as_raw()[start..end], row, y, wave);
+    });
+
+    new_img
+}
+
+/// Write the wave-distorted version of RGBA row `y` from `src` into `dst`
+///
+/// The wave only shifts pixels horizontally, so every output row depends on
+/// its own source row alone.
+pub(super) fn wave_row(src: &[u8], dst: &mut [u8], y: u32, wave: Wave) {
+    let width = (src.len() / 4) as i32;
+    let offset = wave.offset(y as f32);
+    for (x, pixel) in dst.chunks_exact_mut(4).enumerate() {
+        let src_x = (x as i32 + offset as i32).max(0).min(width - 1) as usize;
+        pixel.copy_from_slice(&src[src_x * 4..src_x * 4 + 4]);
+    }
+}
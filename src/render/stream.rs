@@ -0,0 +1,122 @@
+use std::io::Write;
+
+use super::raster::{
+    blend, dot_pixels, fill_background_row, glyph_pixels, line_pixels, opaque, wave_row,
+};
+use image::ImageError;
+
+use super::{RasterRenderer, Renderer, Scene};
+use crate::encode::png_error;
+use crate::CaptchaError;
+
+/// A pending write to one pixel of a row
+#[derive(Clone, Copy)]
+enum PixelOp {
+    Blend { x: u32, color: [u8; 3], alpha: f32 },
+    Put { x: u32, color: [u8; 3] },
+}
+
+/// Encode a scene as PNG one row at a time
+///
+/// Only two rows of pixels are held at once, plus the sparse list of glyph,
+/// line and dot pixels. The output pixels are identical to the raster renderer.
+/// Image effects need the whole raster, so scenes with any are rendered in
+/// full first.
+pub(crate) fn write_png<W: Write>(scene: &Scene, writer: W) -> Result<(), CaptchaError> {
+    let (width, height) = (scene.width, scene.height);
+    let alpha = !scene.background.is_opaque();
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(if alpha {
+        png::ColorType::Rgba
+    } else {
+        png::ColorType::Rgb
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header().map_err(png_error)?;
+    let mut stream = png_writer.stream_writer().map_err(png_error)?;
+    let mut rgb = Vec::with_capacity(width as usize * 3);
+    let mut write_row = |rgba: &[u8]| {
+        let written = if alpha {
+            stream.write_all(rgba)
+        } else {
+            rgb.clear();
+            for pixel in rgba.chunks_exact(4) {
+                rgb.extend_from_slice(&pixel[..3]);
+            }
+            stream.write_all(&rgb)
+        };
+        // `CaptchaError::Io` means an unreadable font, not a failing writer
+        written.map_err(|e| CaptchaError::Image(ImageError::IoError(e)))
+    };
+
+    if !scene.image_effects.is_empty() {
+        let image = RasterRenderer.render(scene);
+        for row in image.chunks_exact(width as usize * 4) {
+            write_row(row)?;
+        }
+        stream.finish().map_err(png_error)?;
+        png_writer.finish().map_err(png_error)?;
+        return Ok(());
+    }
+
+    let mut ops: Vec<Vec<PixelOp>> = vec![Vec::new(); height as usize];
+    for glyph in &scene.glyphs {
+        let font = &scene.fonts[glyph.font];
+        glyph_pixels(
+            glyph,
+            font,
+            scene.scale,
+            1.0,
+            width,
+            height,
+            |x, y, alpha| {
+                ops[y as usize].push(PixelOp::Blend {
+                    x,
+                    color: glyph.color,
+                    alpha,
+                });
+            },
+        );
+    }
+    for line in &scene.lines {
+        line_pixels(line, width, height, |x, y| {
+            ops[y as usize].push(PixelOp::Put {
+                x,
+                color: line.color,
+            });
+        });
+    }
+    for dot in &scene.dots {
+        dot_pixels(dot, width, height, |x, y| {
+            ops[y as usize].push(PixelOp::Put {
+                x,
+                color: dot.color,
+            });
+        });
+    }
+
+    let mut row = vec![0u8; width as usize * 4];
+    let mut distorted = vec![0u8; width as usize * 4];
+    for (y, row_ops) in ops.into_iter().enumerate() {
+        let y = y as u32;
+        fill_background_row(&mut row, y, height, scene.background, scene.background_seed);
+        for op in row_ops {
+            let (x, value) = match op {
+                PixelOp::Blend { x, color, alpha } => {
+                    let at = x as usize * 4;
+                    let bg = image::Rgba([row[at], row[at + 1], row[at + 2], row[at + 3]]);
+                    (x, blend(bg, color, alpha))
+                }
+                PixelOp::Put { x, color } => (x, opaque(color)),
+            };
+            let at = x as usize * 4;
+            row[at..at + 4].copy_from_slice(&value.0);
+        }
+        wave_row(&row, &mut distorted, y, scene.wave);
+        write_row(&distorted)?;
+    }
+    stream.finish().map_err(png_error)?;
+    png_writer.finish().map_err(png_error)?;
+    Ok(())
+}
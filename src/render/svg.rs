@@ -0,0 +1,242 @@
+use std::fmt::Write;
+
+use rusttype::{point, OutlineBuilder};
+
+use crate::portable::{cos, sin};
+
+use super::{Background, PlacedGlyph, Renderer, Scene, Wave};
+
+/// Renders a scene as an SVG document with vector glyph outlines
+///
+/// Image effects such as ripple only exist for raster output and are skipped.
+pub(crate) struct SvgRenderer;
+
+impl Renderer for SvgRenderer {
+    type Output = String;
+
+    fn render(&self, scene: &Scene) -> String {
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = scene.width,
+            h = scene.height
+        );
+        write_background(&mut svg, scene);
+
+        for glyph in &scene.glyphs {
+            write_glyph(&mut svg, scene, glyph);
+        }
+
+        for line in &scene.lines {
+            let mut d = String::new();
+            let mut x = 0.0;
+            while x <= scene.width as f32 {
+                let y = line.start_y + sin(x * line.frequency) * line.amplitude;
+                let (px, py) = distort(scene.wave, x, y);
+                let _ = write!(
+                    d,
+                    "{}{:.1} {:.1}",
+                    if d.is_empty() { "M" } else { "L" },
+                    px,
+                    py
+                );
+                x += 2.0;
+            }
+            let _ = write!(
+                svg,
+                r#"<path d="{}" fill="none" stroke="{}" stroke-width="{}"/>"#,
+                d,
+                rgb(line.color),
+                line.thickness * 2 + 1
+            );
+        }
+
+        for dot in &scene.dots {
+            let mut d = String::new();
+            for (dx, dy) in std::iter::once((0, 0)).chain(dot.cluster.iter().copied()) {
+                let x = (dot.x as i32 + dx).clamp(0, scene.width as i32 - 1) as f32;
+                let y = (dot.y as i32 + dy).clamp(0, scene.height as i32 - 1) as f32;
+                let (px, py) = distort(scene.wave, x, y);
+                let _ = write!(d, "M{:.1} {:.1}h1v1h-1z", px, py);
+            }
+            let _ = write!(svg, r#"<path d="{}" fill="{}"/>"#, d, rgb(dot.color));
+        }
+
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+/// Map a point of the undistorted canvas to its position after the wave distortion
+fn distort(wave: Wave, x: f32, y: f32) -> (f32, f32) {
+    (x - wave.offset(y), y)
+}
+
+fn write_background(svg: &mut String, scene: &Scene) {
+    let (w, h) = (scene.width, scene.height);
+    match scene.background {
+        Background::Noise => {
+            let _ = write!(
+                svg,
+                r#"<rect width="{}" height="{}" fill="rgb(250,248,248)"/>"#,
+                w, h
+            );
+        }
+        Background::Transparent => {}
+        Background::Solid(color) => {
+            let _ = write!(
+                svg,
+                r#"<rect width="{}" height="{}" fill="{}" fill-opacity="{:.3}"/>"#,
+                w,
+                h,
+                rgb([color[0], color[1], color[2]]),
+                color[3] as f32 / 255.0
+            );
+        }
+        Background::Gradient { top, bottom } => {
+            let _ = write!(
+                svg,
+                r#"<defs><linearGradient id="bg" x1="0" y1="0" x2="0" y2="1"><stop offset="0" stop-color="{}" stop-opacity="{:.3}"/><stop offset="1" stop-color="{}" stop-opacity="{:.3}"/></linearGradient></defs><rect width="{}" height="{}" fill="url(#bg)"/>"#,
+                rgb([top[0], top[1], top[2]]),
+                top[3] as f32 / 255.0,
+                rgb([bottom[0], bottom[1], bottom[2]]),
+                bottom[3] as f32 / 255.0,
+                w,
+                h
+            );
+        }
+    }
+}
+
+fn rgb(color: [u8; 3]) -> String {
+    format!("rgb({},{},{})", color[0], color[1], color[2])
+}
+
+/// Append the outline of a placed glyph as a filled path
+fn write_glyph(svg: &mut String, scene: &Scene, params: &PlacedGlyph) {
+    let glyph = scene.fonts[params.font]
+        .glyph(params.ch)
+        .scaled(scene.scale);
+
+    if let Some(bb) = glyph.exact_bounding_box() {
+        let glyph = glyph.positioned(point(0.0, 0.0));
+        let mut path = PathBuilder {
+            d: String::new(),
+            transform: GlyphTransform {
+                cx: bb.width() / 2.0,
+                cy: bb.height() / 2.0,
+                cos_r: cos(params.rotation),
+                sin_r: sin(params.rotation),
+                shear: params.shear,
+                dx: params.x_offset + bb.min.x,
+                dy: params.y_offset + bb.min.y,
+                wave: scene.wave,
+            },
+        };
+
+        if glyph.build_outline(&mut path) {
+            let paint = match params.outline {
+                Some(thickness) => format!(
+                    r#"fill="none" stroke="{}" stroke-width="{:.1}""#,
+                    rgb(params.color),
+                    thickness
+                ),
+                None => format!(r#"fill="{}""#, rgb(params.color)),
+            };
+            let _ = write!(svg, r#"<path d="{}" {}/>"#, path.d, paint);
+        }
+    }
+}
+
+/// Same rotation and placement the raster renderer applies per pixel
+struct GlyphTransform {
+    cx: f32,
+    cy: f32,
+    cos_r: f32,
+    sin_r: f32,
+    shear: f32,
+    dx: f32,
+    dy: f32,
+    wave: Wave,
+}
+
+impl GlyphTransform {
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let gy = y - self.cy;
+        let gx = x - self.cx + self.shear * gy;
+        let rx = gx * self.cos_r - gy * self.sin_r + self.cx + self.dx;
+        let ry = gx * self.sin_r + gy * self.cos_r + self.cy + self.dy;
+        distort(self.wave, rx, ry)
+    }
+}
+
+/// Collects glyph contours as SVG path data
+struct PathBuilder {
+    d: String,
+    transform: GlyphTransform,
+}
+
+impl PathBuilder {
+    fn point(&mut self, x: f32, y: f32) {
+        let (x, y) = self.transform.apply(x, y);
+        let _ = write!(self.d, "{:.1} {:.1}", x, y);
+    }
+}
+
+impl OutlineBuilder for PathBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.d.push('M');
+        self.point(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.d.push('L');
+        self.point(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.d.push('Q');
+        self.point(x1, y1);
+        self.d.push(' ');
+        self.point(x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.d.push('C');
+        self.point(x1, y1);
+        self.d.push(' ');
+        self.point(x2, y2);
+        self.d.push(' ');
+        self.point(x, y);
+    }
+
+    fn close(&mut self) {
+        self.d.push('Z');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CaptchaConfig;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_svg_document() {
+        let fonts = crate::font::load_fonts(&[]).unwrap();
+        let config = CaptchaConfig::default();
+        let scene = Scene::generate("AB23", &config, fonts, &mut StdRng::seed_from_u64(5));
+        let svg = SvgRenderer.render(&scene);
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>"));
+        assert!(svg.contains(r#"viewBox="0 0 280 100""#));
+        let paths = svg.matches("<path").count();
+        assert_eq!(
+            paths,
+            scene.glyphs.len() + scene.lines.len() + scene.dots.len()
+        );
+    }
+}
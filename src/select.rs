@@ -0,0 +1,129 @@
+//! Picking a challenge by risk
+//!
+//! A [`ChallengeSelector`] maps a risk score from your own signals (IP
+//! reputation, failed attempts, bot detection) to a challenge kind, so low-risk
+//! visitors get an easy challenge and suspicious ones a hard one:
+//!
+//! ```
+//! use captcha_generator::select::ChallengeSelector;
+//! use captcha_generator::CaptchaConfig;
+//!
+//! let selector = ChallengeSelector::by_difficulty(CaptchaConfig::default());
+//! let issued = selector.select(0.9).unwrap().generate().unwrap();
+//! assert!(!issued.answer.is_empty());
+//! ```
+
+use std::fmt;
+
+use crate::kind::{ChallengeKind, TextChallenge};
+use crate::{CaptchaConfig, Difficulty};
+
+/// Challenge kinds by the lowest risk score they are used for
+#[derive(Default)]
+pub struct ChallengeSelector {
+    /// Sorted by ascending minimum risk
+    tiers: Vec<(f32, Box<dyn ChallengeKind>)>,
+}
+
+impl ChallengeSelector {
+    /// Create a selector without tiers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Text CAPTCHAs built on `base`, harder as the risk rises
+    ///
+    /// Scores from 0.0 get [`Difficulty::Easy`], from 0.3 `Medium`, from 0.6
+    /// `Hard` and from 0.85 `Extreme`.
+    pub fn by_difficulty(base: CaptchaConfig) -> Self {
+        let mut selector = Self::new();
+        for (min_risk, difficulty) in [
+            (0.0, Difficulty::Easy),
+            (0.3, Difficulty::Medium),
+            (0.6, Difficulty::Hard),
+            (0.85, Difficulty::Extreme),
+        ] {
+            let mut config = base.clone();
+            difficulty.apply_to(&mut config);
+            selector = selector.with_tier(min_risk, TextChallenge::new(config));
+        }
+        selector
+    }
+
+    /// Use `kind` for risk scores from `min_risk` up to the next tier
+    ///
+    /// A tier with the same minimum risk as an existing one replaces it.
+    pub fn with_tier(mut self, min_risk: f32, kind: impl ChallengeKind + 'static) -> Self {
+        self.tiers.retain(|(risk, _)| *risk != min_risk);
+        let at = self.tiers.partition_point(|(risk, _)| *risk < min_risk);
+        self.tiers.insert(at, (min_risk, Box::new(kind)));
+        self
+    }
+
+    /// The kind for `risk`, or `None` if it is below every tier
+    ///
+    /// A NaN score is treated as the highest risk, so broken signals do not
+    /// lower the bar.
+    pub fn select(&self, risk: f32) -> Option<&dyn ChallengeKind> {
+        let risk = if risk.is_nan() { f32::INFINITY } else { risk };
+        self.tiers
+            .iter()
+            .rev()
+            .find(|(min_risk, _)| *min_risk <= risk)
+            .map(|(_, kind)| kind.as_ref())
+    }
+}
+
+impl fmt::Debug for ChallengeSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.tiers.iter().map(|(risk, kind)| (risk, kind.name())))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kind::IssuedChallenge;
+    use crate::CaptchaError;
+
+    struct Slider;
+
+    impl ChallengeKind for Slider {
+        fn name(&self) -> &str {
+            "slider"
+        }
+
+        fn generate(&self) -> Result<IssuedChallenge, CaptchaError> {
+            Ok(IssuedChallenge {
+                media: Vec::new(),
+                answer: "42".to_string(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_by_difficulty() {
+        let selector = ChallengeSelector::by_difficulty(CaptchaConfig::default());
+        assert_eq!(
+            format!("{:?}", selector),
+            r#"{0.0: "text", 0.3: "text", 0.6: "text", 0.85: "text"}"#
+        );
+        for risk in [0.0, 0.5, 1.0, f32::NAN] {
+            assert!(selector.select(risk).unwrap().generate().is_ok());
+        }
+        assert!(selector.select(-1.0).is_none());
+    }
+
+    #[test]
+    fn test_custom_tiers() {
+        let selector = ChallengeSelector::new()
+            .with_tier(0.8, Slider)
+            .with_tier(0.2, TextChallenge::new(CaptchaConfig::easy()));
+        assert!(selector.select(0.1).is_none());
+        assert_eq!(selector.select(0.2).unwrap().name(), "text");
+        assert_eq!(selector.select(0.95).unwrap().name(), "slider");
+        assert_eq!(format!("{:?}", selector), r#"{0.2: "text", 0.8: "slider"}"#);
+    }
+}
@@ -0,0 +1,620 @@
+//! An expiring in-memory CAPTCHA store
+//!
+//! For small apps without Redis or a database: the store keeps each code under
+//! a random id until it is answered, runs out of attempts or expires.
+//!
+//! ```
+//! use captcha_generator::store::{CaptchaStore, VerifyResult};
+//! use captcha_generator::CaptchaConfig;
+//!
+//! let store = CaptchaStore::new();
+//! let (id, png) = store.issue(CaptchaConfig::default()).unwrap();
+//!
+//! // ...send `id` and the image to the client, then later:
+//! assert!(matches!(
+//!     store.verify(&id, "wrong"),
+//!     VerifyResult::Incorrect { attempts_left: 2 }
+//! ));
+//! ```
+//!
+//! Expired entries are evicted lazily whenever a CAPTCHA is issued; call
+//! [`CaptchaStore::purge_expired`] from a timer to reclaim memory sooner.
+//!
+//! Multi-tenant apps can issue through [`CaptchaStore::issue_for`] to enforce
+//! a [`Quota`] per tenant.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::clock::{Clock, SystemClock};
+use crate::hash::{AnswerHasher, HmacSha256Hasher};
+use crate::i18n::{Locale, MessageCatalog, MessageKey};
+use crate::verify::normalize;
+use crate::{Captcha, CaptchaConfig, CaptchaError};
+
+/// Outcome of [`CaptchaStore::verify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyResult {
+    /// The answer matches; the CAPTCHA is removed and cannot be reused
+    Correct,
+    /// The answer is wrong; the CAPTCHA is removed once no attempts are left
+    Incorrect {
+        /// How many more answers are accepted
+        attempts_left: u32,
+    },
+    /// The CAPTCHA's TTL has passed
+    Expired,
+    /// No CAPTCHA is stored under the id, or it was already used up
+    NotFound,
+}
+
+impl VerifyResult {
+    /// Whether the answer was accepted
+    pub fn is_correct(&self) -> bool {
+        *self == VerifyResult::Correct
+    }
+
+    /// The catalog key for the user-facing message of a rejected answer
+    pub fn message_key(&self) -> Option<MessageKey> {
+        match self {
+            VerifyResult::Correct => None,
+            VerifyResult::Incorrect { .. } => Some(MessageKey::CodeMismatch),
+            VerifyResult::Expired => Some(MessageKey::CodeExpired),
+            VerifyResult::NotFound => Some(MessageKey::ChallengeNotFound),
+        }
+    }
+}
+
+/// Limits on the CAPTCHAs one tenant can have issued
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quota {
+    /// Most CAPTCHAs that are neither answered, used up nor expired at once
+    pub max_outstanding: Option<usize>,
+    /// Most CAPTCHAs issued within any window of this length
+    pub max_rate: Option<(u32, Duration)>,
+}
+
+/// Which limit of a [`Quota`] was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaLimit {
+    /// [`Quota::max_outstanding`]
+    Outstanding(usize),
+    /// [`Quota::max_rate`]
+    Rate(u32, Duration),
+}
+
+/// Errors from issuing a CAPTCHA through [`CaptchaStore::issue_for`]
+#[derive(Debug)]
+pub enum StoreError {
+    /// The tenant has reached a limit of its quota; HTTP integrations should
+    /// answer `429 Too Many Requests`
+    QuotaExceeded {
+        /// The tenant that was refused
+        tenant: String,
+        /// The limit that was reached
+        limit: QuotaLimit,
+    },
+    /// The CAPTCHA could not be generated
+    Captcha(CaptchaError),
+}
+
+impl StoreError {
+    /// The catalog key for this error's user-facing message
+    pub fn message_key(&self) -> MessageKey {
+        match self {
+            StoreError::QuotaExceeded { .. } => MessageKey::QuotaExceeded,
+            StoreError::Captcha(e) => e.message_key(),
+        }
+    }
+
+    /// The user-facing message for this error, without internal details
+    pub fn localized<'c>(&self, catalog: &'c dyn MessageCatalog) -> &'c str {
+        catalog.message(self.message_key())
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::QuotaExceeded { tenant, limit } => {
+                let message = self.localized(&Locale::En);
+                match limit {
+                    QuotaLimit::Outstanding(max) => write!(
+                        f,
+                        "{}: tenant {:?} has {} outstanding CAPTCHAs",
+                        message, tenant, max
+                    ),
+                    QuotaLimit::Rate(max, window) => write!(
+                        f,
+                        "{}: tenant {:?} was issued {} CAPTCHAs in {:?}",
+                        message, tenant, max, window
+                    ),
+                }
+            }
+            StoreError::Captcha(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::QuotaExceeded { .. } => None,
+            StoreError::Captcha(e) => Some(e),
+        }
+    }
+}
+
+impl From<CaptchaError> for StoreError {
+    fn from(e: CaptchaError) -> Self {
+        StoreError::Captcha(e)
+    }
+}
+
+struct Entry {
+    /// Keyed hash of the normalized code, salted with the id
+    answer_hash: [u8; 32],
+    /// Clock time at which the entry expires
+    expires_at: Duration,
+    attempts_left: u32,
+    /// Set for CAPTCHAs issued through [`CaptchaStore::issue_for`]
+    tenant: Option<String>,
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("expires_at", &self.expires_at)
+            .field("attempts_left", &self.attempts_left)
+            .field("tenant", &self.tenant)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Issues CAPTCHAs and remembers their codes until they are verified or expire
+///
+/// Codes are held only as keyed hashes, so a memory dump of the store does not
+/// reveal answers.
+pub struct CaptchaStore {
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Recent issue times per tenant, for rate quotas; locked after `entries`
+    issued: Mutex<HashMap<String, VecDeque<Duration>>>,
+    ttl: Duration,
+    max_attempts: u32,
+    case_sensitive: bool,
+    default_quota: Quota,
+    quotas: HashMap<String, Quota>,
+    hasher: Arc<dyn AnswerHasher>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CaptchaStore {
+    /// Create a store with a 5 minute TTL, 3 attempts and case-insensitive answers
+    ///
+    /// Codes are hashed with HMAC-SHA256 under a random key.
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            issued: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(300),
+            max_attempts: 3,
+            case_sensitive: false,
+            default_quota: Quota::default(),
+            quotas: HashMap::new(),
+            hasher: Arc::new(HmacSha256Hasher::random()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Hash codes with `hasher`, e.g. to use a key shared with other processes
+    pub fn with_hasher(mut self, hasher: impl AnswerHasher + 'static) -> Self {
+        self.hasher = Arc::new(hasher);
+        self
+    }
+
+    /// Set how long issued CAPTCHAs can be answered
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set how many answers are accepted per CAPTCHA (at least one)
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Read the time for TTLs and rate quotas from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Require answers to match the code's case exactly
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Set the quota of tenants without a quota of their own (unlimited by default)
+    pub fn with_default_quota(mut self, quota: Quota) -> Self {
+        self.default_quota = quota;
+        self
+    }
+
+    /// Set the quota of one tenant
+    pub fn with_tenant_quota(mut self, tenant: impl Into<String>, quota: Quota) -> Self {
+        self.quotas.insert(tenant.into(), quota);
+        self
+    }
+
+    /// Generate a CAPTCHA and store its code, returning a fresh id and the PNG bytes
+    ///
+    /// No quota applies; see [`CaptchaStore::issue_for`].
+    pub fn issue(&self, config: CaptchaConfig) -> Result<(String, Vec<u8>), CaptchaError> {
+        let (id, png, code) = render(config)?;
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires_at > now);
+        let entry = self.entry(&id, &code, now, None);
+        entries.insert(id.clone(), entry);
+        Ok((id, png))
+    }
+
+    /// Like [`CaptchaStore::issue`], but counted against `tenant`'s quota
+    ///
+    /// The quota is checked before rendering, so refused requests cost
+    /// almost nothing, and again before storing.
+    pub fn issue_for(
+        &self,
+        tenant: &str,
+        config: CaptchaConfig,
+    ) -> Result<(String, Vec<u8>), StoreError> {
+        let quota = self.quotas.get(tenant).unwrap_or(&self.default_quota);
+        drop(self.check_quota(tenant, quota, self.clock.now(), false)?);
+        let (id, png, code) = render(config)?;
+
+        let now = self.clock.now();
+        let mut entries = self.check_quota(tenant, quota, now, true)?;
+        let entry = self.entry(&id, &code, now, Some(tenant));
+        entries.insert(id.clone(), entry);
+        Ok((id, png))
+    }
+
+    /// Evict expired entries and fail if `tenant` is at a limit of `quota`
+    ///
+    /// With `record`, the issue is counted towards the rate, and the entries
+    /// stay locked so the caller can insert without racing other issuers.
+    fn check_quota(
+        &self,
+        tenant: &str,
+        quota: &Quota,
+        now: Duration,
+        record: bool,
+    ) -> Result<MutexGuard<'_, HashMap<String, Entry>>, StoreError> {
+        let exceeded = |limit| StoreError::QuotaExceeded {
+            tenant: tenant.to_string(),
+            limit,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires_at > now);
+        if let Some(max) = quota.max_outstanding {
+            let outstanding = entries
+                .values()
+                .filter(|entry| entry.tenant.as_deref() == Some(tenant))
+                .count();
+            if outstanding >= max {
+                return Err(exceeded(QuotaLimit::Outstanding(max)));
+            }
+        }
+
+        if let Some((max, window)) = quota.max_rate {
+            let mut issued = self.issued.lock().unwrap();
+            let times = issued.entry(tenant.to_string()).or_default();
+            while times
+                .front()
+                .is_some_and(|&time| now.saturating_sub(time) >= window)
+            {
+                times.pop_front();
+            }
+            let full = times.len() >= max as usize;
+            if record && !full {
+                times.push_back(now);
+            }
+            // Tenants without recent issues take no memory
+            if times.is_empty() {
+                issued.remove(tenant);
+            }
+            if full {
+                return Err(exceeded(QuotaLimit::Rate(max, window)));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn entry(&self, id: &str, code: &str, now: Duration, tenant: Option<&str>) -> Entry {
+        Entry {
+            answer_hash: self
+                .hasher
+                .hash(id.as_bytes(), &normalize(code, self.case_sensitive)),
+            expires_at: now + self.ttl,
+            attempts_left: self.max_attempts,
+            tenant: tenant.map(str::to_string),
+        }
+    }
+
+    /// Check `input` against the CAPTCHA stored under `id`
+    pub fn verify(&self, id: &str, input: &str) -> VerifyResult {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(id) else {
+            return VerifyResult::NotFound;
+        };
+
+        if self.clock.now() >= entry.expires_at {
+            entries.remove(id);
+            return VerifyResult::Expired;
+        }
+
+        if self.hasher.verify(
+            id.as_bytes(),
+            &normalize(input, self.case_sensitive),
+            &entry.answer_hash,
+        ) {
+            entries.remove(id);
+            return VerifyResult::Correct;
+        }
+
+        entry.attempts_left -= 1;
+        let attempts_left = entry.attempts_left;
+        if attempts_left == 0 {
+            entries.remove(id);
+        }
+        VerifyResult::Incorrect { attempts_left }
+    }
+
+    /// Drop every expired CAPTCHA, and the issue times of tenants that are
+    /// outside their rate window
+    pub fn purge_expired(&self) {
+        let now = self.clock.now();
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.expires_at > now);
+        self.issued.lock().unwrap().retain(|tenant, times| {
+            let quota = self.quotas.get(tenant).unwrap_or(&self.default_quota);
+            // The newest issue is last
+            match (quota.max_rate, times.back()) {
+                (Some((_, window)), Some(&time)) => now.saturating_sub(time) < window,
+                _ => false,
+            }
+        });
+    }
+
+    /// Number of CAPTCHAs currently held, including expired ones not yet purged
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether no CAPTCHAs are held
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for CaptchaStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CaptchaStore")
+            .field("entries", &self.entries)
+            .field("ttl", &self.ttl)
+            .field("max_attempts", &self.max_attempts)
+            .field("case_sensitive", &self.case_sensitive)
+            .field("default_quota", &self.default_quota)
+            .field("quotas", &self.quotas)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for CaptchaStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Generate a CAPTCHA, returning a fresh id, the PNG bytes and the code
+fn render(config: CaptchaConfig) -> Result<(String, Vec<u8>, String), CaptchaError> {
+    let captcha = Captcha::try_with_config(config)?;
+    let png = captcha.to_png_bytes()?;
+    Ok((random_id(), png, captcha.code))
+}
+
+/// A random version 4 UUID, e.g. `"0b5f3c2a-9d1e-4f6b-8a7c-2e4d6f8a0b1c"`
+fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config with a random seed, and the code it renders
+    fn seeded() -> (CaptchaConfig, String) {
+        let config = CaptchaConfig {
+            seed: Some(OsRng.next_u64()),
+            ..Default::default()
+        };
+        let code = Captcha::with_config(config.clone()).code;
+        (config, code)
+    }
+
+    fn issue(store: &CaptchaStore) -> (String, String) {
+        let (config, code) = seeded();
+        let (id, png) = store.issue(config).unwrap();
+        assert!(!png.is_empty());
+        (id, code)
+    }
+
+    #[test]
+    fn test_correct_answer_is_single_use() {
+        let store = CaptchaStore::new();
+        let (id, code) = issue(&store);
+        assert_eq!(id.len(), 36);
+        assert_eq!(
+            store.verify(&id, &format!(" {} ", code.to_lowercase())),
+            VerifyResult::Correct
+        );
+        assert_eq!(store.verify(&id, &code), VerifyResult::NotFound);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_attempts_run_out() {
+        let store = CaptchaStore::new().with_max_attempts(2);
+        let (id, code) = issue(&store);
+        assert_eq!(
+            store.verify(&id, "nope"),
+            VerifyResult::Incorrect { attempts_left: 1 }
+        );
+        assert_eq!(
+            store.verify(&id, "nope"),
+            VerifyResult::Incorrect { attempts_left: 0 }
+        );
+        assert_eq!(store.verify(&id, &code), VerifyResult::NotFound);
+    }
+
+    #[test]
+    fn test_expiry_and_eviction() {
+        let store = CaptchaStore::new().with_ttl(Duration::ZERO);
+        let (id, code) = issue(&store);
+        assert_eq!(store.verify(&id, &code), VerifyResult::Expired);
+        assert_eq!(
+            VerifyResult::Expired.message_key(),
+            Some(MessageKey::CodeExpired)
+        );
+
+        issue(&store);
+        issue(&store);
+        assert_eq!(store.len(), 1);
+        store.purge_expired();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_tenant_quotas() {
+        let store = CaptchaStore::new()
+            .with_default_quota(Quota {
+                max_outstanding: Some(2),
+                max_rate: None,
+            })
+            .with_tenant_quota(
+                "small",
+                Quota {
+                    max_outstanding: None,
+                    max_rate: Some((1, Duration::from_secs(60))),
+                },
+            );
+        let config = CaptchaConfig::default;
+
+        let (seeded, code) = seeded();
+        let (first, _) = store.issue_for("acme", seeded).unwrap();
+        store.issue_for("acme", config()).unwrap();
+        let refused = store.issue_for("acme", config()).unwrap_err();
+        assert!(matches!(
+            refused,
+            StoreError::QuotaExceeded {
+                limit: QuotaLimit::Outstanding(2),
+                ..
+            }
+        ));
+        assert_eq!(refused.message_key(), MessageKey::QuotaExceeded);
+
+        // Answering frees a slot, and other tenants are counted separately
+        assert!(store.verify(&first, &code).is_correct());
+        store.issue_for("acme", config()).unwrap();
+        store.issue_for("other", config()).unwrap();
+        store.issue(config()).unwrap();
+
+        store.issue_for("small", config()).unwrap();
+        assert!(matches!(
+            store.issue_for("small", config()),
+            Err(StoreError::QuotaExceeded {
+                limit: QuotaLimit::Rate(1, _),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_codes_are_not_stored_in_plain_text() {
+        let store = CaptchaStore::new().with_hasher(HmacSha256Hasher::new(b"key".to_vec()));
+        let (id, code) = issue(&store);
+        let hash = store.entries.lock().unwrap()[&id].answer_hash;
+        assert!(!hash.windows(code.len()).any(|w| w == code.as_bytes()));
+        assert_eq!(
+            hash,
+            HmacSha256Hasher::new(b"key".to_vec()).hash(id.as_bytes(), &code)
+        );
+        assert!(!format!("{:?}", store).contains(&code));
+        assert!(store.verify(&id, &code).is_correct());
+    }
+
+    #[test]
+    fn test_manual_clock_drives_expiry_and_rates() {
+        let clock = Arc::new(crate::clock::ManualClock::new(Duration::from_secs(1000)));
+        let store = CaptchaStore::new()
+            .with_clock(clock.clone())
+            .with_default_quota(Quota {
+                max_outstanding: None,
+                max_rate: Some((1, Duration::from_secs(60))),
+            });
+        let (config, code) = seeded();
+        let (id, _) = store.issue_for("acme", config).unwrap();
+        assert!(store.issue_for("acme", CaptchaConfig::default()).is_err());
+
+        clock.advance(Duration::from_secs(60));
+        store.issue_for("acme", CaptchaConfig::default()).unwrap();
+        assert_eq!(
+            store.verify(&id, "nope"),
+            VerifyResult::Incorrect { attempts_left: 2 }
+        );
+
+        clock.advance(Duration::from_secs(240));
+        assert_eq!(store.verify(&id, &code), VerifyResult::Expired);
+        store.purge_expired();
+        assert_eq!(store.len(), 1);
+        // Issue times outside the window are forgotten too
+        assert!(store.issued.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_refused_tenants_are_not_remembered() {
+        let store = CaptchaStore::new().with_default_quota(Quota {
+            max_outstanding: None,
+            max_rate: Some((0, Duration::from_secs(60))),
+        });
+        for i in 0..10 {
+            assert!(store
+                .issue_for(&format!("tenant-{}", i), CaptchaConfig::default())
+                .is_err());
+        }
+        assert!(store.issued.lock().unwrap().is_empty());
+    }
+}
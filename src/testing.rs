@@ -0,0 +1,326 @@
+//! Helpers for testing code that embeds the crate
+//!
+//! Enable the `testing` feature to use them. [`any_config`] is a
+//! [proptest](https://docs.rs/proptest) strategy over realistic
+//! configurations, and [`check_invariants`] asserts the properties every
+//! generated CAPTCHA must have:
+//!
+//! ```
+//! use captcha_generator::testing::{any_config, check_invariants};
+//! use captcha_generator::Captcha;
+//! use proptest::prelude::*;
+//!
+//! proptest!(ProptestConfig::with_cases(4), |(config in any_config())| {
+//!     let captcha = Captcha::try_with_config(config.clone()).unwrap();
+//!     check_invariants(&config, &captcha).unwrap();
+//! });
+//! ```
+//!
+//! For golden tests, [`diff_images`] compares two renders the way a person
+//! would, and [`ink_coverage`] measures how much of the canvas the text takes.
+
+use image::RgbaImage;
+use proptest::prelude::*;
+
+use crate::{
+    Background, Captcha, CaptchaConfig, CharsetPreset, ColorTheme, Difficulty, Effect,
+    HorizontalAlign, Spacing, TextAlignment, VerticalAnchor,
+};
+
+/// Configurations across sizes, charsets, difficulties, themes, layouts and effects
+///
+/// The font size is kept proportional to the height so the text fits, and
+/// every configuration is seeded so failures can be replayed.
+pub fn any_config() -> impl Strategy<Value = CaptchaConfig> {
+    let size = (60u32..480, 30u32..200, 1usize..10, 0.3f32..0.7);
+    let look = (
+        prop::sample::select(CharsetPreset::ALL),
+        prop::sample::select(vec![
+            Difficulty::Easy,
+            Difficulty::Medium,
+            Difficulty::Hard,
+            Difficulty::Extreme,
+        ]),
+        prop::sample::select(vec![
+            ColorTheme::light(),
+            ColorTheme::dark(),
+            ColorTheme {
+                background: Background::Transparent,
+                ..Default::default()
+            },
+        ]),
+    );
+    let layout = (
+        prop_oneof![
+            (-4.0f32..16.0).prop_map(Spacing::Advance),
+            (-4.0f32..16.0).prop_map(Spacing::Optical),
+        ],
+        prop::sample::select(vec![
+            HorizontalAlign::Left,
+            HorizontalAlign::Center,
+            HorizontalAlign::Right,
+        ]),
+        prop::sample::select(vec![
+            VerticalAnchor::Top,
+            VerticalAnchor::Middle,
+            VerticalAnchor::Bottom,
+        ]),
+        0.0f32..12.0,
+    );
+    let extra = (
+        prop::collection::vec(
+            prop_oneof![
+                (0.0f32..0.4).prop_map(Effect::Shear),
+                (0.0f32..6.0).prop_map(Effect::Outline),
+                (1.0f32..3.0, 20.0f32..60.0).prop_map(|(amplitude, wavelength)| {
+                    Effect::Ripple {
+                        amplitude,
+                        wavelength,
+                    }
+                }),
+            ],
+            0..3,
+        ),
+        any::<u64>(),
+    );
+
+    (size, look, layout, extra).prop_map(
+        |(
+            (width, height, code_length, font_scale),
+            (charset, difficulty, theme),
+            (spacing, horizontal, vertical, margin),
+            (effects, seed),
+        )| {
+            let mut config = CaptchaConfig::with_difficulty(difficulty);
+            config.effects.extend(effects);
+            CaptchaConfig {
+                width,
+                height,
+                code_length,
+                charset: charset.chars(),
+                font_size: height as f32 * font_scale,
+                spacing,
+                alignment: TextAlignment {
+                    horizontal,
+                    vertical,
+                    margin,
+                },
+                theme,
+                seed: Some(seed),
+                ..config
+            }
+        },
+    )
+}
+
+/// Number of text pixels of `captcha` that land inside the canvas
+///
+/// Counted from the glyph layout before noise and distortion, so it does not
+/// depend on the text standing out from the background.
+pub fn text_ink_in_canvas(captcha: &Captcha) -> usize {
+    captcha.scene.text_pixels()
+}
+
+/// Check the properties every CAPTCHA generated from `config` must have
+///
+/// The image has the configured size, the code has the configured length and
+/// only uses characters of the charset, and some text is drawn on the canvas.
+pub fn check_invariants(config: &CaptchaConfig, captcha: &Captcha) -> Result<(), String> {
+    let dimensions = captcha.image.dimensions();
+    if dimensions != (config.width, config.height) {
+        return Err(format!(
+            "image is {:?}, expected {:?}",
+            dimensions,
+            (config.width, config.height)
+        ));
+    }
+    if let Some(rgba) = captcha.rgba_image() {
+        if rgba.dimensions() != dimensions {
+            return Err(format!("RGBA image is {:?}", rgba.dimensions()));
+        }
+    }
+    if captcha.code.chars().count() != config.code_length {
+        return Err(format!(
+            "code {:?} does not have {} characters",
+            captcha.code, config.code_length
+        ));
+    }
+    if let Some(ch) = captcha.code.chars().find(|&c| !config.charset.contains(c)) {
+        return Err(format!("{:?} is not in the charset", ch));
+    }
+    if text_ink_in_canvas(captcha) == 0 {
+        return Err("no text is drawn inside the canvas".to_string());
+    }
+    Ok(())
+}
+
+/// How two images differ, as returned by [`diff_images`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDiff {
+    /// Number of pixels whose perceptual difference exceeds the threshold
+    pub differing: usize,
+    /// Number of pixels compared
+    pub total: usize,
+    /// Largest perceptual difference of any pixel, from 0.0 to 1.0
+    pub max_delta: f32,
+}
+
+impl ImageDiff {
+    /// Share of the pixels that differ, from 0.0 to 1.0
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.differing as f32 / self.total as f32
+        }
+    }
+
+    /// Whether no pixel differs
+    pub fn is_identical(&self) -> bool {
+        self.differing == 0
+    }
+}
+
+/// Compare two images pixel by pixel, or `None` if their sizes differ
+///
+/// Pixels are blended over white and compared in the YIQ color space with
+/// luminance weighted highest, so a change a person would hardly notice
+/// scores lower than one in brightness. A pixel differs when its difference,
+/// from 0.0 to 1.0, is above `threshold`; around 0.1 ignores encoder noise.
+pub fn diff_images(actual: &RgbaImage, expected: &RgbaImage, threshold: f32) -> Option<ImageDiff> {
+    if actual.dimensions() != expected.dimensions() {
+        return None;
+    }
+    let mut diff = ImageDiff {
+        differing: 0,
+        total: actual.pixels().len(),
+        max_delta: 0.0,
+    };
+    for (a, b) in actual.pixels().zip(expected.pixels()) {
+        let delta = perceptual_delta(a.0, b.0);
+        diff.max_delta = diff.max_delta.max(delta);
+        if delta > threshold {
+            diff.differing += 1;
+        }
+    }
+    Some(diff)
+}
+
+/// Largest possible value of the weighted YIQ distance of two colors
+const MAX_YIQ_DELTA: f32 = 35215.0;
+
+/// Perceptual difference between two pixels, from 0.0 to 1.0
+fn perceptual_delta(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let yiq = |[r, g, b, alpha]: [u8; 4]| {
+        // Over white, so transparent regions compare equal whatever their color
+        let alpha = alpha as f32 / 255.0;
+        let over_white = |c: u8| 255.0 + (c as f32 - 255.0) * alpha;
+        let (r, g, b) = (over_white(r), over_white(g), over_white(b));
+        (
+            r * 0.298_9 + g * 0.586_6 + b * 0.114_5,
+            r * 0.596_0 - g * 0.274_2 - b * 0.321_8,
+            r * 0.211_5 - g * 0.522_6 + b * 0.311_1,
+        )
+    };
+    let (y1, i1, q1) = yiq(a);
+    let (y2, i2, q2) = yiq(b);
+    let (y, i, q) = (y1 - y2, i1 - i2, q1 - q2);
+    ((0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / MAX_YIQ_DELTA).min(1.0)
+}
+
+/// Share of the canvas covered by the text of `captcha`, from 0.0 to 1.0
+///
+/// Useful to catch layouts that push the text off the image or shrink it to
+/// nothing; see [`text_ink_in_canvas`] for how it is measured.
+pub fn ink_coverage(captcha: &Captcha) -> f32 {
+    let (width, height) = captcha.image.dimensions();
+    text_ink_in_canvas(captcha) as f32 / (width as f32 * height as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(24))]
+
+        #[test]
+        fn prop_generated_captchas_hold_invariants(config in any_config()) {
+            let captcha = Captcha::try_with_config(config.clone()).unwrap();
+            if let Err(e) = check_invariants(&config, &captcha) {
+                return Err(TestCaseError::fail(e));
+            }
+        }
+
+        #[test]
+        fn prop_svg_and_png_never_fail(config in any_config()) {
+            let captcha = Captcha::try_with_config(config).unwrap();
+            prop_assert!(captcha.to_png_bytes().is_ok());
+            prop_assert!(captcha.to_svg_string().starts_with("<svg"));
+        }
+
+        #[test]
+        fn prop_clamped_configs_hold_invariants(
+            width in 0u32..2000,
+            height in 0u32..600,
+            code_length in 0usize..100,
+            font_size in any::<f32>(),
+            rotation in any::<f32>(),
+            lines in (0usize..200, 0usize..200),
+        ) {
+            let mut config = CaptchaConfig {
+                width,
+                height,
+                code_length,
+                font_size,
+                rotation,
+                interference_lines: lines,
+                seed: Some(1),
+                ..Default::default()
+            };
+            let (captcha, _) = Captcha::with_config_clamped(config.clone()).unwrap();
+            config.clamp();
+            prop_assert_eq!(captcha.image.dimensions(), (config.width, config.height));
+            prop_assert_eq!(captcha.code.chars().count(), config.code_length);
+        }
+    }
+
+    fn seeded(seed: u64) -> Captcha {
+        Captcha::with_config(CaptchaConfig {
+            seed: Some(seed),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_diff_images() {
+        let a = seeded(1).to_dynamic_image().to_rgba8();
+        let diff = diff_images(&a, &a, 0.0).unwrap();
+        assert!(diff.is_identical());
+        assert_eq!(diff.max_delta, 0.0);
+
+        let b = seeded(2).to_dynamic_image().to_rgba8();
+        let diff = diff_images(&a, &b, 0.1).unwrap();
+        assert!(diff.fraction() > 0.01);
+        assert!(diff.max_delta > 0.1);
+
+        let small = RgbaImage::new(4, 4);
+        assert_eq!(diff_images(&a, &small, 0.1), None);
+    }
+
+    #[test]
+    fn test_perceptual_delta() {
+        assert!(perceptual_delta([0, 0, 0, 255], [255, 255, 255, 255]) > 0.9);
+        assert_eq!(perceptual_delta([0, 0, 0, 0], [200, 10, 10, 0]), 0.0);
+        // A slight shift in hue matters less than the same shift in brightness
+        let hue = perceptual_delta([100, 100, 100, 255], [110, 100, 90, 255]);
+        let brightness = perceptual_delta([100, 100, 100, 255], [110, 110, 110, 255]);
+        assert!(hue < brightness);
+    }
+
+    #[test]
+    fn test_ink_coverage() {
+        let coverage = ink_coverage(&seeded(1));
+        assert!(coverage > 0.02 && coverage < 0.5, "{}", coverage);
+    }
+}
@@ -0,0 +1,116 @@
+//! Color themes for the text, interference lines, noise and background
+
+use image::{Rgb, Rgba};
+use rand::Rng;
+
+use crate::render::Background;
+
+/// A box of colors, each channel drawn independently from `min..=max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorRange {
+    /// Lower bound for every channel
+    pub min: Rgb<u8>,
+    /// Upper bound for every channel (inclusive)
+    pub max: Rgb<u8>,
+}
+
+impl ColorRange {
+    /// Colors between `min` and `max`
+    pub fn new(min: Rgb<u8>, max: Rgb<u8>) -> Self {
+        Self { min, max }
+    }
+
+    /// Grays with every channel between `min` and `max`
+    pub fn gray(min: u8, max: u8) -> Self {
+        Self::new(Rgb([min; 3]), Rgb([max; 3]))
+    }
+
+    /// Exactly one color
+    pub fn solid(color: Rgb<u8>) -> Self {
+        Self::new(color, color)
+    }
+
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> [u8; 3] {
+        [0, 1, 2].map(|c| {
+            let (a, b) = (self.min[c], self.max[c]);
+            rng.gen_range(a.min(b)..=a.max(b))
+        })
+    }
+}
+
+/// Colors used to draw a CAPTCHA
+///
+/// Every palette element draws one of its ranges at random, so a palette with
+/// several ranges mixes distinct color families.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorTheme {
+    /// What the CAPTCHA is drawn on
+    pub background: Background,
+    /// Colors of the characters
+    pub text: Vec<ColorRange>,
+    /// Colors of the interference lines
+    pub interference: Vec<ColorRange>,
+    /// Colors of the noise dots
+    pub noise: Vec<ColorRange>,
+}
+
+impl ColorTheme {
+    /// Dark text on a light, noisy background (the default)
+    pub fn light() -> Self {
+        Self {
+            background: Background::Noise,
+            text: vec![ColorRange::gray(30, 69)],
+            interference: vec![ColorRange::gray(180, 209)],
+            noise: vec![ColorRange::gray(200, 229), ColorRange::gray(80, 139)],
+        }
+    }
+
+    /// Light text on a dark background, for dark-mode pages
+    pub fn dark() -> Self {
+        Self {
+            background: Background::Gradient {
+                top: Rgba([38, 40, 48, 255]),
+                bottom: Rgba([26, 28, 34, 255]),
+            },
+            text: vec![ColorRange::gray(200, 240)],
+            interference: vec![ColorRange::gray(70, 95)],
+            noise: vec![ColorRange::gray(45, 65), ColorRange::gray(120, 170)],
+        }
+    }
+}
+
+impl Default for ColorTheme {
+    fn default() -> Self {
+        Self::light()
+    }
+}
+
+/// Draw a color from one of the ranges in `palette`, black if it is empty
+pub(crate) fn pick_color<R: Rng + ?Sized>(palette: &[ColorRange], rng: &mut R) -> [u8; 3] {
+    let range = match palette.len() {
+        0 => return [0, 0, 0],
+        1 => palette[0],
+        n => palette[crate::portable::index(rng, n)],
+    };
+    range.sample(rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_pick_color_stays_in_range() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let palette = [ColorRange::new(Rgb([10, 100, 200]), Rgb([20, 100, 210]))];
+        for _ in 0..100 {
+            let [r, g, b] = pick_color(&palette, &mut rng);
+            assert!((10..=20).contains(&r));
+            assert_eq!(g, 100);
+            assert!((200..=210).contains(&b));
+        }
+        assert_eq!(pick_color(&[], &mut rng), [0, 0, 0]);
+    }
+}
@@ -0,0 +1,229 @@
+//! Every random choice behind a rendered CAPTCHA
+//!
+//! [`Captcha::trace`] lists the parameters each rendering stage picked: the
+//! placement of every character, the interference lines, the noise dots, the
+//! wave and the image effects. Logged next to an image that users report as
+//! unreadable, it shows exactly what was drawn, and with the `serde` feature
+//! it serializes for offline inspection:
+//!
+//! ```
+//! use captcha_generator::Captcha;
+//!
+//! let captcha = Captcha::new();
+//! let trace = captcha.trace();
+//! assert_eq!(trace.glyphs.len(), captcha.code.chars().count());
+//! println!("rotations: {:?}", trace.glyphs.iter().map(|g| g.rotation).collect::<Vec<_>>());
+//! ```
+//!
+//! The trace is built from parameters every [`Captcha`] keeps anyway, so
+//! rendering does not get slower when it is not used.
+
+use crate::render::ImageEffect;
+use crate::Captcha;
+
+/// The random parameters of every rendering stage, in the order they are drawn
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RenderTrace {
+    /// Image width in pixels
+    pub width: u32,
+    /// Image height in pixels
+    pub height: u32,
+    /// Font size in pixels
+    pub font_size: f32,
+    /// Seed of the per-pixel background texture
+    pub background_seed: u64,
+    /// Placement of each character of the code
+    pub glyphs: Vec<GlyphTrace>,
+    /// Interference lines, in drawing order
+    pub lines: Vec<LineTrace>,
+    /// Noise dots, in drawing order
+    pub dots: Vec<DotTrace>,
+    /// The horizontal wave distortion
+    pub wave: WaveTrace,
+    /// Image effects applied after the wave, in order
+    pub effects: Vec<EffectTrace>,
+}
+
+/// Where and how one character was drawn
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GlyphTrace {
+    /// The character
+    pub ch: char,
+    /// Index into the configured fonts (0 for the embedded font)
+    pub font: usize,
+    /// Left edge of the glyph's bounding box in pixels
+    pub x: f32,
+    /// Baseline position in pixels
+    pub y: f32,
+    /// Rotation in radians
+    pub rotation: f32,
+    /// Horizontal shear factor
+    pub shear: f32,
+    /// Outline thickness when drawn hollow
+    pub outline: Option<f32>,
+    /// Text color
+    pub color: [u8; 3],
+}
+
+/// A sine-shaped interference line
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineTrace {
+    /// Vertical position at the left edge
+    pub start_y: f32,
+    /// Vertical amplitude in pixels
+    pub amplitude: f32,
+    /// Angular frequency per pixel
+    pub frequency: f32,
+    /// Thickness in pixels
+    pub thickness: i32,
+    /// Line color
+    pub color: [u8; 3],
+}
+
+/// A noise dot and the offsets of its cluster
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DotTrace {
+    /// Horizontal position
+    pub x: u32,
+    /// Vertical position
+    pub y: u32,
+    /// Dot color
+    pub color: [u8; 3],
+    /// Neighbouring pixels painted with the same color
+    pub cluster: Vec<(i32, i32)>,
+}
+
+/// The horizontal sine displacement applied to every row
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WaveTrace {
+    /// Largest displacement in pixels
+    pub amplitude: f32,
+    /// Angular frequency per row
+    pub frequency: f32,
+}
+
+/// An image effect with the parameters it was applied with
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EffectTrace {
+    /// [`Effect::Ripple`](crate::Effect::Ripple) with its random phase
+    Ripple {
+        /// Largest displacement in pixels
+        amplitude: f32,
+        /// Length of one wave period in pixels
+        wavelength: f32,
+        /// Phase of the horizontal and vertical waves
+        phase: (f32, f32),
+    },
+    /// A [`CustomEffect`](crate::CustomEffect) and the seed it was given
+    Custom {
+        /// Seed passed to the effect's function
+        seed: u64,
+    },
+}
+
+impl Captcha {
+    /// The random parameters this CAPTCHA was rendered with
+    pub fn trace(&self) -> RenderTrace {
+        let scene = &self.scene;
+        RenderTrace {
+            width: scene.width,
+            height: scene.height,
+            font_size: scene.scale.y,
+            background_seed: scene.background_seed,
+            glyphs: scene
+                .glyphs
+                .iter()
+                .map(|g| GlyphTrace {
+                    ch: g.ch,
+                    font: g.font,
+                    x: g.x_offset,
+                    y: g.y_offset,
+                    rotation: g.rotation,
+                    shear: g.shear,
+                    outline: g.outline,
+                    color: g.color,
+                })
+                .collect(),
+            lines: scene
+                .lines
+                .iter()
+                .map(|l| LineTrace {
+                    start_y: l.start_y,
+                    amplitude: l.amplitude,
+                    frequency: l.frequency,
+                    thickness: l.thickness,
+                    color: l.color,
+                })
+                .collect(),
+            dots: scene
+                .dots
+                .iter()
+                .map(|d| DotTrace {
+                    x: d.x,
+                    y: d.y,
+                    color: d.color,
+                    cluster: d.cluster.clone(),
+                })
+                .collect(),
+            wave: WaveTrace {
+                amplitude: scene.wave.amplitude,
+                frequency: scene.wave.frequency,
+            },
+            effects: scene
+                .image_effects
+                .iter()
+                .map(|effect| match effect {
+                    ImageEffect::Ripple {
+                        amplitude,
+                        wavelength,
+                        phase,
+                    } => EffectTrace::Ripple {
+                        amplitude: *amplitude,
+                        wavelength: *wavelength,
+                        phase: *phase,
+                    },
+                    ImageEffect::Custom(_, seed) => EffectTrace::Custom { seed: *seed },
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CaptchaConfig, Effect};
+
+    use super::*;
+
+    #[test]
+    fn test_trace_matches_config() {
+        let config = CaptchaConfig {
+            seed: Some(5),
+            interference_lines: (3, 4),
+            noise_dots: 20,
+            effects: vec![Effect::Ripple {
+                amplitude: 2.0,
+                wavelength: 30.0,
+            }],
+            ..Default::default()
+        };
+        let captcha = Captcha::with_config(config.clone());
+        let trace = captcha.trace();
+        assert_eq!((trace.width, trace.height), (280, 100));
+        assert_eq!(trace.font_size, config.font_size);
+        let code: String = trace.glyphs.iter().map(|g| g.ch).collect();
+        assert_eq!(code, captcha.code);
+        assert_eq!(trace.lines.len(), 3);
+        assert_eq!(trace.dots.len(), 20);
+        assert!(matches!(trace.effects[..], [EffectTrace::Ripple { .. }]));
+        assert!(trace.glyphs.iter().all(|g| g.rotation.abs() <= 0.26));
+
+        assert_eq!(trace, Captcha::with_config(config).trace());
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use crate::{warmup, Adjustment, Captcha, CaptchaConfig, CaptchaError};
+
+/// Renders timed per measurement; the median is used
+const SAMPLES: u64 = 5;
+
+impl CaptchaConfig {
+    /// Reduce the most expensive settings until one CAPTCHA renders and encodes within `budget`
+    ///
+    /// Measures this machine by generating and PNG-encoding a few CAPTCHAs,
+    /// then steps down until the median time fits: noise dots are halved,
+    /// ripple effects and extra interference lines dropped, noise dots halved
+    /// again, and finally the canvas and font are shrunk in steps of 15%, to
+    /// about half the size at most. Returns what was changed, in field order;
+    /// if even the smallest setting misses the budget, it is kept anyway.
+    /// Call it once at startup, since measuring renders a few dozen images.
+    pub fn tune_for_latency(&mut self, budget: Duration) -> Result<Vec<Adjustment>, CaptchaError> {
+        warmup(self)?;
+        let original = self.clone();
+        let mut step = 0;
+        while measure(self)? > budget && self.step_down(&original, step) {
+            step += 1;
+        }
+        Ok(changes(&original, self))
+    }
+}
+
+/// Median time to generate and PNG-encode a CAPTCHA from `config`
+fn measure(config: &CaptchaConfig) -> Result<Duration, CaptchaError> {
+    let mut times = Vec::with_capacity(SAMPLES as usize);
+    for seed in 0..SAMPLES {
+        let start = Instant::now();
+        Captcha::try_with_config(CaptchaConfig {
+            seed: Some(seed),
+            ..config.clone()
+        })?
+        .to_png_bytes()?;
+        times.push(start.elapsed());
+    }
+    times.sort_unstable();
+    Ok(times[times.len() / 2])
+}
+
+/// The fields tuning may change that differ between `from` and `to`
+fn changes(from: &CaptchaConfig, to: &CaptchaConfig) -> Vec<Adjustment> {
+    fn change<T: PartialEq + Debug>(
+        adjustments: &mut Vec<Adjustment>,
+        field: &str,
+        from: &T,
+        to: &T,
+    ) {
+        if from != to {
+            adjustments.push(Adjustment {
+                field: field.to_string(),
+                from: format!("{:?}", from),
+                to: format!("{:?}", to),
+            });
+        }
+    }
+
+    let mut adjustments = Vec::new();
+    change(&mut adjustments, "width", &from.width, &to.width);
+    change(&mut adjustments, "height", &from.height, &to.height);
+    change(
+        &mut adjustments,
+        "font_size",
+        &from.font_size,
+        &to.font_size,
+    );
+    change(
+        &mut adjustments,
+        "interference_lines",
+        &from.interference_lines,
+        &to.interference_lines,
+    );
+    change(
+        &mut adjustments,
+        "noise_dots",
+        &from.noise_dots,
+        &to.noise_dots,
+    );
+    // Effects are not `PartialEq`, so compare their printed parameters
+    change(
+        &mut adjustments,
+        "effects",
+        &format!("{:?}", from.effects),
+        &format!("{:?}", to.effects),
+    );
+    adjustments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Difficulty;
+
+    #[test]
+    fn test_generous_budget_changes_nothing() {
+        let mut config = CaptchaConfig::default();
+        let adjustments = config.tune_for_latency(Duration::from_secs(60)).unwrap();
+        assert!(adjustments.is_empty());
+        assert_eq!(config.noise_dots, CaptchaConfig::default().noise_dots);
+    }
+
+    #[test]
+    fn test_impossible_budget_steps_all_the_way_down() {
+        let mut config = CaptchaConfig::with_difficulty(Difficulty::Extreme);
+        let adjustments = config.tune_for_latency(Duration::ZERO).unwrap();
+
+        let fields: Vec<_> = adjustments.iter().map(|a| a.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "width",
+                "height",
+                "font_size",
+                "interference_lines",
+                "noise_dots",
+                "effects"
+            ]
+        );
+        assert_eq!(config.noise_dots, 80);
+        assert_eq!((config.width, config.height), (146, 52));
+        assert!(!format!("{:?}", config.effects).contains("Ripple"));
+        assert!(Captcha::try_with_config(config).is_ok());
+    }
+}
@@ -0,0 +1,617 @@
+//! Stateless verification tokens
+//!
+//! A token carries an HMAC of the normalized code and an expiry timestamp,
+//! so a backend can check an answer without storing anything:
+//!
+//! ```
+//! use captcha_generator::verify::TokenSigner;
+//! use captcha_generator::Captcha;
+//!
+//! let signer = TokenSigner::new(b"server secret key".to_vec());
+//! let captcha = Captcha::new();
+//! let token = signer.mint(&captcha.code);
+//!
+//! // ...send the image and token to the client, then later:
+//! assert!(signer.verify_token(&token, &captcha.code.to_lowercase()).is_ok());
+//! ```
+//!
+//! On its own a token can be answered again and again until it expires. Pass
+//! a [`TokenLedger`] to [`TokenSigner::verify_token_once`] to accept each
+//! token once and only a few wrong answers to it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::clock::{Clock, SystemClock};
+use crate::hash::{AnswerHasher, HmacSha256Hasher};
+use crate::i18n::{Locale, MessageCatalog, MessageKey};
+use crate::wire::{self, Fields, Kind};
+
+const EXPIRY_LEN: usize = 8;
+const NONCE_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+/// Length of the token fields after the header
+const TOKEN_LEN: usize = EXPIRY_LEN + NONCE_LEN + MAC_LEN;
+/// Seconds between sweeps of a [`TokenLedger`] for expired entries
+const SWEEP_INTERVAL: u64 = 10;
+/// Salt prefix of the keystream that encrypts challenge seeds, unlike any expiry
+#[cfg(feature = "challenge")]
+const SEED_CONTEXT: &[u8] = b"captcha-generator seed";
+
+/// Reasons a token/answer pair is rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The token could not be decoded
+    Malformed,
+    /// The token's expiry time has passed
+    Expired,
+    /// The answer does not match the code the token was minted for
+    Mismatch,
+    /// The token was already answered correctly
+    Used,
+    /// The token got too many wrong answers and is no longer accepted
+    TooManyAttempts,
+}
+
+impl VerifyError {
+    /// The catalog key for this error's user-facing message
+    pub fn message_key(&self) -> MessageKey {
+        match self {
+            VerifyError::Malformed => MessageKey::TokenMalformed,
+            VerifyError::Expired => MessageKey::CodeExpired,
+            VerifyError::Mismatch => MessageKey::CodeMismatch,
+            VerifyError::Used => MessageKey::CodeUsed,
+            VerifyError::TooManyAttempts => MessageKey::TooManyAttempts,
+        }
+    }
+
+    /// The user-facing message for this error
+    pub fn localized<'c>(&self, catalog: &'c dyn MessageCatalog) -> &'c str {
+        catalog.message(self.message_key())
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.localized(&Locale::En))
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Mints and verifies HMAC-signed, expiring answer tokens
+#[derive(Clone)]
+pub struct TokenSigner {
+    hasher: Arc<dyn AnswerHasher>,
+    clock: Arc<dyn Clock>,
+    ttl: Duration,
+    case_sensitive: bool,
+}
+
+impl TokenSigner {
+    /// Create a signer with a secret key, a 5 minute TTL and case-insensitive answers
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self::with_hasher(HmacSha256Hasher::new(key))
+    }
+
+    /// Create a signer that authenticates codes with `hasher` instead of HMAC-SHA256
+    ///
+    /// Signers sharing tokens must use the same hasher and key.
+    pub fn with_hasher(hasher: impl AnswerHasher + 'static) -> Self {
+        Self {
+            hasher: Arc::new(hasher),
+            clock: Arc::new(SystemClock),
+            ttl: Duration::from_secs(300),
+            case_sensitive: false,
+        }
+    }
+
+    /// Set how long minted tokens stay valid
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Read the time for expiry from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Require answers to match the code's case exactly
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// How long minted tokens stay valid
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Mint an opaque, URL-safe token for `code`
+    ///
+    /// The token is a version 1 [`wire`] token in unpadded URL-safe base64.
+    pub fn mint(&self, code: &str) -> String {
+        let expiry = self
+            .unix_now()
+            .saturating_add(self.ttl.as_secs())
+            .to_be_bytes();
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut token = Vec::with_capacity(wire::HEADER_LEN + TOKEN_LEN);
+        token.extend_from_slice(&wire::header(Kind::Token));
+        token.extend_from_slice(&expiry);
+        token.extend_from_slice(&nonce);
+        token.extend_from_slice(&self.tag(&expiry, &nonce, code));
+        URL_SAFE_NO_PAD.encode(token)
+    }
+
+    /// Check `input` against a token minted by this signer
+    ///
+    /// The comparison runs in constant time.
+    pub fn verify_token(&self, token: &str, input: &str) -> Result<(), VerifyError> {
+        let token = Token::decode(token)?;
+        if self.unix_now() >= token.expires_at() {
+            return Err(VerifyError::Expired);
+        }
+        self.check_token(&token, input)
+    }
+
+    /// Like [`TokenSigner::verify_token`], but accept each token only once
+    ///
+    /// `ledger` remembers tokens until they expire: a token that was answered
+    /// correctly is [`VerifyError::Used`] afterwards, and once it has had the
+    /// ledger's maximum of wrong answers, it is [`VerifyError::TooManyAttempts`].
+    pub fn verify_token_once(
+        &self,
+        token: &str,
+        input: &str,
+        ledger: &TokenLedger,
+    ) -> Result<(), VerifyError> {
+        let token = Token::decode(token)?;
+        let now = self.unix_now();
+        if now >= token.expires_at() {
+            return Err(VerifyError::Expired);
+        }
+
+        // Forged tokens can claim any expiry, but are never kept longer than real ones
+        let expires_at = token
+            .expires_at()
+            .min(now.saturating_add(self.ttl.as_secs()));
+        ledger.check(token.nonce, expires_at, now, || {
+            self.check_token(&token, input)
+        })
+    }
+
+    fn check_token(&self, token: &Token, input: &str) -> Result<(), VerifyError> {
+        if self.check(&token.expiry, &token.nonce, input, &token.tag) {
+            Ok(())
+        } else {
+            Err(VerifyError::Mismatch)
+        }
+    }
+
+    /// Seconds since the Unix epoch according to the signer's clock
+    pub(crate) fn unix_now(&self) -> u64 {
+        self.clock.now().as_secs()
+    }
+
+    /// The keyed hash of the expiry, nonce and normalized code
+    pub(crate) fn tag(&self, expiry: &[u8], nonce: &[u8], code: &str) -> [u8; MAC_LEN] {
+        self.hasher.hash(
+            &[expiry, nonce].concat(),
+            &normalize(code, self.case_sensitive),
+        )
+    }
+
+    /// The keystream that encrypts the seed of the challenge with `nonce`
+    ///
+    /// XOR-ing it in both encrypts and decrypts. Nonces are random per
+    /// challenge, so no two seeds share a keystream.
+    #[cfg(feature = "challenge")]
+    pub(crate) fn seed_pad(&self, nonce: &[u8]) -> [u8; 8] {
+        let block = self.hasher.hash(&[SEED_CONTEXT, nonce].concat(), "");
+        block[..8].try_into().expect("hash is 32 bytes")
+    }
+
+    /// Whether `input` matches `tag`, compared in constant time
+    pub(crate) fn check(
+        &self,
+        expiry: &[u8],
+        nonce: &[u8],
+        input: &str,
+        tag: &[u8; MAC_LEN],
+    ) -> bool {
+        self.hasher.verify(
+            &[expiry, nonce].concat(),
+            &normalize(input, self.case_sensitive),
+            tag,
+        )
+    }
+}
+
+impl fmt::Debug for TokenSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSigner")
+            .field("ttl", &self.ttl)
+            .field("case_sensitive", &self.case_sensitive)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The fields of a decoded token
+struct Token {
+    expiry: [u8; EXPIRY_LEN],
+    nonce: [u8; NONCE_LEN],
+    tag: [u8; MAC_LEN],
+}
+
+impl Token {
+    fn decode(token: &str) -> Result<Self, VerifyError> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token.trim())
+            .map_err(|_| VerifyError::Malformed)?;
+        let mut fields = Fields(wire::body(&bytes, Kind::Token, TOKEN_LEN)?);
+        Ok(Self {
+            expiry: fields.array(),
+            nonce: fields.array(),
+            tag: fields.array(),
+        })
+    }
+
+    fn expires_at(&self) -> u64 {
+        u64::from_be_bytes(self.expiry)
+    }
+}
+
+struct LedgerEntry {
+    /// Unix time in seconds after which the entry is dropped
+    expires_at: u64,
+    failures: u32,
+    used: bool,
+}
+
+impl LedgerEntry {
+    /// Record the result of checking an answer, turning the last allowed
+    /// mismatch into [`VerifyError::TooManyAttempts`]
+    fn record(
+        &mut self,
+        result: Result<(), VerifyError>,
+        max_attempts: u32,
+    ) -> Result<(), VerifyError> {
+        match result {
+            Ok(()) => {
+                self.used = true;
+                Ok(())
+            }
+            Err(_) => {
+                self.failures += 1;
+                if self.failures >= max_attempts {
+                    Err(VerifyError::TooManyAttempts)
+                } else {
+                    Err(VerifyError::Mismatch)
+                }
+            }
+        }
+    }
+}
+
+struct LedgerState {
+    entries: HashMap<[u8; NONCE_LEN], LedgerEntry>,
+    /// Unix time in seconds of the next sweep for expired entries
+    next_sweep: u64,
+}
+
+/// Tokens that were answered, remembered until they expire
+///
+/// Shared by every request, e.g. in an `Arc`, it makes tokens checked with
+/// [`TokenSigner::verify_token_once`] single-use and stops them from being
+/// brute-forced online. Expired entries are swept every few seconds.
+///
+/// Wrong answers to forged tokens are recorded too, so the ledger holds at
+/// most 100,000 tokens by default. Once full, answers to tokens it does not
+/// know yet are refused with [`VerifyError::TooManyAttempts`] until entries
+/// expire; size it for the tokens answered within one TTL.
+pub struct TokenLedger {
+    state: Mutex<LedgerState>,
+    max_attempts: u32,
+    max_entries: usize,
+}
+
+impl TokenLedger {
+    /// Create a ledger accepting 3 answers per token and remembering up to 100,000 tokens
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(LedgerState {
+                entries: HashMap::new(),
+                next_sweep: 0,
+            }),
+            max_attempts: 3,
+            max_entries: 100_000,
+        }
+    }
+
+    /// Set how many answers are accepted per token (at least one)
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set how many tokens the ledger remembers at most
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Number of tokens remembered, including expired ones not yet dropped
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Whether no tokens are remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run `check` for the entry of `nonce` unless it was used or locked,
+    /// and record its result until `expires_at`
+    pub(crate) fn check(
+        &self,
+        nonce: [u8; NONCE_LEN],
+        expires_at: u64,
+        now: u64,
+        check: impl FnOnce() -> Result<(), VerifyError>,
+    ) -> Result<(), VerifyError> {
+        let mut state = self.state.lock().unwrap();
+        if now >= state.next_sweep {
+            state.entries.retain(|_, entry| entry.expires_at > now);
+            state.next_sweep = now.saturating_add(SWEEP_INTERVAL);
+        }
+        let entries = &mut state.entries;
+
+        // Checked under the lock so that concurrent replays cannot both succeed
+        match entries
+            .get_mut(&nonce)
+            .filter(|entry| entry.expires_at > now)
+        {
+            Some(entry) => {
+                if entry.used {
+                    return Err(VerifyError::Used);
+                }
+                if entry.failures >= self.max_attempts {
+                    return Err(VerifyError::TooManyAttempts);
+                }
+                entry.record(check(), self.max_attempts)
+            }
+            None => {
+                if entries.len() >= self.max_entries && !entries.contains_key(&nonce) {
+                    return Err(VerifyError::TooManyAttempts);
+                }
+                let mut entry = LedgerEntry {
+                    expires_at,
+                    failures: 0,
+                    used: false,
+                };
+                let result = entry.record(check(), self.max_attempts);
+                entries.insert(nonce, entry);
+                result
+            }
+        }
+    }
+}
+
+impl Default for TokenLedger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TokenLedger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenLedger")
+            .field("len", &self.len())
+            .field("max_attempts", &self.max_attempts)
+            .field("max_entries", &self.max_entries)
+            .finish()
+    }
+}
+
+/// The form an answer is compared in: trimmed, and uppercased unless `case_sensitive`
+pub(crate) fn normalize(code: &str, case_sensitive: bool) -> String {
+    let code = code.trim();
+    if case_sensitive {
+        code.to_string()
+    } else {
+        code.to_uppercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> TokenSigner {
+        TokenSigner::new(b"test key".to_vec())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let token = signer().mint("AB23CD");
+        assert_eq!(signer().verify_token(&token, "AB23CD"), Ok(()));
+        assert_eq!(signer().verify_token(&token, " ab23cd "), Ok(()));
+        assert_eq!(
+            signer().verify_token(&token, "AB23CE"),
+            Err(VerifyError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn test_case_sensitive() {
+        let signer = signer().case_sensitive(true);
+        let token = signer.mint("AbC");
+        assert_eq!(signer.verify_token(&token, "AbC"), Ok(()));
+        assert_eq!(
+            signer.verify_token(&token, "ABC"),
+            Err(VerifyError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn test_rejects_other_keys_and_garbage() {
+        let token = signer().mint("AB23CD");
+        let other = TokenSigner::new(b"other key".to_vec());
+        assert_eq!(
+            other.verify_token(&token, "AB23CD"),
+            Err(VerifyError::Mismatch)
+        );
+        assert_eq!(
+            signer().verify_token("not a token", "AB23CD"),
+            Err(VerifyError::Malformed)
+        );
+        assert_eq!(
+            signer().verify_token(&token[..20], "AB23CD"),
+            Err(VerifyError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_expired() {
+        let signer = signer().with_ttl(Duration::ZERO);
+        let token = signer.mint("AB23CD");
+        assert_eq!(
+            signer.verify_token(&token, "AB23CD"),
+            Err(VerifyError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let token = signer().mint("AB23CD");
+        let bytes = URL_SAFE_NO_PAD.decode(&token).unwrap();
+        assert_eq!(bytes.len(), wire::HEADER_LEN + TOKEN_LEN);
+        assert_eq!(bytes[..3], [wire::MAGIC, Kind::Token as u8, wire::VERSION]);
+
+        // Fields appended by a later version are ignored
+        let mut newer = bytes.clone();
+        newer[2] = wire::VERSION + 1;
+        newer.extend_from_slice(b"extension");
+        let newer = URL_SAFE_NO_PAD.encode(newer);
+        assert_eq!(signer().verify_token(&newer, "AB23CD"), Ok(()));
+
+        // The fields alone, without the header
+        let bare = URL_SAFE_NO_PAD.encode(&bytes[wire::HEADER_LEN..]);
+        assert_eq!(
+            signer().verify_token(&bare, "AB23CD"),
+            Err(VerifyError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_tokens_are_single_use() {
+        let ledger = TokenLedger::new();
+        let token = signer().mint("AB23CD");
+        assert_eq!(
+            signer().verify_token_once(&token, "AB23CD", &ledger),
+            Ok(())
+        );
+        assert_eq!(
+            signer().verify_token_once(&token, "AB23CD", &ledger),
+            Err(VerifyError::Used)
+        );
+        // Other tokens are unaffected
+        let other = signer().mint("AB23CD");
+        assert_eq!(
+            signer().verify_token_once(&other, "AB23CD", &ledger),
+            Ok(())
+        );
+        assert_eq!(ledger.len(), 2);
+    }
+
+    #[test]
+    fn test_attempts_are_limited() {
+        let ledger = TokenLedger::new().with_max_attempts(2);
+        let token = signer().mint("AB23CD");
+        assert_eq!(
+            signer().verify_token_once(&token, "XXXXXX", &ledger),
+            Err(VerifyError::Mismatch)
+        );
+        assert_eq!(
+            signer().verify_token_once(&token, "YYYYYY", &ledger),
+            Err(VerifyError::TooManyAttempts)
+        );
+        assert_eq!(
+            signer().verify_token_once(&token, "AB23CD", &ledger),
+            Err(VerifyError::TooManyAttempts)
+        );
+    }
+
+    #[test]
+    fn test_ledger_forgets_expired_tokens() {
+        let clock = Arc::new(crate::clock::ManualClock::new(Duration::from_secs(1000)));
+        let signer = signer()
+            .with_ttl(Duration::from_secs(60))
+            .with_clock(clock.clone());
+        let ledger = TokenLedger::new();
+        let token = signer.mint("AB23CD");
+        assert_eq!(signer.verify_token_once(&token, "AB23CD", &ledger), Ok(()));
+        assert_eq!(ledger.len(), 1);
+
+        clock.advance(Duration::from_secs(61));
+        let fresh = signer.mint("AB23CD");
+        assert_eq!(
+            signer.verify_token_once(&token, "AB23CD", &ledger),
+            Err(VerifyError::Expired)
+        );
+        assert_eq!(signer.verify_token_once(&fresh, "AB23CD", &ledger), Ok(()));
+        assert_eq!(ledger.len(), 1);
+    }
+
+    #[test]
+    fn test_ledger_is_bounded() {
+        let clock = Arc::new(crate::clock::ManualClock::new(Duration::from_secs(1000)));
+        let signer = signer()
+            .with_ttl(Duration::from_secs(60))
+            .with_clock(clock.clone());
+        let forger = TokenSigner::new(b"forged key".to_vec()).with_clock(clock.clone());
+        let ledger = TokenLedger::new().with_max_entries(2);
+
+        let forged = [forger.mint("AB23CD"), forger.mint("AB23CD")];
+        for token in &forged {
+            assert_eq!(
+                signer.verify_token_once(token, "AB23CD", &ledger),
+                Err(VerifyError::Mismatch)
+            );
+        }
+        let token = signer.mint("AB23CD");
+        assert_eq!(
+            signer.verify_token_once(&token, "AB23CD", &ledger),
+            Err(VerifyError::TooManyAttempts)
+        );
+        assert_eq!(ledger.len(), 2);
+        // Tokens already remembered are still counted
+        assert_eq!(
+            signer.verify_token_once(&forged[0], "AB23CD", &ledger),
+            Err(VerifyError::Mismatch)
+        );
+
+        // Forged entries expire with the signer's TTL, not the forged expiry
+        clock.advance(Duration::from_secs(61));
+        let token = signer.mint("AB23CD");
+        assert_eq!(signer.verify_token_once(&token, "AB23CD", &ledger), Ok(()));
+        assert_eq!(ledger.len(), 1);
+    }
+
+    #[test]
+    fn test_tokens_are_unique() {
+        assert_ne!(signer().mint("AB23CD"), signer().mint("AB23CD"));
+    }
+}
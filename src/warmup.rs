@@ -0,0 +1,43 @@
+use rusttype::Scale;
+
+use crate::{font, Captcha, CaptchaConfig, CaptchaError};
+
+/// Pay the cold-start cost of generating CAPTCHAs from `config` up front
+///
+/// Parses and caches the configured fonts, lays out every glyph of the
+/// charset once and renders and encodes a throwaway CAPTCHA, so the first
+/// request a service answers is as fast as the rest. Call it during boot; an
+/// error means requests with this configuration would fail too.
+pub fn warmup(config: &CaptchaConfig) -> Result<(), CaptchaError> {
+    let fonts = font::load_fonts(&config.fonts)?;
+    let scale = Scale::uniform(config.font_size);
+    for font in &fonts {
+        for ch in config.charset.chars() {
+            let _ = font.glyph(ch).scaled(scale).exact_bounding_box();
+        }
+    }
+
+    let throwaway = Captcha::try_with_config(CaptchaConfig {
+        // Seeded so warming up does not draw from the system's entropy
+        seed: Some(0),
+        ..config.clone()
+    })?;
+    throwaway.to_png_bytes()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FontSource;
+
+    #[test]
+    fn test_warmup() {
+        assert!(warmup(&CaptchaConfig::default()).is_ok());
+        let broken = CaptchaConfig {
+            fonts: vec![FontSource::from_bytes(vec![0u8; 8])],
+            ..Default::default()
+        };
+        assert!(matches!(warmup(&broken), Err(CaptchaError::InvalidFont)));
+    }
+}
@@ -0,0 +1,122 @@
+//! JavaScript bindings for generating CAPTCHAs in the browser or on Workers
+//!
+//! Build for `wasm32-unknown-unknown` with the `wasm` feature and without the
+//! default `fs` feature, then run `wasm-bindgen` (or `wasm-pack`) as usual:
+//!
+//! ```js
+//! import init, { WasmCaptcha } from "./captcha_generator.js";
+//!
+//! await init();
+//! const captcha = new WasmCaptcha(280, 100, 6);
+//! img.src = captcha.toDataUri();
+//! console.log(captcha.code);
+//! ```
+//!
+//! Sizes and lengths come from page scripts, so values outside the ranges of
+//! [`CaptchaConfig::clamp`] (16 to 1024 pixels a side, at most 1024×256 in
+//! area, 1 to 16 characters) are rejected with an error instead of rendered.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Captcha, CaptchaConfig};
+
+/// A generated CAPTCHA, exposed to JavaScript as `WasmCaptcha`
+#[wasm_bindgen]
+pub struct WasmCaptcha {
+    inner: Captcha,
+}
+
+#[wasm_bindgen]
+impl WasmCaptcha {
+    /// Generate a CAPTCHA with the default configuration at the given size
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32, code_length: usize) -> Result<WasmCaptcha, JsError> {
+        Self::generate(width, height, code_length, None)
+    }
+
+    /// Generate a reproducible CAPTCHA from `seed` (a `BigInt` in JavaScript)
+    pub fn seeded(
+        width: u32,
+        height: u32,
+        code_length: usize,
+        seed: u64,
+    ) -> Result<WasmCaptcha, JsError> {
+        Self::generate(width, height, code_length, Some(seed))
+    }
+
+    fn generate(
+        width: u32,
+        height: u32,
+        code_length: usize,
+        seed: Option<u64>,
+    ) -> Result<WasmCaptcha, JsError> {
+        let config = checked_config(width, height, code_length, seed)
+            .map_err(|message| JsError::new(&message))?;
+        Ok(Self {
+            inner: Captcha::try_with_config(config)?,
+        })
+    }
+
+    /// The code shown in the image
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> String {
+        self.inner.code.clone()
+    }
+
+    /// The image as PNG bytes (a `Uint8Array` in JavaScript)
+    #[wasm_bindgen(js_name = toPngBytes)]
+    pub fn to_png_bytes(&self) -> Result<Vec<u8>, JsError> {
+        Ok(self.inner.to_png_bytes()?)
+    }
+
+    /// The image as a `data:image/png;base64,...` URI
+    #[wasm_bindgen(js_name = toDataUri)]
+    pub fn to_data_uri(&self) -> Result<String, JsError> {
+        Ok(self.inner.to_data_uri()?)
+    }
+
+    /// The image as an SVG document
+    #[wasm_bindgen(js_name = toSvg)]
+    pub fn to_svg(&self) -> String {
+        self.inner.to_svg_string()
+    }
+}
+
+/// The configuration for the given size, or what is out of range in it
+fn checked_config(
+    width: u32,
+    height: u32,
+    code_length: usize,
+    seed: Option<u64>,
+) -> Result<CaptchaConfig, String> {
+    let config = CaptchaConfig {
+        width,
+        height,
+        code_length,
+        seed,
+        ..Default::default()
+    };
+    let adjustments = config.clone().clamp();
+    if adjustments.is_empty() {
+        Ok(config)
+    } else {
+        let values: Vec<String> = adjustments.iter().map(|a| a.to_string()).collect();
+        Err(format!("out of range: {}", values.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_config() {
+        assert!(checked_config(280, 100, 6, Some(1)).is_ok());
+        assert_eq!(
+            checked_config(100_000, 100, 6, None).unwrap_err(),
+            "out of range: width: 100000 -> 1024"
+        );
+        assert!(checked_config(280, 100, 0, None).is_err());
+        assert!(checked_config(1024, 1024, 6, None).is_err());
+    }
+}
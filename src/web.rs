@@ -0,0 +1,588 @@
+//! Framework-independent pieces of the web integrations
+//!
+//! The [`axum`](crate::axum) and [`actix`](crate::actix) modules build on
+//! these: a [`CaptchaState`] that issues images and tokens, and the
+//! [`VerifiedCaptcha`] extractor that checks an answer before a handler runs.
+//!
+//! Each token is accepted once, and after three wrong answers it is refused
+//! with `429 Too Many Requests`; see [`TokenLedger`]. With the `store`
+//! feature, [`CaptchaState::with_store`] keeps codes in a
+//! [`CaptchaStore`](crate::store::CaptchaStore) instead, and the token is the
+//! store's id; [`CaptchaState::with_tenant`] then counts issued images against
+//! a tenant's quota and answers `429 Too Many Requests` once it is reached.
+//!
+//! The extractor looks for the token and answer in this order:
+//!
+//! 1. the `X-Captcha-Token` / `X-Captcha-Answer` headers,
+//! 2. `captcha_token` / `captcha_answer` fields of a JSON or
+//!    `application/x-www-form-urlencoded` body,
+//! 3. the `captcha_token` cookie set by the image route (token only).
+//!
+//! The cookie only holds the most recently loaded image, so a token sent
+//! explicitly, e.g. by a second form open in another tab, wins over it.
+//!
+//! The widget route serves a script for server-rendered forms: placed inside
+//! a form, it inserts the image, a refresh button, the answer field and a
+//! hidden token field.
+
+use std::fmt;
+use std::sync::Arc;
+
+use serde::de::IgnoredAny;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use serde::{de::DeserializeOwned, Deserialize};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::i18n::Locale;
+use crate::i18n::MessageKey;
+use crate::problem::ApiError;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::problem::Problem;
+#[cfg(feature = "store")]
+use crate::store::{CaptchaStore, StoreError, VerifyResult};
+use crate::verify::{TokenLedger, TokenSigner, VerifyError};
+use crate::{Captcha, CaptchaConfig, CaptchaError};
+
+/// Response header carrying the token of a freshly issued CAPTCHA
+pub const TOKEN_HEADER: &str = "x-captcha-token";
+/// Request header carrying the user's answer
+pub const ANSWER_HEADER: &str = "x-captcha-answer";
+/// Cookie carrying the token of a freshly issued CAPTCHA
+pub const TOKEN_COOKIE: &str = "captcha_token";
+pub use crate::form::{ANSWER_FIELD, TOKEN_FIELD};
+
+/// Shared state for the CAPTCHA routes and extractors
+#[derive(Debug, Clone)]
+pub struct CaptchaState {
+    /// Signs and verifies answer tokens
+    pub signer: TokenSigner,
+    /// Configuration for issued CAPTCHAs
+    pub config: CaptchaConfig,
+    /// Tokens already answered, shared by every clone of the state
+    pub ledger: Arc<TokenLedger>,
+    /// Where codes are kept instead of in signed tokens, if set
+    #[cfg(feature = "store")]
+    pub store: Option<Arc<CaptchaStore>>,
+    /// The tenant whose quota in `store` issued CAPTCHAs count against, if set
+    #[cfg(feature = "store")]
+    pub tenant: Option<String>,
+    /// Whether the token cookie is only sent over HTTPS (the default)
+    pub secure_cookie: bool,
+}
+
+impl CaptchaState {
+    /// Create state that issues CAPTCHAs with the default configuration
+    pub fn new(signer: TokenSigner) -> Self {
+        Self {
+            signer,
+            config: CaptchaConfig::default(),
+            ledger: Arc::new(TokenLedger::new()),
+            #[cfg(feature = "store")]
+            store: None,
+            #[cfg(feature = "store")]
+            tenant: None,
+            secure_cookie: true,
+        }
+    }
+
+    /// Use a custom configuration for issued CAPTCHAs
+    pub fn with_config(mut self, config: CaptchaConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set whether the token cookie is marked `Secure`
+    ///
+    /// Turn it off only for development servers reached over plain HTTP from
+    /// another host; browsers treat `localhost` as secure.
+    pub fn secure_cookie(mut self, secure: bool) -> Self {
+        self.secure_cookie = secure;
+        self
+    }
+
+    /// Remember answered tokens in `ledger`, e.g. to allow more wrong answers
+    pub fn with_ledger(mut self, ledger: TokenLedger) -> Self {
+        self.ledger = Arc::new(ledger);
+        self
+    }
+
+    /// Keep codes in `store` and hand out its ids as tokens
+    ///
+    /// The store's TTL and attempt limit then apply instead of the signer's
+    /// and the ledger's.
+    #[cfg(feature = "store")]
+    pub fn with_store(mut self, store: Arc<CaptchaStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Issue CAPTCHAs from the store through [`CaptchaStore::issue_for`]
+    ///
+    /// Each image counts against `tenant`'s quota, and the image route answers
+    /// `429 Too Many Requests` once it is reached. Mount one state per tenant,
+    /// e.g. under a path prefix each. Without [`CaptchaState::with_store`] the
+    /// tenant is ignored.
+    #[cfg(feature = "store")]
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Generate a CAPTCHA, returning its PNG bytes and answer token
+    pub fn issue(&self) -> Result<(Vec<u8>, String), IssueError> {
+        #[cfg(feature = "store")]
+        if let Some(store) = &self.store {
+            let (id, png) = match &self.tenant {
+                Some(tenant) => store
+                    .issue_for(tenant, self.config.clone())
+                    .map_err(IssueError::Store)?,
+                None => store.issue(self.config.clone())?,
+            };
+            return Ok((png, id));
+        }
+        let captcha = Captcha::try_with_config(self.config.clone())?;
+        let png = captcha.to_png_bytes().map_err(CaptchaError::from)?;
+        Ok((png, self.signer.mint(&captcha.code)))
+    }
+
+    /// Check `answer` against the CAPTCHA behind `token`, using it up on success
+    pub fn verify(&self, token: &str, answer: &str) -> Result<(), CaptchaRejection> {
+        #[cfg(feature = "store")]
+        if let Some(store) = &self.store {
+            return match store.verify(token, answer) {
+                VerifyResult::Correct => Ok(()),
+                VerifyResult::Incorrect { attempts_left: 0 } => {
+                    Err(CaptchaRejection::Verify(VerifyError::TooManyAttempts))
+                }
+                VerifyResult::Incorrect { .. } => {
+                    Err(CaptchaRejection::Verify(VerifyError::Mismatch))
+                }
+                VerifyResult::Expired => Err(CaptchaRejection::Verify(VerifyError::Expired)),
+                VerifyResult::NotFound => Err(CaptchaRejection::NotFound),
+            };
+        }
+        self.signer
+            .verify_token_once(token, answer, &self.ledger)
+            .map_err(CaptchaRejection::Verify)
+    }
+}
+
+/// Why [`CaptchaState::issue`] failed
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum IssueError {
+    /// The CAPTCHA could not be generated
+    Captcha(CaptchaError),
+    /// The store could not issue the CAPTCHA, e.g. because the tenant reached its quota
+    #[cfg(feature = "store")]
+    Store(StoreError),
+}
+
+impl fmt::Display for IssueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueError::Captcha(e) => write!(f, "{}", e),
+            #[cfg(feature = "store")]
+            IssueError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for IssueError {}
+
+impl From<CaptchaError> for IssueError {
+    fn from(e: CaptchaError) -> Self {
+        IssueError::Captcha(e)
+    }
+}
+
+impl ApiError for IssueError {
+    fn code(&self) -> &'static str {
+        match self {
+            IssueError::Captcha(e) => e.code(),
+            #[cfg(feature = "store")]
+            IssueError::Store(e) => e.code(),
+        }
+    }
+
+    fn status(&self) -> u16 {
+        match self {
+            IssueError::Captcha(e) => e.status(),
+            #[cfg(feature = "store")]
+            IssueError::Store(e) => e.status(),
+        }
+    }
+
+    fn message_key(&self) -> MessageKey {
+        match self {
+            IssueError::Captcha(e) => e.message_key(),
+            #[cfg(feature = "store")]
+            IssueError::Store(e) => e.message_key(),
+        }
+    }
+}
+
+/// A request whose CAPTCHA answer has been verified
+///
+/// `T` receives the rest of the JSON or form body, so handlers of form posts
+/// still get their fields. It defaults to ignoring the body.
+#[derive(Debug, Clone)]
+pub struct VerifiedCaptcha<T = IgnoredAny>(pub T);
+
+/// Why a request was rejected by [`VerifiedCaptcha`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptchaRejection {
+    /// No token was found in the headers, cookies or body
+    MissingToken,
+    /// No answer was found in the headers or body
+    MissingAnswer,
+    /// The body could not be parsed
+    InvalidBody(String),
+    /// The store holds no CAPTCHA under the token, or it was already used up
+    NotFound,
+    /// The answer did not verify
+    Verify(VerifyError),
+}
+
+impl CaptchaRejection {
+    /// HTTP status code for the rejection: 429 after too many wrong answers, otherwise 400
+    pub fn status(&self) -> u16 {
+        match self {
+            CaptchaRejection::Verify(e) => e.status(),
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for CaptchaRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptchaRejection::MissingToken => write!(f, "missing CAPTCHA token"),
+            CaptchaRejection::MissingAnswer => write!(f, "missing CAPTCHA answer"),
+            CaptchaRejection::InvalidBody(e) => write!(f, "invalid request body: {}", e),
+            CaptchaRejection::NotFound => write!(f, "unknown CAPTCHA"),
+            CaptchaRejection::Verify(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CaptchaRejection {}
+
+impl ApiError for CaptchaRejection {
+    fn code(&self) -> &'static str {
+        match self {
+            CaptchaRejection::MissingToken => "missing_token",
+            CaptchaRejection::MissingAnswer => "missing_answer",
+            CaptchaRejection::InvalidBody(_) => "invalid_body",
+            CaptchaRejection::NotFound => "challenge_not_found",
+            CaptchaRejection::Verify(e) => e.code(),
+        }
+    }
+
+    fn status(&self) -> u16 {
+        CaptchaRejection::status(self)
+    }
+
+    fn message_key(&self) -> MessageKey {
+        match self {
+            CaptchaRejection::MissingToken
+            | CaptchaRejection::MissingAnswer
+            | CaptchaRejection::InvalidBody(_) => MessageKey::InvalidRequest,
+            CaptchaRejection::NotFound => MessageKey::ChallengeNotFound,
+            CaptchaRejection::Verify(e) => e.message_key(),
+        }
+    }
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+/// The `application/problem+json` body for `error`, with English titles
+pub(crate) fn problem_json<E: ApiError + ?Sized>(error: &E) -> String {
+    serde_json::to_string(&Problem::new(error, &Locale::En))
+        .expect("problem details always serialize")
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Deserialize)]
+struct CaptchaFields {
+    captcha_token: Option<String>,
+    captcha_answer: Option<String>,
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+/// The parts of a request the extractor looks at
+pub(crate) struct RequestFields<'a> {
+    pub token_header: Option<&'a str>,
+    pub answer_header: Option<&'a str>,
+    pub cookie_header: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+impl RequestFields<'_> {
+    /// Find the token and answer, verify them and parse the body as `T`
+    pub fn verify<T: DeserializeOwned>(
+        &self,
+        state: &CaptchaState,
+    ) -> Result<VerifiedCaptcha<T>, CaptchaRejection> {
+        let (fields, rest): (CaptchaFields, T) = if self.is_json() {
+            (parse_json(self.body)?, parse_json(self.body)?)
+        } else if self.is_form() {
+            (parse_form(self.body)?, parse_form(self.body)?)
+        } else {
+            (parse_form(b"")?, parse_form(b"")?)
+        };
+
+        let token = self
+            .token_header
+            .map(str::to_string)
+            .or(fields.captcha_token)
+            .or_else(|| self.cookie_header.and_then(token_from_cookies))
+            .ok_or(CaptchaRejection::MissingToken)?;
+        let answer = self
+            .answer_header
+            .map(str::to_string)
+            .or(fields.captcha_answer)
+            .ok_or(CaptchaRejection::MissingAnswer)?;
+
+        state.verify(&token, &answer)?;
+        Ok(VerifiedCaptcha(rest))
+    }
+
+    fn is_json(&self) -> bool {
+        self.content_type
+            .is_some_and(|ct| ct.starts_with("application/json"))
+    }
+
+    fn is_form(&self) -> bool {
+        self.content_type
+            .is_some_and(|ct| ct.starts_with("application/x-www-form-urlencoded"))
+    }
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+/// `Set-Cookie` value that hands the token to the browser
+pub(crate) fn token_cookie(token: &str, secure: bool) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict{}",
+        TOKEN_COOKIE,
+        token,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+/// Media type of the widget script
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) const WIDGET_CONTENT_TYPE: &str = "text/javascript; charset=utf-8";
+
+/// The widget script, with `IMAGE_URL` standing for the image route
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const WIDGET_SCRIPT: &str = include_str!("../assets/widget.js");
+
+/// The widget script, loading images from `image_path`
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn widget_script(image_path: &str) -> String {
+    // A JSON string is also a JavaScript string literal
+    let url = serde_json::to_string(image_path).expect("strings always serialize");
+    WIDGET_SCRIPT.replace("IMAGE_URL", &url)
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn token_from_cookies(header: &str) -> Option<String> {
+    header.split(';').find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        (name == TOKEN_COOKIE).then(|| value.to_string())
+    })
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn parse_json<T: DeserializeOwned>(body: &[u8]) -> Result<T, CaptchaRejection> {
+    serde_json::from_slice(body).map_err(|e| CaptchaRejection::InvalidBody(e.to_string()))
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn parse_form<T: DeserializeOwned>(body: &[u8]) -> Result<T, CaptchaRejection> {
+    serde_urlencoded::from_bytes(body).map_err(|e| CaptchaRejection::InvalidBody(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> CaptchaState {
+        CaptchaState::new(TokenSigner::new(b"key".to_vec()))
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    fn fields(body: &[u8]) -> RequestFields<'_> {
+        RequestFields {
+            token_header: None,
+            answer_header: None,
+            cookie_header: None,
+            content_type: None,
+            body,
+        }
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    #[test]
+    fn test_headers_and_cookie() {
+        let state = state();
+        let token = state.signer.mint("AB23");
+        let cookie = format!("theme=dark; {}={}", TOKEN_COOKIE, token);
+        assert!(token_cookie(&token, true).ends_with("; Secure"));
+        assert!(!token_cookie(&token, false).contains("Secure"));
+
+        let mut request = fields(b"");
+        request.cookie_header = Some(&cookie);
+        request.answer_header = Some("XXXX");
+        assert_eq!(
+            request.verify::<IgnoredAny>(&state).unwrap_err(),
+            CaptchaRejection::Verify(VerifyError::Mismatch)
+        );
+
+        request.answer_header = Some("ab23");
+        assert!(request.verify::<IgnoredAny>(&state).is_ok());
+
+        request.cookie_header = None;
+        assert_eq!(
+            request.verify::<IgnoredAny>(&state).unwrap_err(),
+            CaptchaRejection::MissingToken
+        );
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    #[test]
+    fn test_body_token_wins_over_cookie() {
+        let state = state();
+        let stale = format!("{}={}", TOKEN_COOKIE, state.signer.mint("ZZZZ"));
+        let form = format!(
+            "{}={}&{}=AB23",
+            TOKEN_FIELD,
+            state.signer.mint("AB23"),
+            ANSWER_FIELD
+        );
+        let mut request = fields(form.as_bytes());
+        request.content_type = Some("application/x-www-form-urlencoded");
+        request.cookie_header = Some(&stale);
+        assert!(request.verify::<IgnoredAny>(&state).is_ok());
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    #[test]
+    fn test_widget_script() {
+        let script = widget_script("/captcha.png?size=\"big\"");
+        assert!(script.contains(r#"fetch("/captcha.png?size=\"big\"","#));
+        assert!(!script.contains("IMAGE_URL"));
+        // The fields the widget posts are the ones the extractor reads
+        assert!(script.contains(&format!("name=\"{}\"", TOKEN_FIELD)));
+        assert!(script.contains(&format!("name=\"{}\"", ANSWER_FIELD)));
+        assert!(script.contains(&format!("headers.get(\"{}\")", TOKEN_HEADER)));
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    #[test]
+    fn test_form_and_json_bodies() {
+        #[derive(Deserialize)]
+        struct Signup {
+            email: String,
+        }
+
+        let state = state();
+        let token = state.signer.mint("AB23");
+
+        let form = format!("email=a%40b.c&captcha_token={}&captcha_answer=AB23", token);
+        let mut request = fields(form.as_bytes());
+        request.content_type = Some("application/x-www-form-urlencoded");
+        let VerifiedCaptcha(signup) = request.verify::<Signup>(&state).unwrap();
+        assert_eq!(signup.email, "a@b.c");
+
+        // Tokens are single-use
+        let token = state.signer.mint("AB23");
+        let json = format!(
+            r#"{{"email":"a@b.c","captcha_token":"{}","captcha_answer":"AB23"}}"#,
+            token
+        );
+        let mut request = fields(json.as_bytes());
+        request.content_type = Some("application/json; charset=utf-8");
+        assert!(request.verify::<Signup>(&state).is_ok());
+
+        let mut request = fields(b"{");
+        request.content_type = Some("application/json");
+        assert!(matches!(
+            request.verify::<IgnoredAny>(&state),
+            Err(CaptchaRejection::InvalidBody(_))
+        ));
+    }
+
+    #[test]
+    fn test_replay_and_rate_limit() {
+        let state = state().with_ledger(TokenLedger::new().with_max_attempts(2));
+        let verify =
+            |token: &str, answer: &str| state.verify(token, answer).map_err(|e| e.status());
+
+        let token = state.signer.mint("AB23");
+        assert_eq!(verify(&token, "AB23"), Ok(()));
+        assert_eq!(
+            state.verify(&token, "AB23"),
+            Err(CaptchaRejection::Verify(VerifyError::Used))
+        );
+
+        // Clones share the ledger
+        let token = state.signer.mint("AB23");
+        let clone = state.clone();
+        assert_eq!(
+            clone.verify(&token, "XXXX").map_err(|e| e.status()),
+            Err(400)
+        );
+        assert_eq!(verify(&token, "YYYY"), Err(429));
+        assert_eq!(verify(&token, "AB23"), Err(429));
+    }
+
+    #[cfg(feature = "store")]
+    #[test]
+    fn test_store() {
+        let config = CaptchaConfig {
+            seed: Some(3),
+            ..Default::default()
+        };
+        let code = Captcha::with_config(config.clone()).code;
+        let state = state()
+            .with_config(config)
+            .with_store(Arc::new(CaptchaStore::new().with_max_attempts(2)));
+
+        let (_, id) = state.issue().unwrap();
+        assert_eq!(state.verify(&id, &code), Ok(()));
+        assert_eq!(state.verify(&id, &code), Err(CaptchaRejection::NotFound));
+        // Signed tokens are not accepted
+        let token = state.signer.mint(&code);
+        assert_eq!(state.verify(&token, &code), Err(CaptchaRejection::NotFound));
+
+        let (_, id) = state.issue().unwrap();
+        assert_eq!(state.verify(&id, "XXXX").map_err(|e| e.status()), Err(400));
+        assert_eq!(state.verify(&id, "YYYY").map_err(|e| e.status()), Err(429));
+        assert_eq!(state.verify(&id, &code), Err(CaptchaRejection::NotFound));
+    }
+
+    #[cfg(feature = "store")]
+    #[test]
+    fn test_tenant_quota() {
+        use crate::store::Quota;
+
+        let store = CaptchaStore::new().with_tenant_quota(
+            "acme",
+            Quota {
+                max_outstanding: Some(1),
+                max_rate: None,
+            },
+        );
+        let state = state().with_store(Arc::new(store));
+        let acme = state.clone().with_tenant("acme");
+        acme.issue().unwrap();
+        let refused = acme.issue().unwrap_err();
+        assert_eq!(refused.status(), 429);
+        assert_eq!(refused.code(), "quota_exceeded");
+        // Without a tenant no quota applies
+        state.issue().unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+//! The stable wire format of tokens and challenges
+//!
+//! Every encoded [`TokenSigner`](crate::verify::TokenSigner) token and
+//! serialized `Challenge` (with the `challenge` feature) starts with a
+//! three-byte header, followed by fixed-size fields in big-endian order:
+//!
+//! | Offset | Size | Field |
+//! |---|---|---|
+//! | 0 | 1 | [`MAGIC`], `0xCA` |
+//! | 1 | 1 | [`Kind`]: `1` for tokens, `2` for challenges |
+//! | 2 | 1 | Format version, currently [`VERSION`] = `1` |
+//!
+//! Version 1 tokens (59 bytes, sent as unpadded URL-safe base64) continue with:
+//!
+//! | Offset | Size | Field |
+//! |---|---|---|
+//! | 3 | 8 | Expiry, Unix seconds |
+//! | 11 | 16 | Random nonce |
+//! | 27 | 32 | Keyed hash of expiry, nonce and normalized code |
+//!
+//! Version 1 challenges (99 bytes) continue with:
+//!
+//! | Offset | Size | Field |
+//! |---|---|---|
+//! | 3 | 16 | Random nonce |
+//! | 19 | 32 | Keyed hash of expiry, nonce and normalized code |
+//! | 51 | 8 | Seed, encrypted under the signer's key |
+//! | 59 | 32 | Configuration hash |
+//! | 91 | 8 | Expiry, Unix seconds |
+//!
+//! Later versions only append fields, so parsers accept any version from 1
+//! on, read the fields they know and ignore the rest. Mixed fleets can then
+//! roll out a new version without rejecting each other's tokens. Version 0,
+//! unknown kinds and encodings without the header are rejected as malformed.
+
+use crate::verify::VerifyError;
+
+/// First byte of every token and challenge
+pub const MAGIC: u8 = 0xCA;
+
+/// The current format version written by this release
+pub const VERSION: u8 = 1;
+
+/// Length of the header in front of every encoding
+pub const HEADER_LEN: usize = 3;
+
+/// What an encoding holds, the second header byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Kind {
+    /// A signed answer token
+    Token = 1,
+    /// A serialized challenge
+    Challenge = 2,
+}
+
+/// The header for `kind` at the current version
+pub(crate) fn header(kind: Kind) -> [u8; HEADER_LEN] {
+    [MAGIC, kind as u8, VERSION]
+}
+
+/// The fields of an encoding of `kind` after its header
+///
+/// Fails unless the header matches and at least `min_len` bytes of fields
+/// follow; fields beyond `min_len`, added by later versions, are cut off.
+pub(crate) fn body(bytes: &[u8], kind: Kind, min_len: usize) -> Result<&[u8], VerifyError> {
+    match bytes {
+        [MAGIC, k, version, fields @ ..]
+            if *k == kind as u8 && *version >= 1 && fields.len() >= min_len =>
+        {
+            Ok(&fields[..min_len])
+        }
+        _ => Err(VerifyError::Malformed),
+    }
+}
+
+/// Reads fixed-size fields in order from a slice whose length has been checked
+pub(crate) struct Fields<'a>(pub &'a [u8]);
+
+impl Fields<'_> {
+    /// The next `N` bytes
+    pub fn array<const N: usize>(&mut self) -> [u8; N] {
+        let (field, rest) = self.0.split_at(N);
+        self.0 = rest;
+        field.try_into().expect("split at N")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body() {
+        let mut bytes = header(Kind::Token).to_vec();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(body(&bytes, Kind::Token, 3), Ok(&[1, 2, 3][..]));
+        assert_eq!(body(&bytes, Kind::Token, 2), Ok(&[1, 2][..]));
+        assert_eq!(body(&bytes, Kind::Token, 4), Err(VerifyError::Malformed));
+        assert_eq!(
+            body(&bytes, Kind::Challenge, 3),
+            Err(VerifyError::Malformed)
+        );
+
+        // A later version with an appended field
+        bytes[2] = 7;
+        bytes.push(9);
+        assert_eq!(body(&bytes, Kind::Token, 3), Ok(&[1, 2, 3][..]));
+
+        bytes[2] = 0;
+        assert_eq!(body(&bytes, Kind::Token, 3), Err(VerifyError::Malformed));
+        bytes[0] = 0;
+        assert_eq!(body(&bytes, Kind::Token, 3), Err(VerifyError::Malformed));
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut fields = Fields(&[1, 2, 3, 4, 5]);
+        assert_eq!(fields.array::<2>(), [1, 2]);
+        assert_eq!(fields.array::<3>(), [3, 4, 5]);
+    }
+}